@@ -1,14 +1,81 @@
+# Name of this language, in this language.
+# The `title-fonts` are tried in order until one has all the letters of a card title.
+# They are relative to the assets folder, and separated by commas.
+language_name = English
+    .title-fonts = fonts/FallingSky-JKwK.otf
+
 # --- Ui strings ---
+ui_main_menu_title = New game
+ui_main_menu_seed_label = Seed
+ui_main_menu_random_seed = Random seed
+ui_main_menu_start = Start
+
 ui_pause_menu_title = Paused
 ui_pause_menu_language_label = Language
 ui_pause_menu_resume = Resume
+ui_pause_menu_save = Save game
+ui_pause_menu_load = Load game
+ui_pause_menu_seed = Seed: {$seed}
+ui_pause_menu_copy_seed = Copy
+ui_pause_menu_settings = Settings
+ui_settings_start_zoom = Zoom at the start of a game
+ui_settings_length_of_day = Length of a day
+ui_settings_fullscreen = Fullscreen
+ui_settings_window_size = Window size
+ui_settings_master_volume = Volume
+ui_settings_music_volume = Music
+ui_settings_effects_volume = Sound effects
+ui_pause_menu_key_bindings = Key bindings
+ui_key_bindings_waiting = Press a key...
+ui_key_bindings_reset = Reset to defaults
+ui_action_toggle_pause = Pause
+ui_action_speed_normal = Normal speed
+ui_action_speed_double = Double speed
+ui_action_speed_triple = Triple speed
+ui_action_open_menu = Menu
+ui_action_pan_camera = Move camera
+ui_action_zoom_in = Zoom in
+ui_action_zoom_out = Zoom out
 
 ui_current_day = Day {$day}
-ui_seconds_left_in_day = {$seconds} seconds left in day
+ui_seconds_left_in_day = { $seconds ->
+    [one] {$seconds} second left in day
+   *[other] {$seconds} seconds left in day
+}
 
 ui_cannot_be_sold = Cannot be sold.
-
-ui_seconds_left_in_recipe = {$seconds} seconds left
+ui_card_value = { $coins ->
+    [one] Sells for {$coins} coin.
+   *[other] Sells for {$coins} coins.
+}
+ui_card_cost = { $coins ->
+    [one] Costs {$coins} coin.
+   *[other] Costs {$coins} coins.
+}
+ui_cards_in_stack = { $count ->
+    [one] {$count} card in this stack.
+   *[other] {$count} cards in this stack.
+}
+
+ui_seconds_left_in_recipe = { $seconds ->
+    [one] {$seconds} second left
+   *[other] {$seconds} seconds left
+}
+ui_recipe_paused = Paused, put the cards back to continue
+ui_cancel_recipe = Cancel
+
+ui_day_end_title = End of day {$day}
+ui_day_end_fed_workers = { $amount ->
+    [one] {$amount} worker was fed
+   *[other] {$amount} workers were fed
+}
+ui_day_end_starved_workers = { $amount ->
+    [one] {$amount} worker starved
+   *[other] {$amount} workers starved
+}
+ui_day_end_food_eaten = Food eaten: {$amount}
+ui_day_end_food_left = Food left: {$amount}
+ui_day_end_continue = Start the next day
 
 # --- Cards ---
 
@@ -18,10 +85,14 @@ cd_market = Sell cards here for coins.
 
 ct_buy_forest_pack = Buy Forest
 cd_buy_forest_pack = Stack coins here to buy a Forest pack.
+ct_buy_farm_pack = Buy Farm
+cd_buy_farm_pack = Stack coins here to buy a Farm pack.
 
 # Card packs
 ct_forest_pack = Forest
 cd_forest_pack = Right click to open.
+ct_farm_pack = Farm
+cd_farm_pack = Right click to open.
 
 # Nature cards
 ct_clay_patch = Clay patch
@@ -44,6 +115,9 @@ cd_plank = Might have splinters.
 ct_villager = Villager
 cd_villager = A strong worker.
 
+ct_corpse = Corpse
+cd_corpse = Should have eaten something.
+
 # Valuable cards
 ct_coin = Coin
 cd_coin = Lifeblood of the village. Literally...
@@ -59,4 +133,5 @@ rt_gather_clay = Gathering {ct_clay}
 rt_make_plank = Sawing {ct_plank}
 rt_sell_cards = Selling cards
 rt_buy_card_pack = Buying card pack
-rt_create_village = Creating {ct_villager}
\ No newline at end of file
+rt_create_villager = Creating {ct_villager}
+rt_plant_tree = Planting {ct_tree}
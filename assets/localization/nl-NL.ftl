@@ -1,14 +1,81 @@
+# Name of this language, in this language.
+# The `title-fonts` are tried in order until one has all the letters of a card title.
+# They are relative to the assets folder, and separated by commas.
+language_name = Nederlands
+    .title-fonts = fonts/FallingSky-JKwK.otf
+
 # --- Ui strings ---
+ui_main_menu_title = Nieuw spel
+ui_main_menu_seed_label = Seed
+ui_main_menu_random_seed = Willekeurige seed
+ui_main_menu_start = Begin
+
 ui_pause_menu_title = Gepauzeerd
 ui_pause_menu_language_label = Taal
 ui_pause_menu_resume = Verder
+ui_pause_menu_save = Spel opslaan
+ui_pause_menu_load = Spel laden
+ui_pause_menu_seed = Seed: {$seed}
+ui_pause_menu_copy_seed = Kopieer
+ui_pause_menu_settings = Instellingen
+ui_settings_start_zoom = Zoom aan het begin van een spel
+ui_settings_length_of_day = Lengte van een dag
+ui_settings_fullscreen = Volledig scherm
+ui_settings_window_size = Venstergrootte
+ui_settings_master_volume = Volume
+ui_settings_music_volume = Muziek
+ui_settings_effects_volume = Geluidseffecten
+ui_pause_menu_key_bindings = Toetsen
+ui_key_bindings_waiting = Druk op een toets...
+ui_key_bindings_reset = Standaard toetsen
+ui_action_toggle_pause = Pauze
+ui_action_speed_normal = Normale snelheid
+ui_action_speed_double = Dubbele snelheid
+ui_action_speed_triple = Driedubbele snelheid
+ui_action_open_menu = Menu
+ui_action_pan_camera = Camera verplaatsen
+ui_action_zoom_in = Inzoomen
+ui_action_zoom_out = Uitzoomen
 
 ui_current_day = Dag {$day}
-ui_seconds_left_in_day = De dag duurt nog {$seconds} seconden
+ui_seconds_left_in_day = { $seconds ->
+    [one] De dag duurt nog {$seconds} seconde
+   *[other] De dag duurt nog {$seconds} seconden
+}
 
 ui_cannot_be_sold = Kan niet verkocht worden.
-
-ui_seconds_left_in_recipe = Duurt nog {$seconds} seconden
+ui_card_value = { $coins ->
+    [one] Verkoopt voor {$coins} munt.
+   *[other] Verkoopt voor {$coins} munten.
+}
+ui_card_cost = { $coins ->
+    [one] Kost {$coins} munt.
+   *[other] Kost {$coins} munten.
+}
+ui_cards_in_stack = { $count ->
+    [one] {$count} kaart in deze stapel.
+   *[other] {$count} kaarten in deze stapel.
+}
+
+ui_seconds_left_in_recipe = { $seconds ->
+    [one] Duurt nog {$seconds} seconde
+   *[other] Duurt nog {$seconds} seconden
+}
+ui_recipe_paused = Gepauzeerd, leg de kaarten terug om verder te gaan
+ui_cancel_recipe = Annuleren
+
+ui_day_end_title = Einde van dag {$day}
+ui_day_end_fed_workers = { $amount ->
+    [one] {$amount} arbeider is gevoed
+   *[other] {$amount} arbeiders zijn gevoed
+}
+ui_day_end_starved_workers = { $amount ->
+    [one] {$amount} arbeider is verhongerd
+   *[other] {$amount} arbeiders zijn verhongerd
+}
+ui_day_end_food_eaten = Opgegeten voedsel: {$amount}
+ui_day_end_food_left = Overgebleven voedsel: {$amount}
+ui_day_end_continue = Begin de volgende dag
 
 # --- Cards ---
 
@@ -18,10 +85,14 @@ cd_market = Verkoop kaarten hier voor munten.
 
 ct_buy_forest_pack = Koop Bos
 cd_buy_forest_pack = Stapel hierop munten om een Bos bundel te kopen
+ct_buy_farm_pack = Koop Boerderij
+cd_buy_farm_pack = Stapel hierop munten om een Boerderij bundel te kopen
 
 # Card packs
 ct_forest_pack = Bos
 cd_forest_pack = Klik met de rechter muisknop om te openen.
+ct_farm_pack = Boerderij
+cd_farm_pack = Klik met de rechter muisknop om te openen.
 
 # Nature cards
 ct_clay_patch = Kleigrond
@@ -44,6 +115,9 @@ cd_plank = Kan nog splinters hebben.
 ct_villager = Dorpeling
 cd_villager = Een sterke arbeider.
 
+ct_corpse = Lijk
+cd_corpse = Had iets moeten eten.
+
 # Valuable cards
 ct_coin = Munt
 cd_coin = Levensbloed van het dorp. Letterlijk...
@@ -59,4 +133,5 @@ rt_gather_clay = {ct_clay} verzamelen
 rt_make_plank = {ct_plank} zagen
 rt_sell_cards = Kaarten verkopen
 rt_buy_card_pack = Koop kaarten bundel
-rt_create_village = {ct_villager} maken
\ No newline at end of file
+rt_create_villager = {ct_villager} maken
+rt_plant_tree = {ct_tree} planten
@@ -0,0 +1,69 @@
+//! Runs the game without a window, and reports how the board looks at the end of each day.
+//!
+//! Usage: `simulate [--seed <seed>] [--days <days>] [--actions <file.ron>]`
+//!
+//! The seed can be a number, or any other text, like in the game.
+//! The actions file contains a list of [ScheduledAction]s, see `simulations/` for examples.
+
+use std::process::exit;
+use the_stacks::headless::{run_simulation, DayReport, ScheduledAction};
+use the_stacks::procedural::seed_from_text;
+
+const DEFAULT_SEED: u32 = 42;
+const DEFAULT_DAYS: u32 = 5;
+
+fn main() {
+    let mut seed = DEFAULT_SEED;
+    let mut days = DEFAULT_DAYS;
+    let mut actions: Vec<ScheduledAction> = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .unwrap_or_else(|| fail(&format!("`{}` needs a value", arg)));
+        match arg.as_str() {
+            "--seed" => seed = seed_from_text(&value),
+            "--days" => {
+                days = value
+                    .parse()
+                    .unwrap_or_else(|_| fail("Days should be a number"))
+            }
+            "--actions" => {
+                let text = std::fs::read_to_string(&value)
+                    .unwrap_or_else(|e| fail(&format!("Could not read `{}`: {}", value, e)));
+                actions = ron::from_str(&text)
+                    .unwrap_or_else(|e| fail(&format!("Could not parse `{}`: {}", value, e)));
+            }
+            _ => fail(&format!("Unknown argument `{}`", arg)),
+        }
+    }
+
+    match run_simulation(seed, days, actions) {
+        Ok(reports) => print_reports(reports),
+        Err(stalled) => {
+            let message = stalled.to_string();
+            print_reports(stalled.reports);
+            eprintln!("{}", message);
+            exit(1);
+        }
+    }
+}
+
+fn print_reports(reports: Vec<DayReport>) {
+    for report in reports {
+        println!(
+            "Day {}: {} coins, {} villagers, {} starved",
+            report.day, report.coins, report.villagers, report.starved_villagers
+        );
+        for (card_type, amount) in report.card_counts {
+            println!("    {}: {}", card_type, amount);
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("Usage: simulate [--seed <seed>] [--days <days>] [--actions <file.ron>]");
+    exit(1);
+}
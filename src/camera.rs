@@ -1,16 +1,21 @@
+use crate::key_bindings::{Action, ActionInput};
+use crate::settings::Settings;
 use crate::stack::StackRelativeDragPosition;
+use crate::ui::UiClaimsMouse;
 use crate::GameState;
 use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
 use bevy::prelude::*;
 use bevy::render::camera::Camera2d;
-use crate::ui::UiClaimsMouse;
 
 /// Mouse wheels are less precise than touchpads, so we scale the zoom when using a scroll wheel.
 const MOUSE_WHEEL_ZOOM_FACTOR: f32 = 0.1;
-const MAX_ZOOMED_OUT_SCALE: f32 = 10.0;
-const MAX_ZOOMED_IN_SCALE: f32 = 1.0;
+/// Zoom per second while the zoom keys are held.
+const KEY_ZOOM_SPEED: f32 = 2.0;
+pub const MAX_ZOOMED_OUT_SCALE: f32 = 10.0;
+pub const MAX_ZOOMED_IN_SCALE: f32 = 1.0;
 
-const START_ZOOM: f32 = 2.0;
+/// Default of the zoom in the [Settings].
+pub const START_ZOOM: f32 = 2.0;
 
 pub struct OrthographicCameraPlugin;
 
@@ -25,38 +30,50 @@ impl Plugin for OrthographicCameraPlugin {
     }
 }
 
-pub fn camera_setup(mut commands: Commands) {
+pub fn camera_setup(mut commands: Commands, settings: Res<Settings>) {
     let mut camera_bundle = OrthographicCameraBundle::new_2d();
-    camera_bundle.transform.scale = Vec3::splat(START_ZOOM);
+    camera_bundle.transform.scale = Vec3::splat(settings.start_zoom);
     commands.spawn_bundle(camera_bundle);
 }
 
 pub fn camera_zoom_system(
     mut camera_query: Query<&mut Transform, With<Camera2d>>,
     mut mouse_wheel: EventReader<MouseWheel>,
+    input: ActionInput,
+    time: Res<Time>,
 ) {
-    let mut camera = camera_query.single_mut();
-    for event in mouse_wheel.iter() {
-        let scroll_amount = match event.unit {
+    let mut zoom_amount: f32 = mouse_wheel
+        .iter()
+        .map(|event| match event.unit {
             MouseScrollUnit::Line => event.y * MOUSE_WHEEL_ZOOM_FACTOR,
             MouseScrollUnit::Pixel => event.y,
-        } * camera.scale.x;
+        })
+        .sum();
+    if input.pressed(Action::ZoomIn) {
+        zoom_amount += KEY_ZOOM_SPEED * time.delta_seconds();
+    }
+    if input.pressed(Action::ZoomOut) {
+        zoom_amount -= KEY_ZOOM_SPEED * time.delta_seconds();
+    }
+    if zoom_amount == 0.0 {
+        return;
+    }
 
-        camera.scale.x -= scroll_amount;
-        camera.scale.x = camera
-            .scale
-            .x
-            .clamp(MAX_ZOOMED_IN_SCALE, MAX_ZOOMED_OUT_SCALE);
+    let mut camera = camera_query.single_mut();
+    camera.scale.x -= zoom_amount * camera.scale.x;
+    camera.scale.x = camera
+        .scale
+        .x
+        .clamp(MAX_ZOOMED_IN_SCALE, MAX_ZOOMED_OUT_SCALE);
 
-        // Zoom is always equal on x an y axis.
-        camera.scale.y = camera.scale.x;
-    }
+    // Zoom is always equal on x an y axis.
+    camera.scale.y = camera.scale.x;
 }
 
 pub fn camera_drag_system(
     mut camera_query: Query<(&mut Transform, &OrthographicProjection), With<Camera2d>>,
     windows: Res<Windows>,
-    mouse_button: Res<Input<MouseButton>>,
+    input: ActionInput,
     mut last_pos: Local<Option<Vec2>>,
     dragged_card_query: Query<&StackRelativeDragPosition>,
     ui_claims_mouse: Res<UiClaimsMouse>,
@@ -73,7 +90,7 @@ pub fn camera_drag_system(
     };
     let delta = current_pos - last_pos.unwrap_or(current_pos);
 
-    if (mouse_button.pressed(MouseButton::Left) || mouse_button.pressed(MouseButton::Right)) && !ui_claims_mouse.0 {
+    if input.pressed(Action::PanCamera) && !ui_claims_mouse.0 {
         let (mut camera_transform, projection) = camera_query.single_mut();
 
         let scaling = Vec2::new(
@@ -1,45 +1,122 @@
-use crate::card_types::{CardCategory, CardType, CLAY_PATCH, TREE};
-use crate::procedural::SeededHasherResource;
+use crate::card_types::{CardTypeId, CardTypes};
+use crate::history::{HistoryRecorder, Operation};
+use crate::procedural::{HashedRng, SeededHasherResource};
 use crate::stack::stack_utils::delete_cards;
-use crate::stack::{Card, CardStack, CreateStackEvent, HoveredCard, IsExclusiveBottomCard};
+use crate::stack::{Card, CardStack, CreateStackEvent, HoveredCard};
 use crate::UpdateStage;
 use bevy::prelude::*;
-
-const FOREST_PACK_CONTENT_OPTIONS: &[CardType] = &[TREE, CLAY_PATCH];
-
-pub(crate) const BUY_FOREST_PACK: CardType = CardType {
-    id: "buy_forest_pack",
-    value: Some(3),
-    category: CardCategory::SystemCard,
-    on_spawn: Some(|commands: &mut Commands, card: Entity| {
-        commands.entity(card).insert(IsExclusiveBottomCard);
-    }),
-};
-
-pub(crate) const FOREST_PACK: CardType = CardType {
-    id: "forest_pack",
-    value: None,
-    category: CardCategory::CardPack,
-    on_spawn: Some(|commands: &mut Commands, card: Entity| {
-        commands.entity(card).insert(CardPack { cards: 3 });
-    }),
-};
+use serde::Deserialize;
 
 pub struct CardPackPlugin;
 
 impl Plugin for CardPackPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set_to_stage(
-            UpdateStage::SystemsThatDeleteCards.as_str(),
-            SystemSet::new().with_system(card_pack_open_system),
-        );
+        app.add_event::<OpenCardPackEvent>()
+            .add_system(card_pack_mouse_open_system)
+            .add_system_set_to_stage(
+                UpdateStage::SystemsThatDeleteCards.as_str(),
+                SystemSet::new().with_system(card_pack_open_system),
+            );
     }
 }
 
+/// Event that takes a single card out of the given card pack.
+pub struct OpenCardPackEvent(pub(crate) Entity);
+
 /// Marks card packs.
 #[derive(Component)]
 pub struct CardPack {
-    cards: usize,
+    /// Amount of cards left in the pack.
+    pub(crate) cards: usize,
+}
+
+/// How rare a card is in a card pack. Used for the guaranteed slots of a [LootTable].
+#[derive(Deserialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+pub enum Rarity {
+    Common,
+    Uncommon,
+    Rare,
+}
+
+impl Default for Rarity {
+    fn default() -> Self {
+        Rarity::Common
+    }
+}
+
+/// Contents of a card pack, as written in a `.cards.ron` file.
+#[derive(Deserialize, Debug)]
+pub struct CardPackDefinition {
+    /// Amount of cards in a new pack.
+    pub cards: usize,
+    /// Minimum rarity of the first cards taken out of the pack, one entry per card.
+    #[serde(default)]
+    pub guaranteed: Vec<Rarity>,
+    pub loot: Vec<LootDefinition>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LootDefinition {
+    /// Card type id.
+    pub card: String,
+    /// Chance of this card, relative to the other cards of the pack.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default)]
+    pub rarity: Rarity,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// What can come out of a card pack of a certain [CardType](crate::card_types::CardType).
+pub struct LootTable {
+    /// Amount of cards in a new pack.
+    pub cards: usize,
+    pub guaranteed: Vec<Rarity>,
+    pub entries: Vec<LootEntry>,
+}
+
+pub struct LootEntry {
+    pub card_type: CardTypeId,
+    pub weight: u32,
+    pub rarity: Rarity,
+}
+
+impl LootTable {
+    /// Picks the card that comes out of the pack as the `card_number`th card, counting from 0.
+    /// Returns `None` when no card in the table is rare enough for a guaranteed slot.
+    pub fn draw(&self, card_number: usize, rng: &mut HashedRng) -> Option<CardTypeId> {
+        let minimum_rarity = self
+            .guaranteed
+            .get(card_number)
+            .copied()
+            .unwrap_or_default();
+        let candidates: Vec<&LootEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.rarity >= minimum_rarity && entry.weight > 0)
+            .collect();
+
+        rng.choose_weighted(&candidates, |entry| entry.weight as usize)
+            .map(|entry| entry.card_type)
+    }
+}
+
+/// Opens the hovered card pack when the player right clicks it.
+pub fn card_pack_mouse_open_system(
+    hovered_card_query: Query<Entity, (With<HoveredCard>, With<CardPack>)>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut open_events: EventWriter<OpenCardPackEvent>,
+    mut history: HistoryRecorder,
+) {
+    if mouse_input.just_pressed(MouseButton::Right) {
+        for hovered in hovered_card_query.iter() {
+            history.record(Operation::OpenPack);
+            open_events.send(OpenCardPackEvent(hovered));
+        }
+    }
 }
 
 /// This system has to go in a system stage that isn't [CoreStage::Update].
@@ -49,46 +126,97 @@ pub struct CardPack {
 pub fn card_pack_open_system(
     mut commands: Commands,
     mut card_pack_query: Query<(&Card, &mut CardPack, &GlobalTransform, &Parent)>,
-    hovered_card_query: Query<Entity, With<HoveredCard>>,
     stacks_query: Query<&CardStack>,
-    mouse_input: Res<Input<MouseButton>>,
+    mut open_events: EventReader<OpenCardPackEvent>,
     seeded_hasing: Res<SeededHasherResource>,
+    card_types: Res<CardTypes>,
     mut creation: EventWriter<CreateStackEvent>,
 ) {
-    if mouse_input.just_pressed(MouseButton::Right) {
-        for hovered in hovered_card_query.iter() {
-            if let Ok((card, mut pack, global_transform, root)) = card_pack_query.get_mut(hovered) {
-                if pack.cards > 0 {
-                    let mut rng = seeded_hasing.with(hovered);
-                    rng.with(pack.cards);
-
-                    let new_card = if card.is_type(&FOREST_PACK) {
-                        // TODO (Wybe 2022-06-05): randomize.
-                        let card = &FOREST_PACK_CONTENT_OPTIONS
-                            [rng.value_in_range(0..FOREST_PACK_CONTENT_OPTIONS.len())];
-                        Some(card)
-                    } else {
-                        None
-                    };
+    for &OpenCardPackEvent(pack_entity) in open_events.iter() {
+        if let Ok((card, mut pack, global_transform, root)) = card_pack_query.get_mut(pack_entity) {
+            if pack.cards > 0 {
+                let mut rng = seeded_hasing.with(pack_entity);
+                rng.with(pack.cards);
 
+                let maybe_new_card = card_types
+                    .get(card.type_id)
+                    .and_then(|card_type| card_type.pack.as_ref())
+                    .and_then(|loot_table| {
+                        let card_number = loot_table.cards.saturating_sub(pack.cards);
+                        loot_table.draw(card_number, &mut rng)
+                    });
+                if let Some(new_card) = maybe_new_card {
                     // Spawn one card from the card pack.
-                    if let Some(new_card) = new_card {
-                        creation.send(CreateStackEvent {
-                            position: global_transform.translation.truncate(),
-                            card_type: new_card,
-                            amount: 1,
-                        });
-                        pack.cards -= 1;
-                    }
+                    creation.send(CreateStackEvent {
+                        position: global_transform.translation.truncate(),
+                        card_type: new_card,
+                        amount: 1,
+                        feed_waiting_stack: false,
+                    });
                 }
+                pack.cards -= 1;
+            }
 
-                // Delete card pack when empty.
-                if pack.cards == 0 {
-                    if let Ok(stack) = stacks_query.get(root.0) {
-                        delete_cards(&mut commands, &[hovered], root.0, &stack.0);
-                    }
+            // Delete card pack when empty.
+            if pack.cards == 0 {
+                if let Ok(stack) = stacks_query.get(root.0) {
+                    delete_cards(&mut commands, &[pack_entity], root.0, &stack.0);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::card_packs::{LootEntry, LootTable, Rarity};
+    use crate::card_types::CardTypeId;
+    use crate::procedural::SeededHasherResource;
+
+    const COMMON: CardTypeId = CardTypeId("common");
+    const RARE: CardTypeId = CardTypeId("rare");
+
+    fn loot_table() -> LootTable {
+        LootTable {
+            cards: 3,
+            guaranteed: vec![Rarity::Rare],
+            entries: vec![
+                LootEntry {
+                    card_type: COMMON,
+                    weight: 9,
+                    rarity: Rarity::Common,
+                },
+                LootEntry {
+                    card_type: RARE,
+                    weight: 1,
+                    rarity: Rarity::Rare,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_loot_table_guaranteed_slots() {
+        let table = loot_table();
+        let hasher = SeededHasherResource::new(42);
+
+        for i in 0..100 {
+            let mut rng = hasher.with(i);
+            assert_eq!(table.draw(0, &mut rng), Some(RARE));
+        }
+    }
+
+    #[test]
+    fn test_loot_table_weights() {
+        let table = loot_table();
+        let hasher = SeededHasherResource::new(42);
+
+        let draws = 1000;
+        let rare_draws = (0..draws)
+            .filter(|&i| table.draw(1, &mut hasher.with(i)) == Some(RARE))
+            .count();
+
+        // Expected is 100.
+        assert!((50..150).contains(&rare_draws), "{}", rare_draws);
+    }
+}
@@ -1,9 +1,42 @@
+use crate::card_packs::{CardPack, CardPackDefinition, LootEntry, LootTable};
+use crate::localization::Localizer;
 use crate::recipe::RecipeUses;
+use crate::stack::stack_utils::{
+    CARD_DESCRIPTION_LOCALIZATION_PREFIX, CARD_TITLE_LOCALIZATION_PREFIX,
+};
 use crate::stack::{Card, IsExclusiveBottomCard};
+use crate::GameState;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
 use bevy::prelude::*;
-use std::hash::{Hash, Hasher};
+use bevy::reflect::TypeUuid;
+use bevy_asset_loader::AssetCollection;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Extension of files containing [CardTypeDefinitions].
+const CARD_TYPES_FILE_EXTENSION: &str = "cards.ron";
+
+pub struct CardTypesPlugin;
+
+impl Plugin for CardTypesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<CardTypeDefinitions>()
+            .init_asset_loader::<CardTypeDefinitionsLoader>()
+            .insert_resource(CardTypes::default())
+            .add_system_set(
+                SystemSet::on_exit(GameState::AssetLoading).with_system(card_types_registry_system),
+            );
+    }
+}
+
+/// To be loaded by an [AssetLoader](bevy_asset_loader::AssetLoader).
+#[derive(AssetCollection)]
+pub struct CardTypeAssets {
+    #[asset(path = "cards/base.cards.ron")]
+    pub base: Handle<CardTypeDefinitions>,
+}
 
-#[derive(Eq, PartialEq, Clone, Copy)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Deserialize)]
 pub enum CardCategory {
     /// Cards which are integral to the game system, such as the market to sell things.
     SystemCard,
@@ -42,15 +75,93 @@ impl CardCategory {
     }
 }
 
+/// Identifies a [CardType] in the [CardTypes] registry.
+/// Card types are defined in asset files, but the game logic can refer to the ones it needs
+/// through the constants in this file.
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
+pub struct CardTypeId(pub &'static str);
+
+pub(crate) const MARKET: CardTypeId = CardTypeId("market");
+pub(crate) const TREE: CardTypeId = CardTypeId("tree");
+pub(crate) const CLAY_PATCH: CardTypeId = CardTypeId("clay_patch");
+pub(crate) const CLAY: CardTypeId = CardTypeId("clay");
+pub(crate) const LOG: CardTypeId = CardTypeId("log");
+pub(crate) const PLANK: CardTypeId = CardTypeId("plank");
+pub(crate) const VILLAGER: CardTypeId = CardTypeId("villager");
+pub(crate) const COIN: CardTypeId = CardTypeId("coin");
+pub(crate) const APPLE: CardTypeId = CardTypeId("apple");
+pub(crate) const CORPSE: CardTypeId = CardTypeId("corpse");
+
+/// Card types that the game logic refers to directly. These have to be defined in the assets.
+const CARD_TYPES_USED_IN_CODE: &[CardTypeId] = &[
+    MARKET, TREE, CLAY_PATCH, CLAY, LOG, PLANK, VILLAGER, COIN, APPLE, CORPSE,
+];
+
+/// A single card type, as written in a `.cards.ron` file.
+#[derive(Deserialize, Debug)]
+pub struct CardTypeDefinition {
+    pub id: String,
+    pub category: CardCategory,
+    /// Base cost of this card when sold.
+    /// `None` means the card cannot be sold.
+    #[serde(default)]
+    pub value: Option<usize>,
+    /// Amount of [RecipeUses] a new card of this type starts with.
+    #[serde(default)]
+    pub recipe_uses: Option<u32>,
+    /// Whether cards of this type can only be at the bottom of a stack.
+    #[serde(default)]
+    pub exclusive_bottom: bool,
+    /// What is in a card pack of this type.
+    #[serde(default)]
+    pub pack: Option<CardPackDefinition>,
+    /// Id of the card pack type that can be bought by stacking coins on a card of this type.
+    /// The cost is the card's `value`.
+    #[serde(default)]
+    pub buys: Option<String>,
+    /// Path of the image drawn on the card, relative to the assets folder.
+    /// The image should be in a folder that is loaded by the [CardImages](crate::stack::CardImages) collection.
+    #[serde(default)]
+    pub foreground_image: Option<String>,
+}
+
+/// Asset containing all card types defined in a single file.
+#[derive(Deserialize, TypeUuid, Debug)]
+#[uuid = "5d1b9a52-1c2e-4f4b-9f57-7d0b7c4e2a61"]
+pub struct CardTypeDefinitions(pub Vec<CardTypeDefinition>);
+
+#[derive(Default)]
+pub struct CardTypeDefinitionsLoader;
+
+impl AssetLoader for CardTypeDefinitionsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let definitions: CardTypeDefinitions = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(definitions));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[CARD_TYPES_FILE_EXTENSION]
+    }
+}
+
 pub struct CardType {
-    pub id: &'static str,
+    pub id: CardTypeId,
     pub category: CardCategory,
     /// Base cost of this card when sold.
     /// `None` means the card cannot be sold.
     pub value: Option<usize>,
-    /// Function that is ran on spawn of a card.
-    /// Use this to add additional components.
-    pub on_spawn: Option<fn(&mut Commands, Entity)>,
+    pub recipe_uses: Option<u32>,
+    pub exclusive_bottom: bool,
+    pub pack: Option<LootTable>,
+    pub buys: Option<CardTypeId>,
+    pub foreground_image: Option<Handle<Image>>,
 }
 
 impl CardType {
@@ -59,90 +170,238 @@ impl CardType {
             type_id: self.id,
             category: self.category,
             value: self.value,
+            buys: self.buys,
         }
     }
-}
 
-impl PartialEq<Self> for CardType {
-    /// Only checks the card id, because non-equal cards should be identifiable by their id.
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+    /// Adds the components that cards of this type start out with.
+    pub fn insert_components(&self, commands: &mut Commands, card: Entity) {
+        let mut card = commands.entity(card);
+        if let Some(uses) = self.recipe_uses {
+            card.insert(RecipeUses(uses));
+        }
+        if self.exclusive_bottom {
+            card.insert(IsExclusiveBottomCard);
+        }
+        if let Some(loot_table) = &self.pack {
+            card.insert(CardPack {
+                cards: loot_table.cards,
+            });
+        }
     }
 }
 
-impl Eq for CardType {}
+/// Resource containing all the known card types.
+#[derive(Default)]
+pub struct CardTypes(HashMap<CardTypeId, CardType>);
 
-impl Hash for CardType {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write(self.id.as_ref())
+impl CardTypes {
+    pub fn get(&self, id: CardTypeId) -> Option<&CardType> {
+        self.0.get(&id)
+    }
+
+    /// Looks up a card type by the id as written in text, for example in a save game.
+    pub fn get_by_name(&self, id: &str) -> Option<&CardType> {
+        self.0.values().find(|card_type| card_type.id.0 == id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = CardTypeId> + '_ {
+        self.0.keys().copied()
+    }
+
+    /// Builds the registry from the given definitions.
+    /// Returns the problems found in the definitions. Definitions with problems are still
+    /// added where possible, so the game stays playable while designers fix them.
+    ///
+    /// Without `image_assets` and `localizer` the card types get no images, and the images and
+    /// localization are not checked. That is only useful when the game runs without a window.
+    pub fn from_definitions(
+        definitions: &[CardTypeDefinition],
+        image_assets: Option<&Assets<Image>>,
+        localizer: Option<&Localizer>,
+    ) -> (Self, Vec<String>) {
+        let mut card_types = HashMap::new();
+        let mut errors = vec![];
+        let mut used_definitions = vec![];
+
+        for definition in definitions {
+            if card_types
+                .keys()
+                .any(|id: &CardTypeId| id.0 == definition.id)
+            {
+                errors.push(format!(
+                    "Card type `{}` is defined more than once, only the first definition is used",
+                    definition.id
+                ));
+                continue;
+            }
+
+            let foreground_image = image_assets.and_then(|image_assets| {
+                definition.foreground_image.as_ref().map(|path| {
+                    let handle = image_assets.get_handle(path.as_str());
+                    if image_assets.get(&handle).is_none() {
+                        errors.push(format!(
+                            "Card type `{}` uses image `{}`, which is not loaded",
+                            definition.id, path
+                        ));
+                    }
+                    handle
+                })
+            });
+
+            if let Some(localizer) = localizer {
+                for prefix in [
+                    CARD_TITLE_LOCALIZATION_PREFIX,
+                    CARD_DESCRIPTION_LOCALIZATION_PREFIX,
+                ] {
+                    let message_id = prefix.to_owned() + &definition.id;
+                    for language in localizer.languages_missing_message(&message_id) {
+                        errors.push(format!(
+                            "Card type `{}` has no `{}` message for language `{}`",
+                            definition.id, message_id, language
+                        ));
+                    }
+                }
+            }
+
+            used_definitions.push(definition);
+
+            // Card type ids are used for the lifetime of the game, and are only loaded once.
+            // Leaking them allows cards to keep cheap `Copy` references to their type.
+            let id = CardTypeId(Box::leak(definition.id.clone().into_boxed_str()));
+            card_types.insert(
+                id,
+                CardType {
+                    id,
+                    category: definition.category,
+                    value: definition.value,
+                    recipe_uses: definition.recipe_uses,
+                    exclusive_bottom: definition.exclusive_bottom,
+                    pack: None,
+                    buys: None,
+                    foreground_image,
+                },
+            );
+        }
+
+        // Card types can refer to each other, so these are filled in once all ids are known.
+        for definition in used_definitions {
+            let (pack, buys) =
+                resolve_references(definition, definitions, &card_types, &mut errors);
+            if let Some(card_type) = card_types
+                .values_mut()
+                .find(|card_type| card_type.id.0 == definition.id)
+            {
+                card_type.pack = pack;
+                card_type.buys = buys;
+            }
+        }
+
+        for id in CARD_TYPES_USED_IN_CODE {
+            if !card_types.contains_key(id) {
+                errors.push(format!(
+                    "Card type `{}` is used by the game, but is not defined",
+                    id.0
+                ));
+            }
+        }
+
+        (CardTypes(card_types), errors)
     }
 }
 
-pub(crate) const MARKET: CardType = CardType {
-    id: "market",
-    value: None,
-    category: CardCategory::SystemCard,
-    on_spawn: Some(|commands: &mut Commands, card: Entity| {
-        commands.entity(card).insert(IsExclusiveBottomCard);
-    }),
-};
+/// Turns the card type ids written in a definition into [CardTypeId]s.
+/// Unknown card types are reported and left out.
+fn resolve_references(
+    definition: &CardTypeDefinition,
+    definitions: &[CardTypeDefinition],
+    card_types: &HashMap<CardTypeId, CardType>,
+    errors: &mut Vec<String>,
+) -> (Option<LootTable>, Option<CardTypeId>) {
+    let find_id = |id: &str| card_types.keys().find(|known| known.0 == id).copied();
 
-pub(crate) const TREE: CardType = CardType {
-    id: "tree",
-    value: Some(0),
-    category: CardCategory::Nature,
-    on_spawn: Some(|commands: &mut Commands, card: Entity| {
-        commands.entity(card).insert(RecipeUses(3));
-    }),
-};
+    let pack = definition.pack.as_ref().map(|pack| {
+        let entries: Vec<LootEntry> = pack
+            .loot
+            .iter()
+            .filter_map(|loot| match find_id(&loot.card) {
+                Some(card_type) => Some(LootEntry {
+                    card_type,
+                    weight: loot.weight,
+                    rarity: loot.rarity,
+                }),
+                None => {
+                    errors.push(format!(
+                        "Card pack `{}` contains unknown card type `{}`",
+                        definition.id, loot.card
+                    ));
+                    None
+                }
+            })
+            .collect();
 
-pub(crate) const CLAY_PATCH: CardType = CardType {
-    id: "clay_patch",
-    value: Some(0),
-    category: CardCategory::Nature,
-    on_spawn: Some(|commands: &mut Commands, card: Entity| {
-        commands.entity(card).insert(RecipeUses(3));
-    }),
-};
+        for rarity in &pack.guaranteed {
+            if !entries
+                .iter()
+                .any(|entry| entry.rarity >= *rarity && entry.weight > 0)
+            {
+                errors.push(format!(
+                    "Card pack `{}` guarantees a {:?} card, but contains none",
+                    definition.id, rarity
+                ));
+            }
+        }
+        if pack.guaranteed.len() > pack.cards {
+            errors.push(format!(
+                "Card pack `{}` has more guaranteed cards than cards",
+                definition.id
+            ));
+        }
 
-pub(crate) const CLAY: CardType = CardType {
-    id: "clay",
-    value: Some(1),
-    category: CardCategory::Resource,
-    on_spawn: None,
-};
+        LootTable {
+            cards: pack.cards,
+            guaranteed: pack.guaranteed.clone(),
+            entries,
+        }
+    });
 
-pub(crate) const LOG: CardType = CardType {
-    id: "log",
-    value: Some(1),
-    category: CardCategory::Resource,
-    on_spawn: None,
-};
+    let buys = definition.buys.as_ref().and_then(|pack_id| {
+        // Only the first definition of a card type is used.
+        let is_pack = definitions
+            .iter()
+            .find(|other| other.id == *pack_id)
+            .map_or(false, |other| other.pack.is_some());
+        match find_id(pack_id) {
+            Some(id) if is_pack => Some(id),
+            _ => {
+                errors.push(format!(
+                    "Card type `{}` buys `{}`, which is not a card pack",
+                    definition.id, pack_id
+                ));
+                None
+            }
+        }
+    });
 
-pub(crate) const PLANK: CardType = CardType {
-    id: "plank",
-    value: Some(2),
-    category: CardCategory::Resource,
-    on_spawn: None,
-};
+    (pack, buys)
+}
 
-pub(crate) const VILLAGER: CardType = CardType {
-    id: "villager",
-    value: None,
-    category: CardCategory::Worker,
-    on_spawn: None,
-};
+/// Fills the [CardTypes] registry once all the card type definitions are loaded.
+pub fn card_types_registry_system(
+    mut commands: Commands,
+    card_type_assets: Res<CardTypeAssets>,
+    definitions: Res<Assets<CardTypeDefinitions>>,
+    image_assets: Res<Assets<Image>>,
+    localizer: Res<Localizer>,
+) {
+    // Can call `unwrap()` because the asset_loader will have caught any missing assets already.
+    let base_definitions = definitions.get(&card_type_assets.base).unwrap();
 
-pub(crate) const COIN: CardType = CardType {
-    id: "coin",
-    value: None,
-    category: CardCategory::Valuable,
-    on_spawn: None,
-};
+    let (card_types, errors) =
+        CardTypes::from_definitions(&base_definitions.0, Some(&image_assets), Some(&localizer));
+    for error in errors {
+        error!("{}", error);
+    }
 
-pub(crate) const APPLE: CardType = CardType {
-    id: "apple",
-    value: Some(1),
-    category: CardCategory::Food,
-    on_spawn: None,
-};
+    commands.insert_resource(card_types);
+}
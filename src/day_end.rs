@@ -0,0 +1,158 @@
+use crate::card_types::{CardCategory, CORPSE};
+use crate::stack::stack_utils::delete_cards;
+use crate::stack::{Card, CardStack, CreateStackEvent};
+use crate::UpdateStage;
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+
+/// Amount of food cards a single worker eats at the end of the day.
+const FOOD_PER_WORKER: usize = 1;
+
+pub struct DayEndPlugin;
+
+impl Plugin for DayEndPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DayEndedEvent>().add_system_set_to_stage(
+            UpdateStage::SystemsThatDeleteCards.as_str(),
+            SystemSet::new().with_system(day_end_feeding_system),
+        );
+    }
+}
+
+/// Sent when a day is over. Time is paused until the player continues to the next day.
+pub struct DayEndedEvent {
+    /// The day that just ended.
+    pub day: u32,
+}
+
+/// Resource describing what happened at the end of the day.
+/// Exists only while the player has not yet continued to the next day.
+pub struct DayEndSummary {
+    pub day: u32,
+    pub fed_workers: usize,
+    pub starved_workers: usize,
+    pub food_eaten: usize,
+    pub food_left: usize,
+}
+
+/// Which workers get fed by which food.
+#[derive(Debug, PartialEq)]
+struct Feeding<T> {
+    /// Every fed worker, together with the food it ate.
+    fed: Vec<(T, Vec<T>)>,
+    starved: Vec<T>,
+    food_left: Vec<T>,
+}
+
+/// Workers are fed in the given order, until the food runs out.
+fn feed_workers<T: Copy>(workers: &[T], food: &[T]) -> Feeding<T> {
+    let fed_amount = workers.len().min(food.len() / FOOD_PER_WORKER);
+    let (fed_workers, starved) = workers.split_at(fed_amount);
+    let (eaten, food_left) = food.split_at(fed_amount * FOOD_PER_WORKER);
+
+    Feeding {
+        fed: fed_workers
+            .iter()
+            .copied()
+            .zip(eaten.chunks(FOOD_PER_WORKER).map(|meal| meal.to_vec()))
+            .collect(),
+        starved: starved.to_vec(),
+        food_left: food_left.to_vec(),
+    }
+}
+
+/// Lets every worker eat, and turns the ones that don't get enough food into corpses.
+pub fn day_end_feeding_system(
+    mut commands: Commands,
+    mut day_ended: EventReader<DayEndedEvent>,
+    stack_query: Query<(Entity, &CardStack, &GlobalTransform)>,
+    card_query: Query<&Card>,
+    mut creation: EventWriter<CreateStackEvent>,
+) {
+    let day = match day_ended.iter().last() {
+        Some(event) => event.day,
+        None => return,
+    };
+
+    // Sorted, so the same board always gives the same outcome.
+    let mut stacks: Vec<(Entity, &CardStack, &GlobalTransform)> = stack_query.iter().collect();
+    stacks.sort_by_key(|(root, _, _)| *root);
+
+    let mut workers = vec![];
+    let mut food = vec![];
+    for (root, stack, _) in stacks.iter() {
+        for &card_entity in stack.iter() {
+            if let Ok(card) = card_query.get(card_entity) {
+                match card.category {
+                    CardCategory::Worker => workers.push((*root, card_entity)),
+                    CardCategory::Food => food.push((*root, card_entity)),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let feeding = feed_workers(&workers, &food);
+
+    let mut cards_to_delete: BTreeMap<Entity, Vec<Entity>> = BTreeMap::new();
+    let eaten = feeding.fed.iter().flat_map(|(_, meal)| meal.iter());
+    for &(root, card) in eaten.chain(feeding.starved.iter()) {
+        cards_to_delete.entry(root).or_default().push(card);
+    }
+
+    for (root, stack, global_transform) in stacks {
+        if let Some(cards) = cards_to_delete.get(&root) {
+            delete_cards(&mut commands, cards, root, stack);
+        }
+
+        let corpses = feeding
+            .starved
+            .iter()
+            .filter(|(worker_root, _)| *worker_root == root)
+            .count();
+        if corpses > 0 {
+            creation.send(CreateStackEvent {
+                position: global_transform.translation.truncate(),
+                card_type: CORPSE,
+                amount: corpses,
+                feed_waiting_stack: false,
+            });
+        }
+    }
+
+    commands.insert_resource(DayEndSummary {
+        day,
+        fed_workers: feeding.fed.len(),
+        starved_workers: feeding.starved.len(),
+        food_eaten: feeding.fed.len() * FOOD_PER_WORKER,
+        food_left: feeding.food_left.len(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::day_end::{feed_workers, Feeding};
+
+    #[test]
+    fn test_feed_workers() {
+        let feeding = feed_workers(&["a", "b"], &["apple_1", "apple_2", "apple_3"]);
+        assert_eq!(
+            feeding,
+            Feeding {
+                fed: vec![("a", vec!["apple_1"]), ("b", vec!["apple_2"])],
+                starved: vec![],
+                food_left: vec!["apple_3"],
+            }
+        );
+
+        let feeding = feed_workers(&["a", "b", "c"], &["apple_1"]);
+        assert_eq!(
+            feeding,
+            Feeding {
+                fed: vec![("a", vec!["apple_1"])],
+                starved: vec!["b", "c"],
+                food_left: vec![],
+            }
+        );
+    }
+}
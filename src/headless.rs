@@ -0,0 +1,470 @@
+//! Runs the game logic without a window, for example to simulate a number of days while
+//! balancing recipes.
+
+use crate::card_packs::{CardPack, OpenCardPackEvent};
+use crate::card_types::{CardTypeDefinitions, CardTypeId, CardTypes, COIN, MARKET, VILLAGER};
+use crate::day_end::DayEndSummary;
+use crate::procedural::SeededHasherResource;
+use crate::recipe::{
+    add_recipe_definitions, add_recipe_scripts, read_recipe_scripts, OngoingRecipe,
+    RecipeDefinitions,
+};
+use crate::stack::stack_utils::{global_center_of_top_card, spawn_bare_stack, split_stack};
+use crate::stack::{Card, CardStack, CardVisualSize, CreateStackEvent, StackDroppedEvent};
+use crate::{FrameTime, GameLogicPlugin, GameState, LengthOfDay, TimeOfDay, TimeSpeed};
+use bevy::app::App;
+use bevy::hierarchy::HierarchyPlugin;
+use bevy::input::InputPlugin;
+use bevy::math::const_vec2;
+use bevy::prelude::*;
+use bevy::transform::TransformPlugin;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+
+/// In-game seconds per simulated frame.
+const SIMULATION_TIMESTEP: f32 = 1. / 30.;
+
+/// A simulation gives up when it takes this many times more frames than the days should take.
+/// Days take a few frames longer than their length, because time is paused at the end of a day.
+const SIMULATION_FRAME_LIMIT_FACTOR: f32 = 1.5;
+
+/// Size of the card images, which are not loaded when running headless.
+const HEADLESS_CARD_VISUAL_SIZE: Vec2 = const_vec2!([200., 275.]);
+
+const CARD_TYPES_FILE: &str = "cards/base.cards.ron";
+const RECIPES_FILE: &str = "recipes/base.recipes.ron";
+const RECIPE_SCRIPTS_FOLDER: &str = "recipes/scripts";
+
+/// Runs the game logic at a fixed timestep, without a window, sprites, ui or the asset loader.
+/// The player is replaced by a list of [ScheduledAction]s.
+/// A [DayReport] is written to the [SimulationReport] at the end of every day.
+pub struct HeadlessPlugin {
+    pub seed: u32,
+    pub actions: Vec<ScheduledAction>,
+}
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            // Not used, but the card pack plugin listens to the mouse.
+            .add_plugin(InputPlugin)
+            .add_state(GameState::Run)
+            .add_plugin(GameLogicPlugin)
+            .insert_resource(SeededHasherResource::new(self.seed))
+            .insert_resource(FrameTime {
+                delta_seconds: 0.,
+                fixed_timestep: Some(SIMULATION_TIMESTEP),
+            })
+            .insert_resource(CardVisualSize(HEADLESS_CARD_VISUAL_SIZE))
+            .insert_resource(PlannedActions {
+                actions: self.actions.iter().cloned().collect(),
+                split_off: false,
+            })
+            .insert_resource(SimulationReport::default())
+            .add_system_set(
+                SystemSet::on_update(GameState::Run)
+                    .with_system(bare_stack_creation_system)
+                    .with_system(planned_action_system),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, day_end_report_system);
+
+        let card_type_definitions: CardTypeDefinitions = read_asset_file(CARD_TYPES_FILE);
+        let (card_types, errors) =
+            CardTypes::from_definitions(&card_type_definitions.0, None, None);
+        for error in errors {
+            error!("{}", error);
+        }
+        app.insert_resource(card_types);
+
+        let recipe_definitions: RecipeDefinitions = read_asset_file(RECIPES_FILE);
+        let known_card_types: Vec<String> = card_type_definitions
+            .0
+            .into_iter()
+            .map(|definition| definition.id)
+            .collect();
+        add_recipe_definitions(&mut app.world, recipe_definitions.0, &known_card_types);
+
+        let scripts_folder = asset_path(RECIPE_SCRIPTS_FOLDER);
+        let recipe_scripts = read_recipe_scripts(&scripts_folder)
+            .unwrap_or_else(|e| panic!("Could not read `{}`: {}", scripts_folder.display(), e));
+        add_recipe_scripts(&mut app.world, recipe_scripts);
+    }
+}
+
+/// Location of a file in the assets folder.
+fn asset_path(path: &str) -> PathBuf {
+    // Same location as where bevy's asset server looks.
+    std::env::var("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .or_else(|_| {
+            std::env::current_exe().map(|exe| exe.parent().map(PathBuf::from).unwrap_or_default())
+        })
+        .unwrap_or_default()
+        .join("assets")
+        .join(path)
+}
+
+/// Reads a file from the assets folder.
+/// Panics when it can't, because the simulation means nothing without the game's data.
+fn read_asset_file<T: for<'de> Deserialize<'de>>(path: &str) -> T {
+    let path = asset_path(path);
+
+    let text = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Could not read `{}`: {}", path.display(), e));
+    ron::from_str(&text).unwrap_or_else(|e| panic!("Could not parse `{}`: {}", path.display(), e))
+}
+
+/// Something the simulated player does, once the given moment of the game has been reached.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ScheduledAction {
+    pub day: u32,
+    /// 0 to 1
+    #[serde(default)]
+    pub time_of_day: f32,
+    pub action: PlayerAction,
+}
+
+/// Cards are referred to by their card type id, such as `villager`.
+/// When there are multiple matching stacks, the oldest one is picked.
+#[derive(Deserialize, Clone, Debug)]
+pub enum PlayerAction {
+    /// Takes `amount` cards of type `cards` off the top of a stack, and drops them onto the
+    /// stack whose bottom card is of type `onto`.
+    Merge {
+        cards: String,
+        #[serde(default = "one", deserialize_with = "at_least_one")]
+        amount: usize,
+        onto: String,
+    },
+    /// Takes a single card out of a card pack of the given type.
+    OpenPack(String),
+    /// Drops `amount` cards of the given type onto the market.
+    Sell {
+        cards: String,
+        #[serde(default = "one", deserialize_with = "at_least_one")]
+        amount: usize,
+    },
+}
+
+fn one() -> usize {
+    1
+}
+
+fn at_least_one<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let amount = usize::deserialize(deserializer)?;
+    if amount == 0 {
+        Err(serde::de::Error::custom("amount should be at least 1"))
+    } else {
+        Ok(amount)
+    }
+}
+
+/// Actions that are not yet done, in order.
+struct PlannedActions {
+    actions: VecDeque<ScheduledAction>,
+    /// Whether the cards for the first action were already split off.
+    split_off: bool,
+}
+
+impl PlannedActions {
+    /// Moves on to the next action.
+    fn pop_front(&mut self) {
+        self.actions.pop_front();
+        self.split_off = false;
+    }
+}
+
+/// What the board looks like at the end of a day.
+#[derive(PartialEq, Eq, Debug)]
+pub struct DayReport {
+    pub day: u32,
+    pub coins: usize,
+    pub villagers: usize,
+    pub starved_villagers: usize,
+    /// Amount of cards on the board, per card type.
+    pub card_counts: BTreeMap<String, usize>,
+}
+
+/// Resource collecting a [DayReport] for every day that is over.
+#[derive(Default)]
+pub struct SimulationReport(pub Vec<DayReport>);
+
+/// The simulation did not get through all the days, because the game stopped making progress.
+#[derive(Debug)]
+pub struct SimulationStalled {
+    pub frames: u64,
+    /// Reports of the days that did end.
+    pub reports: Vec<DayReport>,
+}
+
+impl std::fmt::Display for SimulationStalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "simulation stalled on day {}, after {} frames",
+            self.reports.len() + 1,
+            self.frames
+        )
+    }
+}
+
+/// Sets up a simulation with the given seed and player actions, and runs it for `days` days.
+pub fn run_simulation(
+    seed: u32,
+    days: u32,
+    actions: Vec<ScheduledAction>,
+) -> Result<Vec<DayReport>, SimulationStalled> {
+    let mut app = App::new();
+    app.add_plugin(HeadlessPlugin { seed, actions });
+
+    let frames_per_day = app.world.resource::<LengthOfDay>().0 / SIMULATION_TIMESTEP;
+    let max_frames = (days as f32 * frames_per_day * SIMULATION_FRAME_LIMIT_FACTOR) as u64;
+
+    let mut frames = 0;
+    while app.world.resource::<SimulationReport>().0.len() < days as usize {
+        if frames >= max_frames {
+            return Err(SimulationStalled {
+                frames,
+                reports: app
+                    .world
+                    .remove_resource::<SimulationReport>()
+                    .unwrap_or_default()
+                    .0,
+            });
+        }
+        app.update();
+        frames += 1;
+    }
+
+    Ok(app
+        .world
+        .remove_resource::<SimulationReport>()
+        .unwrap_or_default()
+        .0)
+}
+
+/// Counterpart of the [stack_creation_system](crate::stack::stack_creation_system),
+/// which spawns stacks without visuals.
+fn bare_stack_creation_system(
+    mut commands: Commands,
+    card_types: Res<CardTypes>,
+    mut events: EventReader<CreateStackEvent>,
+) {
+    for event in events.iter() {
+        if let Some(card_type) = card_types.get(event.card_type) {
+            spawn_bare_stack(
+                &mut commands,
+                event.position,
+                &vec![card_type; event.amount],
+                true,
+            );
+        } else {
+            warn!("Cannot create unknown card type `{}`", event.card_type.0);
+        }
+    }
+}
+
+/// Does the next planned action, once it is time for it.
+/// Actions that need a stack to be split first take two frames. The first one splits the stack,
+/// and the second one drops the split off cards.
+fn planned_action_system(
+    mut commands: Commands,
+    mut actions: ResMut<PlannedActions>,
+    time_of_day: Res<TimeOfDay>,
+    stack_query: Query<(Entity, &CardStack, &GlobalTransform, Option<&OngoingRecipe>)>,
+    card_query: Query<(&Card, &GlobalTransform, Option<&CardPack>)>,
+    mut drop_events: EventWriter<StackDroppedEvent>,
+    mut open_events: EventWriter<OpenCardPackEvent>,
+) {
+    let next = match actions.actions.front() {
+        Some(next) => next.clone(),
+        None => return,
+    };
+    if (time_of_day.day, time_of_day.time_of_day) < (next.day, next.time_of_day) {
+        return;
+    }
+
+    // Oldest stacks first, so the same actions always pick the same stacks.
+    let mut stacks: Vec<_> = stack_query.iter().collect();
+    stacks.sort_by_key(|(root, _, _, _)| *root);
+
+    let is_type = |card: Entity, type_id: &str| {
+        card_query
+            .get(card)
+            .map(|(card, _, _)| card.type_id.0 == type_id)
+            .unwrap_or(false)
+    };
+
+    let (cards, amount, onto) = match &next.action {
+        PlayerAction::Merge {
+            cards,
+            amount,
+            onto,
+        } => (cards.as_str(), *amount, onto.as_str()),
+        PlayerAction::Sell { cards, amount } => (cards.as_str(), *amount, MARKET.0),
+        PlayerAction::OpenPack(pack) => {
+            let maybe_pack = stacks
+                .iter()
+                .flat_map(|(_, stack, _, _)| stack.iter().copied())
+                .find(|&card| {
+                    let has_cards_left = card_query
+                        .get(card)
+                        .map(|(_, _, maybe_pack)| maybe_pack.map_or(false, |p| p.cards > 0))
+                        .unwrap_or(false);
+                    is_type(card, pack.as_str()) && has_cards_left
+                });
+            match maybe_pack {
+                Some(pack_card) => open_events.send(OpenCardPackEvent(pack_card)),
+                None => warn!("There is no `{}` pack to open", pack),
+            }
+            actions.pop_front();
+            return;
+        }
+    };
+
+    let maybe_target = stacks
+        .iter()
+        .find(|(_, stack, _, _)| is_type(stack[0], onto))
+        .copied();
+    let (target_root, target_stack, target_transform, _) = match maybe_target {
+        Some(target) => target,
+        None => {
+            warn!("There is no stack with a `{}` at the bottom", onto);
+            actions.pop_front();
+            return;
+        }
+    };
+
+    let has_cards_on_top = |stack: &CardStack| {
+        stack.len() >= amount
+            && stack[stack.len() - amount..]
+                .iter()
+                .all(|&c| is_type(c, cards))
+    };
+    // Stacks that are busy with a recipe are left alone.
+    let sources = stacks.iter().filter(|(root, stack, _, maybe_recipe)| {
+        *root != target_root && maybe_recipe.is_none() && has_cards_on_top(*stack)
+    });
+
+    if let Some((root, _, _, _)) = sources
+        .clone()
+        .find(|(_, stack, _, _)| stack.len() == amount)
+    {
+        drop_events.send(StackDroppedEvent(
+            *root,
+            global_center_of_top_card(target_transform, target_stack.len()),
+        ));
+        actions.pop_front();
+    } else if actions.split_off {
+        // For example because the split off cards started a recipe of their own.
+        warn!(
+            "The {} `{}` cards that were split off can't be dropped",
+            amount, cards
+        );
+        actions.pop_front();
+    } else if let Some((root, stack, _, maybe_recipe)) = sources
+        .clone()
+        .find(|(_, stack, _, _)| stack.iter().all(|&c| is_type(c, cards)))
+        .or_else(|| sources.clone().next())
+    {
+        // Split off the cards. They get dropped next frame.
+        let new_bottom_card = stack[stack.len() - amount];
+        if let Ok((_, card_transform, _)) = card_query.get(new_bottom_card) {
+            split_stack(
+                &mut commands,
+                *root,
+                stack,
+                *maybe_recipe,
+                new_bottom_card,
+                card_transform,
+            );
+        }
+        actions.split_off = true;
+    } else {
+        warn!(
+            "There are no {} `{}` cards on top of a stack",
+            amount, cards
+        );
+        actions.pop_front();
+    }
+}
+
+/// Writes down how the day ended, and immediately continues with the next day.
+fn day_end_report_system(
+    mut commands: Commands,
+    maybe_summary: Option<Res<DayEndSummary>>,
+    mut speed: ResMut<TimeSpeed>,
+    mut report: ResMut<SimulationReport>,
+    card_query: Query<&Card>,
+) {
+    let summary = match maybe_summary {
+        // Wait a frame, so the corpses of starved villagers are on the board.
+        Some(summary) if !summary.is_added() => summary,
+        _ => return,
+    };
+
+    let count_of = |type_id: CardTypeId| card_query.iter().filter(|c| c.is_type(type_id)).count();
+    let mut card_counts = BTreeMap::new();
+    for card in card_query.iter() {
+        *card_counts.entry(card.type_id.0.to_string()).or_default() += 1;
+    }
+
+    report.0.push(DayReport {
+        day: summary.day,
+        coins: count_of(COIN),
+        villagers: count_of(VILLAGER),
+        starved_villagers: summary.starved_workers,
+        card_counts,
+    });
+
+    commands.remove_resource::<DayEndSummary>();
+    speed.running = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::{run_simulation, PlayerAction, ScheduledAction};
+    use std::path::Path;
+
+    fn first_days_actions() -> Vec<ScheduledAction> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("simulations/first_days.actions.ron");
+        ron::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_simulation_is_deterministic() {
+        let reports = run_simulation(42, 2, first_days_actions()).unwrap();
+
+        assert_eq!(reports.len(), 2);
+        for (report, day) in reports.iter().zip(1..) {
+            assert_eq!(report.day, day);
+            assert_eq!(report.card_counts.get("market"), Some(&1));
+            assert_eq!(
+                report.card_counts.get("villager").copied().unwrap_or(0),
+                report.villagers
+            );
+            assert_eq!(
+                report.card_counts.get("coin").copied().unwrap_or(0),
+                report.coins
+            );
+        }
+
+        assert_eq!(
+            run_simulation(42, 2, first_days_actions()).unwrap(),
+            reports
+        );
+    }
+
+    #[test]
+    fn test_actions_without_cards_are_rejected() {
+        assert!(ron::from_str::<PlayerAction>(r#"Sell(cards: "log", amount: 0)"#).is_err());
+        assert!(ron::from_str::<PlayerAction>(
+            r#"Merge(cards: "coin", amount: 0, onto: "market")"#
+        )
+        .is_err());
+        assert!(ron::from_str::<PlayerAction>(r#"Sell(cards: "log")"#).is_ok());
+    }
+}
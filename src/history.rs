@@ -0,0 +1,588 @@
+//! Lets the player undo and redo what they did to the stacks, with Ctrl+Z and Ctrl+Y.
+
+use crate::card_packs::CardPack;
+use crate::card_types::{CardTypeId, CardTypes};
+use crate::localization::Localizer;
+use crate::recipe::{OngoingRecipe, RecipeId, RecipeUses};
+use crate::stack::stack_utils::{
+    build_card, get_semi_random_stack_root_z, set_stack_card_transforms,
+};
+use crate::stack::{
+    Card, CardFonts, CardImages, CardStack, CardVisualSize, StackPhysics, StackRelativeDragPosition,
+};
+use crate::ui::UiClaimsKeyboard;
+use crate::GameState;
+use bevy::ecs::system::{SystemParam, SystemState};
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// Amount of operations that can be undone.
+const MAX_HISTORY_LENGTH: usize = 50;
+
+/// Requires the [StackPlugin](crate::stack::StackPlugin), because undoing can bring back cards
+/// that were removed.
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(History::default()).add_system_set(
+            SystemSet::on_update(GameState::Run)
+                .with_system(history_keyboard_system.exclusive_system()),
+        );
+    }
+}
+
+/// Something the player did that can be undone.
+#[derive(Clone, Copy, Debug)]
+pub enum Operation {
+    /// Picking up part of a stack.
+    Split,
+    /// Dropping a stack onto another one.
+    Merge,
+    OpenPack,
+    /// Cancelling a recipe with the button in the ui.
+    CancelRecipe(RecipeId),
+    /// Instant recipes finish right after the operation that made them valid, so they are undone
+    /// together with that operation. Undoing only the recipe would make it finish again.
+    InstantRecipe(RecipeId),
+}
+
+#[derive(Clone)]
+pub struct CardSnapshot {
+    entity: Entity,
+    type_id: CardTypeId,
+    uses: Option<u32>,
+    pack_cards: Option<usize>,
+}
+
+#[derive(Clone)]
+pub struct StackSnapshot {
+    root: Entity,
+    position: Vec2,
+    /// Bottom card first.
+    cards: Vec<CardSnapshot>,
+    recipe: Option<OngoingRecipe>,
+}
+
+/// The stacks on the board at a certain moment.
+#[derive(Clone, Default)]
+pub struct BoardSnapshot(Vec<StackSnapshot>);
+
+impl BoardSnapshot {
+    fn replace_entity(&mut self, old: Entity, new: Entity) {
+        for stack in self.0.iter_mut() {
+            if stack.root == old {
+                stack.root = new;
+            }
+            for card in stack.cards.iter_mut() {
+                if card.entity == old {
+                    card.entity = new;
+                }
+            }
+        }
+    }
+}
+
+struct HistoryEntry {
+    operations: Vec<Operation>,
+    /// The board as it was before the operations.
+    board: BoardSnapshot,
+}
+
+/// Resource with the operations that can be undone, and the ones that can be redone.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Forgets everything. For example when the board is replaced by a save game.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    fn push(&mut self, operation: Operation, board: BoardSnapshot) {
+        self.redo.clear();
+        self.undo.push(HistoryEntry {
+            operations: vec![operation],
+            board,
+        });
+        if self.undo.len() > MAX_HISTORY_LENGTH {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Adds an operation to the last entry, so they are undone together.
+    pub fn add_to_last(&mut self, operation: Operation) {
+        if let Some(last) = self.undo.last_mut() {
+            last.operations.push(operation);
+        }
+    }
+
+    /// Used when an entity could not be brought back under its old id.
+    fn replace_entity(&mut self, old: Entity, new: Entity) {
+        for entry in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            entry.board.replace_entity(old, new);
+        }
+    }
+}
+
+/// Everything needed to take a [BoardSnapshot].
+#[derive(SystemParam)]
+pub struct BoardQuery<'w, 's> {
+    stacks: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static Transform,
+            &'static CardStack,
+            Option<&'static OngoingRecipe>,
+        ),
+    >,
+    cards: Query<
+        'w,
+        's,
+        (
+            &'static Card,
+            Option<&'static RecipeUses>,
+            Option<&'static CardPack>,
+        ),
+    >,
+}
+
+impl<'w, 's> BoardQuery<'w, 's> {
+    pub fn snapshot(&self) -> BoardSnapshot {
+        let mut stacks: Vec<StackSnapshot> = self
+            .stacks
+            .iter()
+            .map(|(root, transform, stack, maybe_recipe)| StackSnapshot {
+                root,
+                position: transform.translation.truncate(),
+                cards: stack
+                    .iter()
+                    .filter_map(|&entity| {
+                        self.cards
+                            .get(entity)
+                            .ok()
+                            .map(|(card, maybe_uses, maybe_pack)| CardSnapshot {
+                                entity,
+                                type_id: card.type_id,
+                                uses: maybe_uses.map(|uses| uses.0),
+                                pack_cards: maybe_pack.map(|pack| pack.cards),
+                            })
+                    })
+                    .collect(),
+                recipe: maybe_recipe.cloned(),
+            })
+            .collect();
+        // Stacks are restored in this order, which decides which entity ids are free for the next.
+        stacks.sort_by_key(|stack| stack.root);
+
+        BoardSnapshot(stacks)
+    }
+}
+
+/// Lets systems record the operations the player does.
+/// Does nothing when there is no [HistoryPlugin], such as when running headless.
+#[derive(SystemParam)]
+pub struct HistoryRecorder<'w, 's> {
+    history: Option<ResMut<'w, History>>,
+    board: BoardQuery<'w, 's>,
+}
+
+impl<'w, 's> HistoryRecorder<'w, 's> {
+    /// Call this before the operation changes the board.
+    pub fn record(&mut self, operation: Operation) {
+        if let Some(history) = &mut self.history {
+            history.push(operation, self.board.snapshot());
+        }
+    }
+}
+
+/// Undoes or redoes an operation when the player presses Ctrl+Z or Ctrl+Y.
+/// Nothing happens while the player is dragging a stack, or typing in the ui.
+pub fn history_keyboard_system(world: &mut World) {
+    if world
+        .get_resource::<UiClaimsKeyboard>()
+        .map_or(false, |claims| claims.0)
+    {
+        return;
+    }
+    let keyboard = world.resource::<Input<KeyCode>>();
+    if !keyboard.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        return;
+    }
+    let undo = keyboard.just_pressed(KeyCode::Z);
+    let redo = keyboard.just_pressed(KeyCode::Y);
+    if !(undo || redo) {
+        return;
+    }
+
+    let is_dragging = world
+        .query_filtered::<Entity, With<StackRelativeDragPosition>>()
+        .iter(world)
+        .next()
+        .is_some();
+    if is_dragging {
+        return;
+    }
+
+    let maybe_entry = {
+        let mut history = world.resource_mut::<History>();
+        if undo {
+            history.undo.pop()
+        } else {
+            history.redo.pop()
+        }
+    };
+    let entry = match maybe_entry {
+        Some(entry) => entry,
+        None => return,
+    };
+
+    let current_board = SystemState::<BoardQuery>::new(world)
+        .get_mut(world)
+        .snapshot();
+    let replaced_entities = restore_board(world, &entry.board);
+
+    let mut history = world.resource_mut::<History>();
+    let opposite = HistoryEntry {
+        operations: entry.operations.clone(),
+        board: current_board,
+    };
+    if undo {
+        info!("Undid {:?}", entry.operations);
+        history.redo.push(opposite);
+    } else {
+        info!("Redid {:?}", entry.operations);
+        history.undo.push(opposite);
+    }
+    for (old, new) in replaced_entities {
+        history.replace_entity(old, new);
+    }
+}
+
+/// Replaces all the stacks on the board with the ones in the snapshot.
+/// Cards that are still around are moved into place, and cards that were removed are brought back
+/// under their old entity id. When that id has been taken by something else in the meantime,
+/// the card gets a new id. These replaced ids are returned as `(old, new)`.
+fn restore_board(world: &mut World, board: &BoardSnapshot) -> Vec<(Entity, Entity)> {
+    let wanted_cards: HashSet<Entity> = board
+        .0
+        .iter()
+        .flat_map(|stack| stack.cards.iter().map(|card| card.entity))
+        .collect();
+
+    // Take the board apart, but keep the cards that are still needed.
+    let mut state: SystemState<(Commands, Query<(Entity, &CardStack)>)> = SystemState::new(world);
+    {
+        let (mut commands, stacks) = state.get_mut(world);
+        for (root, stack) in stacks.iter() {
+            commands.entity(root).remove_children(stack);
+            commands.entity(root).despawn_recursive();
+            for &card in stack.iter() {
+                if !wanted_cards.contains(&card) {
+                    commands.entity(card).despawn_recursive();
+                }
+            }
+        }
+    }
+    state.apply(world);
+
+    let mut replaced_entities = vec![];
+    let mut respawn = |world: &mut World, entity: Entity| match world.get_or_spawn(entity) {
+        Some(respawned) => respawned.id(),
+        None => {
+            let new = world.spawn().id();
+            replaced_entities.push((entity, new));
+            new
+        }
+    };
+
+    let mut stacks = board.0.clone();
+    let mut rebuilt_cards = HashSet::new();
+    for stack in stacks.iter_mut() {
+        stack.root = respawn(world, stack.root);
+        for card in stack.cards.iter_mut() {
+            if world.get_entity(card.entity).is_none() {
+                card.entity = respawn(world, card.entity);
+                rebuilt_cards.insert(card.entity);
+            }
+        }
+    }
+
+    let mut state: SystemState<(
+        Commands,
+        Res<CardTypes>,
+        Res<CardImages>,
+        Res<CardFonts>,
+        Res<CardVisualSize>,
+        Res<Localizer>,
+    )> = SystemState::new(world);
+    {
+        let (mut commands, card_types, card_images, card_fonts, visual_size, localizer) =
+            state.get_mut(world);
+
+        for stack in stacks.iter() {
+            for card in stack.cards.iter() {
+                if rebuilt_cards.contains(&card.entity) {
+                    if let Some(card_type) = card_types.get(card.type_id) {
+                        build_card(
+                            &mut commands,
+                            card.entity,
+                            card_type,
+                            &card_images,
+                            &card_fonts,
+                            visual_size.0,
+                            &localizer,
+                        );
+                    }
+                }
+
+                // These go after the card type's starting components, so they overwrite them.
+                let mut card_commands = commands.entity(card.entity);
+                if let Some(uses) = card.uses {
+                    card_commands.insert(RecipeUses(uses));
+                } else {
+                    card_commands.remove::<RecipeUses>();
+                }
+                if let Some(cards) = card.pack_cards {
+                    card_commands.insert(CardPack { cards });
+                } else {
+                    card_commands.remove::<CardPack>();
+                }
+            }
+
+            let cards: Vec<Entity> = stack.cards.iter().map(|card| card.entity).collect();
+            let transform = Transform::from_translation(
+                stack
+                    .position
+                    .extend(get_semi_random_stack_root_z(stack.root)),
+            );
+            commands
+                .entity(stack.root)
+                .insert_bundle(TransformBundle::from_transform(transform))
+                .insert(CardStack(cards.clone()))
+                .insert(StackPhysics)
+                .insert_children(0, &cards);
+            if let Some(recipe) = &stack.recipe {
+                commands.entity(stack.root).insert(recipe.clone());
+            }
+            set_stack_card_transforms(&mut commands, &cards);
+        }
+    }
+    state.apply(world);
+
+    replaced_entities
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::card_packs::CardPack;
+    use crate::card_types::{CardTypeDefinitions, CardTypeId, CardTypes, COIN, TREE, VILLAGER};
+    use crate::history::{
+        history_keyboard_system, BoardQuery, BoardSnapshot, History, HistoryEntry, Operation,
+        MAX_HISTORY_LENGTH,
+    };
+    use crate::localization::Localizer;
+    use crate::recipe::{OngoingRecipe, RecipeId, RecipeInstanceId, RecipeUses};
+    use crate::stack::{Card, CardFonts, CardImages, CardStack, CardVisualSize};
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::*;
+    use std::time::Duration;
+
+    type CardSummary = (Entity, CardTypeId, Option<u32>, Option<usize>);
+    type RecipeSummary = (RecipeId, RecipeInstanceId, Duration);
+    /// Root, position, cards and recipe of a stack.
+    type StackSummary = (Entity, Vec2, Vec<CardSummary>, Option<RecipeSummary>);
+
+    fn history_world() -> World {
+        let mut world = World::default();
+
+        let card_type_definitions: CardTypeDefinitions =
+            ron::from_str(include_str!("../assets/cards/base.cards.ron")).unwrap();
+        let (card_types, _) = CardTypes::from_definitions(&card_type_definitions.0, None, None);
+        world.insert_resource(card_types);
+        world.insert_resource(CardImages {
+            background: default(),
+            border: default(),
+            hover_overlay: default(),
+            stack_drop_target: default(),
+            _card_foreground_images: vec![],
+        });
+        world.insert_resource(CardFonts {
+            title: default(),
+            _fonts: vec![],
+        });
+        world.insert_resource(CardVisualSize(Vec2::new(200., 275.)));
+        world.insert_resource(Localizer::with_default_language());
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(History::default());
+        world
+    }
+
+    fn spawn_card(world: &mut World, card_type: CardTypeId) -> Entity {
+        let card = world
+            .resource::<CardTypes>()
+            .get(card_type)
+            .unwrap()
+            .get_card_component();
+        world
+            .spawn()
+            .insert(card)
+            .insert_bundle(TransformBundle::default())
+            .id()
+    }
+
+    fn spawn_stack(world: &mut World, position: Vec2, cards: &[Entity]) -> Entity {
+        world
+            .spawn()
+            .insert_bundle(TransformBundle::from_transform(
+                Transform::from_translation(position.extend(0.)),
+            ))
+            .insert(CardStack(cards.to_vec()))
+            .push_children(cards)
+            .id()
+    }
+
+    fn board_summary(world: &mut World) -> Vec<StackSummary> {
+        let board = SystemState::<BoardQuery>::new(world)
+            .get_mut(world)
+            .snapshot();
+        board
+            .0
+            .into_iter()
+            .map(|stack| {
+                let cards = stack
+                    .cards
+                    .iter()
+                    .map(|card| (card.entity, card.type_id, card.uses, card.pack_cards))
+                    .collect();
+                let recipe = stack
+                    .recipe
+                    .map(|recipe| (recipe.id, recipe.instance, recipe.timer.elapsed()));
+                (stack.root, stack.position, cards, recipe)
+            })
+            .collect()
+    }
+
+    fn record(world: &mut World, operation: Operation) {
+        let board = SystemState::<BoardQuery>::new(world)
+            .get_mut(world)
+            .snapshot();
+        world.resource_mut::<History>().push(operation, board);
+    }
+
+    fn press_with_ctrl(world: &mut World, key: KeyCode) {
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::LControl);
+        keys.press(key);
+        world.insert_resource(keys);
+        history_keyboard_system(world);
+    }
+
+    #[test]
+    fn test_history_push() {
+        let mut history = History::default();
+        history.redo.push(HistoryEntry {
+            operations: vec![Operation::Merge],
+            board: BoardSnapshot::default(),
+        });
+
+        for _ in 0..MAX_HISTORY_LENGTH + 5 {
+            history.push(Operation::Split, BoardSnapshot::default());
+        }
+        assert_eq!(history.undo.len(), MAX_HISTORY_LENGTH);
+        // Doing something new means the undone operations can no longer be redone.
+        assert!(history.redo.is_empty());
+
+        history.add_to_last(Operation::OpenPack);
+        assert_eq!(history.undo.last().unwrap().operations.len(), 2);
+    }
+
+    #[test]
+    fn test_undo_and_redo_restore_the_board() {
+        let mut world = history_world();
+
+        let villager = spawn_card(&mut world, VILLAGER);
+        let tree = spawn_card(&mut world, TREE);
+        world.entity_mut(tree).insert(RecipeUses(2));
+        let coins: Vec<Entity> = (0..3).map(|_| spawn_card(&mut world, COIN)).collect();
+        let pack = spawn_card(&mut world, CardTypeId("forest_pack"));
+        world.entity_mut(pack).insert(CardPack { cards: 2 });
+
+        let recipe_root = spawn_stack(&mut world, Vec2::new(10., 20.), &[villager, tree]);
+        let mut recipe = OngoingRecipe::new(RecipeId("cut_tree"), 2.0);
+        recipe.timer.tick(Duration::from_secs(1));
+        world.entity_mut(recipe_root).insert(recipe);
+        let coin_root = spawn_stack(&mut world, Vec2::new(100., 0.), &coins);
+        spawn_stack(&mut world, Vec2::new(-50., 0.), &[pack]);
+
+        let before = board_summary(&mut world);
+
+        // Split the tree off, which stops the recipe, and use it once.
+        record(&mut world, Operation::Split);
+        let tree_root = spawn_stack(&mut world, Vec2::new(10., -40.), &[tree]);
+        world
+            .entity_mut(recipe_root)
+            .insert(CardStack(vec![villager]))
+            .remove::<OngoingRecipe>();
+        world.entity_mut(tree).insert(RecipeUses(1));
+
+        // Merge the coins onto it, and have an instant recipe consume one of them.
+        record(&mut world, Operation::Merge);
+        world
+            .entity_mut(tree_root)
+            .insert(CardStack(vec![tree, coins[0], coins[1]]))
+            .push_children(&coins[..2]);
+        world.entity_mut(coins[2]).despawn_recursive();
+        world.entity_mut(coin_root).despawn_recursive();
+        world
+            .resource_mut::<History>()
+            .add_to_last(Operation::InstantRecipe(RecipeId("sell_cards")));
+
+        // Take a card out of the pack.
+        record(&mut world, Operation::OpenPack);
+        world.entity_mut(pack).insert(CardPack { cards: 1 });
+
+        let after = board_summary(&mut world);
+
+        for _ in 0..3 {
+            press_with_ctrl(&mut world, KeyCode::Z);
+        }
+
+        // The same entities are back, in the same order and places.
+        assert_eq!(board_summary(&mut world), before);
+        assert!(world.get_entity(tree_root).is_none());
+        assert!(world.get::<Card>(coins[2]).is_some());
+        assert_eq!(world.get::<Parent>(coins[2]).unwrap().0, coin_root);
+        let history = world.resource::<History>();
+        assert!(history.undo.is_empty());
+        assert_eq!(history.redo.len(), 3);
+
+        for _ in 0..3 {
+            press_with_ctrl(&mut world, KeyCode::Y);
+        }
+
+        // The stack roots that were removed might have come back under a new id.
+        let without_roots = |summary: Vec<StackSummary>| {
+            let mut stacks: Vec<_> = summary
+                .into_iter()
+                .map(|(_, position, cards, recipe)| (position, cards, recipe))
+                .collect();
+            stacks.sort_by_key(|(_, cards, _)| cards[0].0);
+            stacks
+        };
+        assert_eq!(
+            without_roots(board_summary(&mut world)),
+            without_roots(after)
+        );
+        assert!(world.get_entity(coins[2]).is_none());
+        let history = world.resource::<History>();
+        assert_eq!(history.undo.len(), 3);
+        assert!(history.redo.is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+//! Lets the player choose which keys and mouse buttons do what.
+//! Systems ask for an [Action] through [ActionInput], instead of checking keys directly.
+
+use crate::settings::config_path;
+use crate::ui::UiClaimsKeyboard;
+use crate::GameState;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+const KEY_BINDINGS_FILE_NAME: &str = "key_bindings.ron";
+
+/// Prefix of the localization id of an action's name.
+const ACTION_LOCALIZATION_PREFIX: &str = "ui_action_";
+
+pub struct KeyBindingsPlugin;
+
+impl Plugin for KeyBindingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(KeyBindings::load())
+            .insert_resource(RebindingAction(None))
+            .add_system_set(
+                SystemSet::on_update(GameState::PauseMenu).with_system(rebinding_system),
+            );
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Action {
+    TogglePause,
+    SpeedNormal,
+    SpeedDouble,
+    SpeedTriple,
+    /// Opens the pause menu, or closes it when it is open.
+    OpenMenu,
+    /// Held down while moving the mouse.
+    PanCamera,
+    ZoomIn,
+    ZoomOut,
+}
+
+impl Action {
+    /// In the order they are shown to the player.
+    pub const ALL: [Action; 8] = [
+        Action::TogglePause,
+        Action::SpeedNormal,
+        Action::SpeedDouble,
+        Action::SpeedTriple,
+        Action::OpenMenu,
+        Action::PanCamera,
+        Action::ZoomIn,
+        Action::ZoomOut,
+    ];
+
+    pub fn localization_id(&self) -> String {
+        let name = match self {
+            Action::TogglePause => "toggle_pause",
+            Action::SpeedNormal => "speed_normal",
+            Action::SpeedDouble => "speed_double",
+            Action::SpeedTriple => "speed_triple",
+            Action::OpenMenu => "open_menu",
+            Action::PanCamera => "pan_camera",
+            Action::ZoomIn => "zoom_in",
+            Action::ZoomOut => "zoom_out",
+        };
+        ACTION_LOCALIZATION_PREFIX.to_string() + name
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl Binding {
+    /// Short name to show to the player.
+    pub fn name(&self) -> String {
+        match self {
+            Binding::Key(key) => {
+                let name = format!("{:?}", key);
+                // `Key1` is shown as `1`.
+                match name.strip_prefix("Key") {
+                    Some(digit) if !digit.is_empty() => digit.to_string(),
+                    _ => name,
+                }
+            }
+            Binding::Mouse(MouseButton::Other(button)) => format!("Mouse {}", button),
+            Binding::Mouse(button) => format!("Mouse {:?}", button),
+        }
+    }
+}
+
+/// Resource with the keys and mouse buttons bound to each action.
+/// Stored in the config directory, so the bindings are kept between games.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeyBindings(BTreeMap<Action, Vec<Binding>>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings(BTreeMap::from([
+            (Action::TogglePause, vec![Binding::Key(KeyCode::Space)]),
+            (Action::SpeedNormal, vec![Binding::Key(KeyCode::Key1)]),
+            (Action::SpeedDouble, vec![Binding::Key(KeyCode::Key2)]),
+            (Action::SpeedTriple, vec![Binding::Key(KeyCode::Key3)]),
+            (Action::OpenMenu, vec![Binding::Key(KeyCode::Escape)]),
+            (
+                Action::PanCamera,
+                vec![
+                    Binding::Mouse(MouseButton::Left),
+                    Binding::Mouse(MouseButton::Right),
+                ],
+            ),
+            (Action::ZoomIn, vec![Binding::Key(KeyCode::Equals)]),
+            (Action::ZoomOut, vec![Binding::Key(KeyCode::Minus)]),
+        ]))
+    }
+}
+
+impl KeyBindings {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.0.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn set(&mut self, action: Action, bindings: Vec<Binding>) {
+        self.0.insert(action, bindings);
+    }
+
+    /// The bindings of the action, like `[Space]`. For hover texts.
+    pub fn hint(&self, action: Action) -> String {
+        let names: Vec<String> = self.bindings(action).iter().map(Binding::name).collect();
+        format!("[{}]", names.join(", "))
+    }
+
+    /// Actions that are missing from the text keep their default bindings,
+    /// so actions added in a newer version of the game get bound as well.
+    fn from_ron(text: &str) -> anyhow::Result<Self> {
+        let loaded: KeyBindings = ron::from_str(text)?;
+        let mut bindings = KeyBindings::default();
+        bindings.0.extend(loaded.0);
+        Ok(bindings)
+    }
+
+    /// Falls back on the default bindings when there is no config file, or it cannot be read.
+    fn load() -> Self {
+        let path = key_bindings_path();
+        if !path.exists() {
+            return KeyBindings::default();
+        }
+
+        match fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| KeyBindings::from_ron(&text))
+        {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                warn!(
+                    "Could not read key bindings from `{}`, using the defaults: {}",
+                    path.display(),
+                    e
+                );
+                KeyBindings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = key_bindings_path();
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(anyhow::Error::from)
+            .and_then(|text| {
+                if let Some(folder) = path.parent() {
+                    fs::create_dir_all(folder)?;
+                }
+                Ok(fs::write(&path, text)?)
+            });
+
+        if let Err(e) = result {
+            error!("Could not save key bindings to `{}`: {}", path.display(), e);
+        }
+    }
+}
+
+fn key_bindings_path() -> PathBuf {
+    config_path(KEY_BINDINGS_FILE_NAME)
+}
+
+/// Resource with the action that will be bound to the next key or mouse button the player
+/// presses.
+pub struct RebindingAction(pub Option<Action>);
+
+/// Checks whether the keys and mouse buttons bound to an action are pressed.
+/// Keys are ignored while the ui uses the keyboard, so typing in a text field does not pause the
+/// game.
+#[derive(SystemParam)]
+pub struct ActionInput<'w, 's> {
+    keys: ResMut<'w, Input<KeyCode>>,
+    mouse_buttons: ResMut<'w, Input<MouseButton>>,
+    bindings: Res<'w, KeyBindings>,
+    ui_claims_keyboard: Res<'w, UiClaimsKeyboard>,
+    #[system_param(ignore)]
+    _marker: std::marker::PhantomData<&'s ()>,
+}
+
+impl<'w, 's> ActionInput<'w, 's> {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.bindings
+            .bindings(action)
+            .iter()
+            .any(|binding| match *binding {
+                Binding::Key(key) => !self.ui_claims_keyboard.0 && self.keys.pressed(key),
+                Binding::Mouse(button) => self.mouse_buttons.pressed(button),
+            })
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.bindings
+            .bindings(action)
+            .iter()
+            .any(|binding| match *binding {
+                Binding::Key(key) => !self.ui_claims_keyboard.0 && self.keys.just_pressed(key),
+                Binding::Mouse(button) => self.mouse_buttons.just_pressed(button),
+            })
+    }
+
+    /// Like [ActionInput::just_pressed], but other systems will no longer see the press this
+    /// frame. For actions that change the [GameState], so the systems of the new state don't
+    /// react to the same press.
+    pub fn take_just_pressed(&mut self, action: Action) -> bool {
+        let mut pressed = false;
+        for binding in self.bindings.bindings(action) {
+            pressed |= match *binding {
+                Binding::Key(key) => {
+                    !self.ui_claims_keyboard.0 && self.keys.clear_just_pressed(key)
+                }
+                Binding::Mouse(button) => self.mouse_buttons.clear_just_pressed(button),
+            };
+        }
+        pressed
+    }
+}
+
+/// Binds the next key or mouse button that is pressed to the [RebindingAction].
+/// Mouse buttons only count when they are not pressed on the menu itself.
+fn rebinding_system(
+    mut rebinding: ResMut<RebindingAction>,
+    mut bindings: ResMut<KeyBindings>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut mouse_buttons: ResMut<Input<MouseButton>>,
+    mut context: ResMut<EguiContext>,
+) {
+    let action = match rebinding.0 {
+        Some(action) => action,
+        None => return,
+    };
+
+    let maybe_binding = if let Some(&key) = keys.get_just_pressed().next() {
+        keys.clear_just_pressed(key);
+        Some(Binding::Key(key))
+    } else if context.ctx_mut().is_pointer_over_area() {
+        None
+    } else if let Some(&button) = mouse_buttons.get_just_pressed().next() {
+        mouse_buttons.clear_just_pressed(button);
+        Some(Binding::Mouse(button))
+    } else {
+        None
+    };
+
+    if let Some(binding) = maybe_binding {
+        info!("Bound {:?} to {}", action, binding.name());
+        bindings.set(action, vec![binding]);
+        bindings.save();
+        rebinding.0 = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::key_bindings::{Action, ActionInput, Binding, KeyBindings};
+    use crate::ui::UiClaimsKeyboard;
+    use bevy::prelude::*;
+
+    /// Resource with the actions that [test_action_system] saw being pressed.
+    #[derive(Default)]
+    struct PressedActions(Vec<Action>);
+
+    fn test_action_system(input: ActionInput, mut pressed: ResMut<PressedActions>) {
+        pressed.0 = Action::ALL
+            .into_iter()
+            .filter(|&action| input.just_pressed(action))
+            .collect();
+    }
+
+    #[test]
+    fn test_keys_are_ignored_while_ui_claims_keyboard() {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel().with_system(test_action_system);
+
+        world.insert_resource(KeyBindings::default());
+        world.insert_resource(PressedActions::default());
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(Input::<MouseButton>::default());
+        world.insert_resource(UiClaimsKeyboard(true));
+
+        world.resource_mut::<Input<KeyCode>>().press(KeyCode::Space);
+        world
+            .resource_mut::<Input<MouseButton>>()
+            .press(MouseButton::Left);
+        update_stage.run(&mut world);
+        // Only the mouse button counts, because the ui has the keyboard.
+        assert_eq!(world.resource::<PressedActions>().0, [Action::PanCamera]);
+
+        world.insert_resource(UiClaimsKeyboard(false));
+        update_stage.run(&mut world);
+        assert_eq!(
+            world.resource::<PressedActions>().0,
+            [Action::TogglePause, Action::PanCamera]
+        );
+    }
+
+    #[test]
+    fn test_binding_names() {
+        assert_eq!(Binding::Key(KeyCode::Key1).name(), "1");
+        assert_eq!(Binding::Key(KeyCode::Space).name(), "Space");
+        assert_eq!(Binding::Mouse(MouseButton::Left).name(), "Mouse Left");
+        assert_eq!(Binding::Mouse(MouseButton::Other(4)).name(), "Mouse 4");
+
+        assert_eq!(KeyBindings::default().hint(Action::TogglePause), "[Space]");
+        assert_eq!(
+            KeyBindings::default().hint(Action::PanCamera),
+            "[Mouse Left, Mouse Right]"
+        );
+    }
+
+    #[test]
+    fn test_missing_actions_get_default_bindings() {
+        let bindings = KeyBindings::from_ron("({TogglePause: [Key(P)]})").unwrap();
+        assert_eq!(
+            bindings.bindings(Action::TogglePause),
+            [Binding::Key(KeyCode::P)]
+        );
+        assert_eq!(
+            bindings.bindings(Action::SpeedNormal),
+            KeyBindings::default().bindings(Action::SpeedNormal)
+        );
+
+        assert!(KeyBindings::from_ron("({Fly: [Key(F)]})").is_err());
+    }
+
+    #[test]
+    fn test_key_bindings_round_trip() {
+        let mut bindings = KeyBindings::default();
+        bindings.set(Action::ZoomIn, vec![Binding::Mouse(MouseButton::Middle)]);
+
+        let text = ron::to_string(&bindings).unwrap();
+        assert_eq!(KeyBindings::from_ron(&text).unwrap(), bindings);
+    }
+}
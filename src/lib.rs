@@ -4,72 +4,125 @@
 // Which makes system arguments even more verbose than they already are.
 // Once the elided lifetimes are no longer allowed, bevy will probably have a solution for it.
 #![allow(elided_lifetimes_in_paths)]
+// For the benchmarks, which run with `cargo bench`.
+#![cfg_attr(test, feature(test))]
+
+#[cfg(test)]
+extern crate test;
 
 mod camera;
 mod card_packs;
 mod card_types;
+mod day_end;
+pub mod headless;
+mod history;
+mod key_bindings;
 mod localization;
 pub mod procedural;
 mod recipe;
+mod save_game;
+mod settings;
 mod stack;
 mod ui;
 
 use crate::camera::*;
 use crate::card_packs::CardPackPlugin;
-use crate::localization::LocalizationPlugin;
-use crate::procedural::ProceduralPlugin;
-use crate::recipe::RecipePlugin;
-use crate::stack::{CardFonts, CardImages, StackPlugin};
-use crate::ui::UiPlugin;
+use crate::card_types::{CardTypeAssets, CardTypesPlugin};
+use crate::day_end::{DayEndPlugin, DayEndSummary, DayEndedEvent};
+use crate::history::HistoryPlugin;
+use crate::key_bindings::{Action, ActionInput, KeyBindingsPlugin};
+use crate::localization::{LocalizationAssets, LocalizationPlugin};
+use crate::procedural::{random_seed, ProceduralPlugin};
+use crate::recipe::{RecipeAssets, RecipeLogicPlugin, RecipePlugin};
+use crate::save_game::SaveGamePlugin;
+use crate::settings::SettingsPlugin;
+use crate::stack::{CardFonts, CardImages, StackLogicPlugin, StackPlugin};
+use crate::ui::{NewGameSeed, UiPlugin};
 use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
 use bevy_asset_loader::AssetLoader;
 
-pub struct TheStacksPlugin;
+#[derive(Default)]
+pub struct TheStacksPlugin {
+    /// Seed that is filled in on the main menu. A random one is used when `None`.
+    pub seed: Option<u32>,
+}
 
 impl Plugin for TheStacksPlugin {
     fn build(&self, app: &mut App) {
         AssetLoader::new(GameState::AssetLoading)
-            .continue_to_state(GameState::Run)
+            .continue_to_state(GameState::MainMenu)
             .with_collection::<CardImages>()
             .with_collection::<CardFonts>()
+            .with_collection::<CardTypeAssets>()
+            .with_collection::<RecipeAssets>()
+            .with_collection::<LocalizationAssets>()
             .build(app);
 
         app.insert_resource(Msaa { samples: 4 })
             .insert_resource(ClearColor(Color::rgb(0.1, 0.2, 0.1)))
-            .insert_resource(TimeSpeed {
-                running: true,
-                speed: Speed::NORMAL,
-            })
-            .insert_resource(TimeOfDay {
-                time_of_day: 0.,
-                day: 1,
-            })
-            .insert_resource(LengthOfDay(100.))
             .add_state(GameState::AssetLoading)
-            .add_stage_after(
-                CoreStage::Update,
-                UpdateStage::SystemsThatDeleteCards.as_str(),
-                SystemStage::parallel(),
-            )
-            .add_plugin(ProceduralPlugin)
+            .add_plugin(GameLogicPlugin)
+            .add_plugin(CardTypesPlugin)
             .add_plugin(StackPlugin)
-            .add_plugin(CardPackPlugin)
             .add_plugin(RecipePlugin)
             .add_plugin(OrthographicCameraPlugin)
             .add_plugin(UiPlugin)
             .add_plugin(LocalizationPlugin)
+            .add_plugin(SaveGamePlugin)
+            .add_plugin(HistoryPlugin)
+            .add_plugin(KeyBindingsPlugin)
+            .add_plugin(SettingsPlugin)
+            .insert_resource(NewGameSeed(
+                self.seed.unwrap_or_else(random_seed).to_string(),
+            ))
             .add_system_set(
-                SystemSet::on_update(GameState::Run)
-                    .with_system(game_speed_change_system)
-                    .with_system(time_of_day_progress_system),
+                SystemSet::on_update(GameState::Run).with_system(game_speed_change_system),
             );
     }
 }
 
+/// The rules of the game, without anything that is needed to show it on screen.
+/// Shared by the [TheStacksPlugin] and the [HeadlessPlugin](headless::HeadlessPlugin).
+struct GameLogicPlugin;
+
+impl Plugin for GameLogicPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TimeSpeed {
+            running: true,
+            speed: Speed::NORMAL,
+        })
+        .insert_resource(TimeOfDay {
+            time_of_day: 0.,
+            day: 1,
+        })
+        .insert_resource(LengthOfDay(DEFAULT_LENGTH_OF_DAY))
+        .insert_resource(FrameTime {
+            delta_seconds: 0.,
+            fixed_timestep: None,
+        })
+        .add_stage_after(
+            CoreStage::Update,
+            UpdateStage::SystemsThatDeleteCards.as_str(),
+            SystemStage::parallel(),
+        )
+        .add_plugin(ProceduralPlugin)
+        .add_plugin(StackLogicPlugin)
+        .add_plugin(CardPackPlugin)
+        .add_plugin(RecipeLogicPlugin)
+        .add_plugin(DayEndPlugin)
+        .add_system_to_stage(CoreStage::PreUpdate, frame_time_system)
+        .add_system_set(
+            SystemSet::on_update(GameState::Run).with_system(time_of_day_progress_system),
+        );
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 enum GameState {
     AssetLoading,
+    /// Where the player picks the seed of a new game.
+    MainMenu,
     Run,
     PauseMenu,
 }
@@ -98,6 +151,23 @@ pub struct TimeOfDay {
 /// Resource that holds the configured length of a single day, in in-game seconds.
 pub struct LengthOfDay(f32);
 
+/// Length of a day when the player did not choose one in the settings.
+const DEFAULT_LENGTH_OF_DAY: f32 = 100.;
+
+/// Resource with the seconds that passed since the previous frame, not taking into account the
+/// [TimeSpeed]. Game logic should use this instead of [Time], so it can also run at a fixed timestep.
+pub struct FrameTime {
+    delta_seconds: f32,
+    /// When set, every frame lasts this many seconds, no matter how long it really took.
+    fixed_timestep: Option<f32>,
+}
+
+impl FrameTime {
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta_seconds
+    }
+}
+
 /// There are separate `running` and `speed` fields so that the game can remember the desired speed
 /// while paused.
 /// Influences things like the progress of crafting recipes.
@@ -124,19 +194,29 @@ pub enum Speed {
     TRIPLE,
 }
 
-fn game_speed_change_system(keys: Res<Input<KeyCode>>, mut speed: ResMut<TimeSpeed>) {
-    if keys.just_pressed(KeyCode::Space) {
+/// Keys are ignored while the ui claims the keyboard, see [ActionInput].
+fn game_speed_change_system(
+    input: ActionInput,
+    mut speed: ResMut<TimeSpeed>,
+    day_end_summary: Option<Res<DayEndSummary>>,
+) {
+    if day_end_summary.is_some() {
+        // Time stays paused until the player has seen how the day ended.
+        return;
+    }
+
+    if input.just_pressed(Action::TogglePause) {
         speed.running = !speed.running;
     }
-    if keys.just_pressed(KeyCode::Key1) {
+    if input.just_pressed(Action::SpeedNormal) {
         speed.running = true;
         speed.speed = Speed::NORMAL;
     }
-    if keys.just_pressed(KeyCode::Key2) {
+    if input.just_pressed(Action::SpeedDouble) {
         speed.running = true;
         speed.speed = Speed::DOUBLE;
     }
-    if keys.just_pressed(KeyCode::Key3) {
+    if input.just_pressed(Action::SpeedTriple) {
         speed.running = true;
         speed.speed = Speed::TRIPLE;
     }
@@ -144,19 +224,28 @@ fn game_speed_change_system(keys: Res<Input<KeyCode>>, mut speed: ResMut<TimeSpe
 
 fn time_of_day_progress_system(
     mut days: ResMut<TimeOfDay>,
-    speed: ResMut<TimeSpeed>,
+    mut speed: ResMut<TimeSpeed>,
     length_of_day: Res<LengthOfDay>,
-    time: Res<Time>,
+    frame_time: Res<FrameTime>,
+    mut day_ended: EventWriter<DayEndedEvent>,
 ) {
     if speed.running {
-        days.time_of_day += (time.delta_seconds() * speed.as_factor()) / length_of_day.0;
+        days.time_of_day += (frame_time.delta_seconds() * speed.as_factor()) / length_of_day.0;
         if days.time_of_day >= 1.0 {
             days.time_of_day -= 1.0;
+            day_ended.send(DayEndedEvent { day: days.day });
             days.day += 1;
+            speed.running = false;
         }
     }
 }
 
+fn frame_time_system(time: Res<Time>, mut frame_time: ResMut<FrameTime>) {
+    frame_time.delta_seconds = frame_time
+        .fixed_timestep
+        .unwrap_or_else(|| time.delta_seconds());
+}
+
 /// Conditional that can be used in [SystemSet::with_run_criteria](bevy::prelude::SystemSet::with_run_criteria) statements.
 pub fn is_time_running(speed: Res<TimeSpeed>) -> ShouldRun {
     if speed.running {
@@ -165,3 +254,52 @@ pub fn is_time_running(speed: Res<TimeSpeed>) -> ShouldRun {
         ShouldRun::No
     }
 }
+
+/// Conditional for systems that can run in any [GameState], as long as the assets are loaded.
+pub fn are_assets_loaded(state: Res<State<GameState>>) -> ShouldRun {
+    if *state.current() == GameState::AssetLoading {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::key_bindings::KeyBindings;
+    use crate::ui::UiClaimsKeyboard;
+    use crate::{game_speed_change_system, Speed, TimeSpeed};
+    use bevy::prelude::*;
+
+    #[test]
+    fn test_game_speed_keys_are_ignored_while_ui_claims_keyboard() {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel().with_system(game_speed_change_system);
+
+        world.insert_resource(KeyBindings::default());
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(Input::<MouseButton>::default());
+        world.insert_resource(UiClaimsKeyboard(true));
+        world.insert_resource(TimeSpeed {
+            running: true,
+            speed: Speed::NORMAL,
+        });
+
+        // Like typing a space and a 2 in a text field.
+        let mut keys = world.resource_mut::<Input<KeyCode>>();
+        keys.press(KeyCode::Space);
+        keys.press(KeyCode::Key2);
+        update_stage.run(&mut world);
+
+        let speed = world.resource::<TimeSpeed>();
+        assert!(speed.running);
+        assert!(speed.speed == Speed::NORMAL);
+
+        world.insert_resource(UiClaimsKeyboard(false));
+        world.insert_resource(Input::<KeyCode>::default());
+        world.resource_mut::<Input<KeyCode>>().press(KeyCode::Space);
+        update_stage.run(&mut world);
+
+        assert!(!world.resource::<TimeSpeed>().running);
+    }
+}
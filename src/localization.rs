@@ -1,44 +1,115 @@
-use bevy::asset::AssetServerSettings;
+use crate::GameState;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
 use bevy::prelude::*;
-use fluent::{bundle::FluentBundle, FluentArgs, FluentResource};
+use bevy::reflect::TypeUuid;
+use bevy_asset_loader::AssetCollection;
+use fluent::types::{FluentNumber, FluentNumberOptions};
+use fluent::{bundle::FluentBundle, FluentArgs, FluentResource, FluentValue};
+use fluent_syntax::ast;
 use intl_memoizer::concurrent::IntlLangMemoizer;
-use std::collections::HashMap;
-use std::fs;
+use intl_memoizer::Memoizable;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
 use std::sync::Arc;
 use unic_langid::{langid, LanguageIdentifier};
 
 const LOCALIZATION_ASSETS_PATH: &str = "localization";
-const FLUENT_FILE_EXTENSION: &str = ".ftl";
+const FLUENT_FILE_EXTENSION: &str = "ftl";
 
 const DEFAULT_LANGUAGE: LanguageIdentifier = langid!("en-US");
+/// Generated from the [DEFAULT_LANGUAGE], to spot text that is not localized or does not fit.
+/// See [pseudo_transform].
+const PSEUDO_LANGUAGE: LanguageIdentifier = langid!("qps-ploc");
+/// Players have no use for the [PSEUDO_LANGUAGE], so it can only be selected in debug builds.
+const PSEUDO_LANGUAGE_AVAILABLE: bool = cfg!(debug_assertions);
+const PSEUDO_LOCALIZATION_PADDING: char = '~';
 
-/// Definition of the supported languages.
-/// Includes the language id, and the name of the language,
-/// in that language (used for language selection UI).
-///
-/// Expects there to be a `lang-id.ftl` file in the [LOCALIZATION_ASSETS_PATH] folder for each entry.
-/// If the file isn't there, then that language can't be selected.
-const LANGUAGES: &[(&str, &str)] = &[("en-US", "English"), ("nl-NL", "Nederlands")];
+/// Every language file has a message with this id, containing the name of the language in that
+/// language. Used for the language selection ui.
+const LANGUAGE_NAME_MESSAGE: &str = "language_name";
+/// Attribute of the [LANGUAGE_NAME_MESSAGE] with the fonts for the card titles, separated by
+/// commas. Like `fonts/FallingSky-JKwK.otf`, relative to the assets folder.
+const TITLE_FONTS_ATTRIBUTE: &str = "title-fonts";
+
+type Bundle = FluentBundle<Arc<FluentResource>, IntlLangMemoizer>;
 
 pub struct LocalizationPlugin;
 
 impl Plugin for LocalizationPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(localization_loader_system);
+        app.add_event::<LanguageChangedEvent>()
+            .insert_resource(LocalizationCache::default())
+            .add_asset::<LanguageFile>()
+            .init_asset_loader::<LanguageFileLoader>()
+            .add_system_set(SystemSet::on_exit(GameState::AssetLoading).with_system(
+                // Exclusive, so the `Localizer` is there for the other systems that run
+                // when the assets are loaded, such as the one that checks the card types.
+                localization_loader_system.exclusive_system().at_start(),
+            ))
+            .add_system(localization_reload_system)
+            // Cleared before the ui runs, so it does not show strings of the previous language.
+            .add_system_to_stage(CoreStage::PreUpdate, localization_cache_invalidation_system)
+            .add_system_to_stage(CoreStage::PostUpdate, language_changed_event_system);
+    }
+}
+
+/// Sent when the language of the [Localizer] is changed, or the messages of a language are
+/// reloaded. Everything that shows localized text should localize it again.
+pub struct LanguageChangedEvent {
+    pub language: LanguageIdentifier,
+}
+
+/// To be loaded by an [AssetLoader](bevy_asset_loader::AssetLoader).
+/// Every `<language id>.ftl` file in the folder is a language that can be selected.
+#[derive(AssetCollection)]
+pub struct LocalizationAssets {
+    #[asset(path = "localization", folder)]
+    pub files: Vec<HandleUntyped>,
+}
+
+/// The messages of one language.
+#[derive(TypeUuid)]
+#[uuid = "0f1c6a2e-8a3b-4a56-b0f4-62e8d7c35b19"]
+pub struct LanguageFile(Arc<FluentResource>);
+
+#[derive(Default)]
+pub struct LanguageFileLoader;
+
+impl AssetLoader for LanguageFileLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let text = String::from_utf8(bytes.to_vec())?;
+            let resource = parse_fluent_resource(text).map_err(|e| {
+                anyhow::anyhow!("Could not parse `{}`:{}", load_context.path().display(), e)
+            })?;
+            load_context.set_default_asset(LoadedAsset::new(LanguageFile(Arc::new(resource))));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[FLUENT_FILE_EXTENSION]
     }
 }
 
+/// Resource with the language of each loaded [LanguageFile], so they can be reloaded.
+struct LanguageFileHandles(HashMap<HandleId, LanguageIdentifier>);
+
 pub struct Localizer {
-    /// All supported languages, along with their name in that language.
-    languages: HashMap<
-        LanguageIdentifier,
-        (
-            FluentBundle<Arc<FluentResource>, IntlLangMemoizer>,
-            &'static str,
-        ),
-    >,
+    /// All supported languages.
+    languages: HashMap<LanguageIdentifier, Bundle>,
     /// The language that is in-use.
     current_language: LanguageIdentifier,
+    /// Languages that are tried in order when a message is missing. See [Localizer::fallback_chain].
+    fallback_chain: Vec<LanguageIdentifier>,
+    /// Whether a [LanguageChangedEvent] should be sent, see [language_changed_event_system].
+    language_changed: bool,
 }
 
 impl Localizer {
@@ -46,16 +117,107 @@ impl Localizer {
         self.current_language.clone()
     }
 
+    /// When the language is not available, another variant of the same language is selected,
+    /// for example `nl-NL` for `nl-BE`. If there is none, the language stays the same.
     pub fn select_language(&mut self, new_language: LanguageIdentifier) {
-        if self.languages.contains_key(&new_language) {
-            self.current_language = new_language;
+        let maybe_language = if self.languages.contains_key(&new_language) {
+            Some(new_language)
+        } else {
+            self.variants_of_language(&new_language).into_iter().next()
+        };
+
+        match maybe_language {
+            Some(language) => {
+                self.fallback_chain = self.fallback_chain(&language);
+                self.current_language = language;
+                self.language_changed = true;
+            }
+            None => warn!("Language `{}` is not available", new_language),
+        }
+    }
+
+    /// Available languages with the same language subtag, but a different region or script.
+    /// Ones without a region come first.
+    fn variants_of_language(&self, language: &LanguageIdentifier) -> Vec<LanguageIdentifier> {
+        let mut variants: Vec<LanguageIdentifier> = self
+            .languages
+            .keys()
+            .filter(|other| other.language == language.language && *other != language)
+            .cloned()
+            .collect();
+        variants.sort_by_key(|variant| (variant.region.is_some(), variant.to_string()));
+        variants
+    }
+
+    /// The languages to try when localizing: the given language itself, then other variants of
+    /// the same language, and then the [DEFAULT_LANGUAGE].
+    fn fallback_chain(&self, language: &LanguageIdentifier) -> Vec<LanguageIdentifier> {
+        let mut chain = vec![language.clone()];
+        chain.extend(self.variants_of_language(language));
+        if !chain.contains(&DEFAULT_LANGUAGE) {
+            chain.push(DEFAULT_LANGUAGE);
+        }
+        chain
+    }
+
+    /// All supported languages, along with their name in that language.
+    pub fn language_options(&self) -> HashMap<LanguageIdentifier, String> {
+        self.languages
+            .iter()
+            .map(|(language, bundle)| {
+                let name = bundle
+                    .get_message(LANGUAGE_NAME_MESSAGE)
+                    .and_then(|message| message.value())
+                    .map(|pattern| {
+                        bundle
+                            .format_pattern(pattern, None, &mut vec![])
+                            .to_string()
+                    })
+                    .unwrap_or_else(|| language.to_string());
+                (language.clone(), name)
+            })
+            .collect()
+    }
+
+    /// Fonts for the card titles, in the order they should be tried until one has all the
+    /// letters of a title. The fonts of the languages in the fallback chain come last, so
+    /// titles that are not translated still have a font.
+    pub fn title_fonts(&self) -> Vec<String> {
+        let mut fonts: Vec<String> = vec![];
+        for language in self.fallback_chain.iter() {
+            if *language == PSEUDO_LANGUAGE {
+                // Its font paths are pseudo localized as well.
+                continue;
+            }
+            let bundle = &self.languages[language];
+            let maybe_pattern = bundle
+                .get_message(LANGUAGE_NAME_MESSAGE)
+                .and_then(|message| message.get_attribute(TITLE_FONTS_ATTRIBUTE))
+                .map(|attribute| attribute.value());
+            if let Some(pattern) = maybe_pattern {
+                let paths = bundle.format_pattern(pattern, None, &mut vec![]);
+                for path in paths.split(',').map(str::trim) {
+                    if !path.is_empty() && !fonts.iter().any(|font| font == path) {
+                        fonts.push(path.to_string());
+                    }
+                }
+            }
         }
+        fonts
+    }
+
+    /// Used when a language file is reloaded.
+    fn replace_language(&mut self, language: LanguageIdentifier, bundle: Bundle) {
+        self.languages.insert(language, bundle);
+        self.language_changed = true;
     }
 
-    pub fn language_options(&self) -> HashMap<LanguageIdentifier, &'static str> {
+    /// Lists the languages which do not have a message with the given id.
+    pub fn languages_missing_message(&self, id: &str) -> Vec<LanguageIdentifier> {
         self.languages
             .iter()
-            .map(|(key, (_, name))| (key.clone(), <&str>::clone(name)))
+            .filter(|(_, bundle)| !bundle.has_message(id))
+            .map(|(language, _)| language.clone())
             .collect()
     }
 
@@ -63,80 +225,176 @@ impl Localizer {
         self.localize_with_args(id, &[])
     }
 
-    pub fn localize_with_args(&self, id: &str, args: &[(&str, &str)]) -> String {
-        // TODO (Wybe 2022-06-19): Fall back on en-US if there is missing translations.
-        // TODO (Wybe 2022-06-19): Add a stage to the cargo make file that checks if all the
-        //      translation files have the values that the en-US.ftl file has. To make sure
-        //      there are no missing translations in other languages. (ignore the ones prefixed
-        //      with '-' of course.
-        //      and maybe also check that it uses the same arguments (e.g. `{$seconds}`) as the
-        //      english one uses, to prevent it mising information. The variables have to be
-        //      directly embedded into the translation entry (and not in referenced entries), so it should be checkable.
+    /// Messages that are missing in the current language are taken from the next language in
+    /// the fallback chain. Whether all languages have all messages is checked by the tests of
+    /// this module.
+    ///
+    /// Numbers should be given as numbers, like `("day", 3.into())`, so the message can select
+    /// on their plural category and they are written the way the language writes them.
+    /// See [decimal_number] for numbers with a fraction.
+    pub fn localize_with_args(&self, id: &str, args: &[(&str, FluentValue<'_>)]) -> String {
         let mut fluent_args = FluentArgs::new();
         for (key, value) in args {
-            fluent_args.set(<&str>::clone(key), <&str>::clone(value));
+            fluent_args.set(*key, value.clone());
         }
 
-        let current_bundle = &self.languages[&self.current_language].0;
+        for language in self.fallback_chain.iter() {
+            let bundle = &self.languages[language];
+            let pattern = match bundle.get_message(id).and_then(|msg| msg.value()) {
+                Some(pattern) => pattern,
+                None => continue,
+            };
 
-        if let Some(msg) = current_bundle.get_message(id) {
-            if let Some(pattern) = msg.value() {
-                let mut errors = vec![];
-                let result =
-                    current_bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+            let mut errors = vec![];
+            let result = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
 
-                if !errors.is_empty() {
-                    let errors_string = errors
-                        .iter()
-                        .enumerate()
-                        // TODO (Wybe 2022-06-06): Clean up this error reporting?
-                        .map(|(i, err)| format!("\n{}: {:?}", i, err))
-                        .collect::<String>();
+            if !errors.is_empty() {
+                let errors_string = errors
+                    .iter()
+                    .enumerate()
+                    // TODO (Wybe 2022-06-06): Clean up this error reporting?
+                    .map(|(i, err)| format!("\n{}: {:?}", i, err))
+                    .collect::<String>();
 
-                    warn!(
-                        "Errors while localizing `{}` for language `{}`, with arguments {:x?}:{}",
-                        id, self.current_language, args, errors_string
-                    );
-                }
-                result.to_string()
-            } else {
                 warn!(
-                    "Could not localize `{}` for language `{}`",
-                    id, self.current_language
+                    "Errors while localizing `{}` for language `{}`, with arguments {:x?}:{}",
+                    id, language, args, errors_string
                 );
-                id.to_string()
             }
-        } else {
-            warn!(
-                "Could not localize `{}` for language `{}`",
-                id, self.current_language
-            );
-            id.to_string()
+            if *language == PSEUDO_LANGUAGE {
+                return format!("[{}]", result);
+            }
+            return result.to_string();
         }
+
+        warn!(
+            "Could not localize `{}` for language `{}`",
+            id, self.current_language
+        );
+        id.to_string()
+    }
+
+    fn new(languages: HashMap<LanguageIdentifier, Bundle>) -> Self {
+        let mut localizer = Localizer {
+            languages,
+            current_language: DEFAULT_LANGUAGE,
+            fallback_chain: vec![],
+            language_changed: false,
+        };
+        localizer.select_language(DEFAULT_LANGUAGE);
+        // Nothing has been localized yet.
+        localizer.language_changed = false;
+        localizer
+    }
+
+    /// Only has the messages of the [DEFAULT_LANGUAGE], for tests that don't load the assets.
+    #[cfg(test)]
+    pub(crate) fn with_default_language() -> Self {
+        let text = include_str!("../assets/localization/en-US.ftl").to_string();
+        let resource = Arc::new(parse_fluent_resource(text).unwrap());
+        let bundle = create_bundle(DEFAULT_LANGUAGE, resource).unwrap();
+        Localizer::new(HashMap::from([(DEFAULT_LANGUAGE, bundle)]))
     }
 }
 
-/// TODO (Wybe 2022-06-06): Allow switching the language while playing the game (and auto-update things like the card titles).
+/// Resource with localized strings, for text that is shown every frame.
+/// Cleared when a [LanguageChangedEvent] is sent.
+#[derive(Default)]
+pub struct LocalizationCache(HashMap<String, CachedString>);
 
-/// TODO (Wybe 2022-06-06): Make localization files assets, so they can be loaded by the AssetLoader,
-///       and included in the overall loading progress.
-pub fn localization_loader_system(
-    mut commands: Commands,
-    asset_server_settings: Res<AssetServerSettings>,
-) {
-    let folder = asset_server_settings.asset_folder.to_owned() + "/" + LOCALIZATION_ASSETS_PATH;
+struct CachedString {
+    args: Vec<(String, FluentValue<'static>)>,
+    text: String,
+}
 
-    let mut languages = HashMap::new();
-    for (lang_id_string, name) in LANGUAGES {
-        match load_language_file(&folder, lang_id_string) {
-            Ok((id, bundle)) => {
-                languages.insert(id, (bundle, name.clone()));
+impl LocalizationCache {
+    pub fn localize(&mut self, localizer: &Localizer, id: &str) -> String {
+        self.localize_with_args(localizer, id, &[])
+    }
+
+    /// Only localizes the message again when the arguments differ from the last time it was
+    /// requested.
+    pub fn localize_with_args(
+        &mut self,
+        localizer: &Localizer,
+        id: &str,
+        args: &[(&str, FluentValue<'_>)],
+    ) -> String {
+        if let Some(cached) = self.0.get(id) {
+            let same_args =
+                cached.args.len() == args.len()
+                    && cached.args.iter().zip(args).all(
+                        |((key, value), (other_key, other_value))| {
+                            key == other_key && value == other_value
+                        },
+                    );
+            if same_args {
+                return cached.text.clone();
             }
-            Err(e) => {
-                warn!(
-                    "Language {} ({}) will not be available: {}",
-                    name, lang_id_string, e
-                );
+        }
+
+        let text = localizer.localize_with_args(id, args);
+        self.0.insert(
+            id.to_string(),
+            CachedString {
+                args: args
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), owned_value(value)))
+                    .collect(),
+                text: text.clone(),
+            },
+        );
+        text
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Creates the [Localizer] from the loaded language files.
+/// The language of a file is taken from its name, so `nl-NL.ftl` is Dutch as spoken in the
+/// Netherlands.
+pub fn localization_loader_system(world: &mut World) {
+    let mut languages = HashMap::new();
+    let mut handles = HashMap::new();
+    {
+        let assets = world.resource::<LocalizationAssets>();
+        let files = world.resource::<Assets<LanguageFile>>();
+        let asset_server = world.resource::<AssetServer>();
+
+        for handle in assets.files.iter() {
+            let file = match files.get(handle) {
+                Some(file) => file,
+                // Not a language file.
+                None => continue,
+            };
+            let maybe_language = asset_server
+                .get_handle_path(handle)
+                .and_then(|path| language_of_file(path.path()));
+            let language = match maybe_language {
+                Some(language) => language,
+                None => {
+                    warn!(
+                        "File {:?} in `{}` is not named after a language, like `en-US.ftl`",
+                        asset_server.get_handle_path(handle),
+                        LOCALIZATION_ASSETS_PATH
+                    );
+                    continue;
+                }
+            };
+
+            match create_bundle(language.clone(), file.0.clone()) {
+                Ok(bundle) => {
+                    if language == DEFAULT_LANGUAGE && PSEUDO_LANGUAGE_AVAILABLE {
+                        if let Ok(pseudo_bundle) = create_pseudo_bundle(file.0.clone()) {
+                            languages.insert(PSEUDO_LANGUAGE, pseudo_bundle);
+                        }
+                    }
+                    handles.insert(handle.id, language.clone());
+                    languages.insert(language, bundle);
+                }
+                Err(e) => warn!("Language {} will not be available: {}", language, e),
             }
         }
     }
@@ -144,42 +402,275 @@ pub fn localization_loader_system(
         panic!("Default language {} is not available.", DEFAULT_LANGUAGE);
     }
 
-    commands.insert_resource(Localizer {
-        languages,
-        current_language: DEFAULT_LANGUAGE,
-    });
+    world.insert_resource(Localizer::new(languages));
+    world.insert_resource(LanguageFileHandles(handles));
+}
+
+/// Replaces the messages of a language when its file changes on disk.
+/// Only happens when the asset server watches for changes, see `main.rs`.
+fn localization_reload_system(
+    mut events: EventReader<AssetEvent<LanguageFile>>,
+    files: Res<Assets<LanguageFile>>,
+    maybe_handles: Option<Res<LanguageFileHandles>>,
+    maybe_localizer: Option<ResMut<Localizer>>,
+) {
+    let (handles, mut localizer) = match (maybe_handles, maybe_localizer) {
+        (Some(handles), Some(localizer)) => (handles, localizer),
+        // Still loading.
+        _ => return,
+    };
+
+    for event in events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            let (language, file) = match (handles.0.get(&handle.id), files.get(handle)) {
+                (Some(language), Some(file)) => (language, file),
+                _ => continue,
+            };
+            match create_bundle(language.clone(), file.0.clone()) {
+                Ok(bundle) => {
+                    info!("Reloaded language {}", language);
+                    if *language == DEFAULT_LANGUAGE && PSEUDO_LANGUAGE_AVAILABLE {
+                        if let Ok(pseudo_bundle) = create_pseudo_bundle(file.0.clone()) {
+                            localizer.replace_language(PSEUDO_LANGUAGE, pseudo_bundle);
+                        }
+                    }
+                    localizer.replace_language(language.clone(), bundle);
+                }
+                Err(e) => warn!("Could not reload language {}: {}", language, e),
+            }
+        }
+    }
+}
+
+/// Sends a [LanguageChangedEvent] when the [Localizer] has been changed this frame.
+fn language_changed_event_system(
+    maybe_localizer: Option<ResMut<Localizer>>,
+    mut events: EventWriter<LanguageChangedEvent>,
+) {
+    if let Some(mut localizer) = maybe_localizer {
+        if localizer.language_changed {
+            localizer.language_changed = false;
+            events.send(LanguageChangedEvent {
+                language: localizer.current_language(),
+            });
+        }
+    }
 }
 
-fn load_language_file(
-    localization_folder: &str,
-    lang_id_string: &str,
-) -> Result<
-    (
-        LanguageIdentifier,
-        FluentBundle<Arc<FluentResource>, IntlLangMemoizer>,
-    ),
-    String,
-> {
-    let language_file_path =
-        localization_folder.to_owned() + "/" + lang_id_string + FLUENT_FILE_EXTENSION;
+fn localization_cache_invalidation_system(
+    mut events: EventReader<LanguageChangedEvent>,
+    mut cache: ResMut<LocalizationCache>,
+) {
+    if events.iter().count() > 0 {
+        cache.clear();
+    }
+}
 
-    let fluent_content = fs::read_to_string(language_file_path).map_err(|e| e.to_string())?;
+/// `nl-NL.ftl` contains the language `nl-NL`.
+fn language_of_file(path: &Path) -> Option<LanguageIdentifier> {
+    path.file_stem()?.to_str()?.parse().ok()
+}
 
-    let resource = FluentResource::try_new(fluent_content).map_err(|(_, errors)| {
+fn parse_fluent_resource(fluent_content: String) -> Result<FluentResource, String> {
+    FluentResource::try_new(fluent_content).map_err(|(_, errors)| {
         errors
             .iter()
             .enumerate()
             // TODO (Wybe 2022-06-06): Clean up this error reporting?
             .map(|(i, err)| format!("\n{}: {:?}", i, err))
             .collect::<String>()
-    })?;
+    })
+}
+
+/// A number that is shown with exactly this many digits after the decimal separator.
+/// Also used by Fluent to pick the plural category, so `1.0` is plural in English.
+pub fn decimal_number(value: f32, fraction_digits: usize) -> FluentValue<'static> {
+    let factor = 10f64.powi(fraction_digits as i32);
+    FluentValue::Number(FluentNumber::new(
+        (value as f64 * factor).round() / factor,
+        FluentNumberOptions {
+            // Fluent would write `3.` for 0 digits.
+            minimum_fraction_digits: if fraction_digits > 0 {
+                Some(fraction_digits)
+            } else {
+                None
+            },
+            ..Default::default()
+        },
+    ))
+}
+
+/// Makes text look like a translation, while it can still be read by those who know the
+/// [DEFAULT_LANGUAGE]. Letters get accents and the text is padded by about 40%, because text in
+/// many languages is longer than in English. Used for the [PSEUDO_LANGUAGE], where the
+/// [Localizer] also puts brackets around every message, so text that is cut off stands out.
+fn pseudo_transform(text: &str) -> Cow<'_, str> {
+    let trimmed = text.trim_end();
+    if trimmed.trim_start().is_empty() {
+        return Cow::Borrowed(text);
+    }
+
+    let mut result = String::new();
+    for letter in trimmed.chars() {
+        let accented = match letter {
+            'a' => 'å',
+            'c' => 'ç',
+            'd' => 'ď',
+            'e' => 'ḗ',
+            'g' => 'ğ',
+            'h' => 'ĥ',
+            'i' => 'ï',
+            'j' => 'ĵ',
+            'k' => 'ķ',
+            'l' => 'ľ',
+            'n' => 'ñ',
+            'o' => 'ö',
+            'r' => 'ř',
+            's' => 'š',
+            't' => 'ť',
+            'u' => 'ü',
+            'w' => 'ŵ',
+            'y' => 'ý',
+            'z' => 'ž',
+            'A' => 'Å',
+            'C' => 'Ç',
+            'D' => 'Ď',
+            'E' => 'É',
+            'G' => 'Ğ',
+            'H' => 'Ĥ',
+            'I' => 'Ï',
+            'J' => 'Ĵ',
+            'K' => 'Ķ',
+            'L' => 'Ľ',
+            'N' => 'Ñ',
+            'O' => 'Ö',
+            'R' => 'Ř',
+            'S' => 'Š',
+            'T' => 'Ť',
+            'U' => 'Ü',
+            'W' => 'Ŵ',
+            'Y' => 'Ý',
+            'Z' => 'Ž',
+            other => other,
+        };
+        result.push(accented);
+    }
+    let padding = (trimmed.chars().count() * 2 + 2) / 5;
+    result.extend(std::iter::repeat(PSEUDO_LOCALIZATION_PADDING).take(padding));
+    // Keeps the space in front of arguments, like in `Day {$day}`.
+    result.push_str(&text[trimmed.len()..]);
+    Cow::Owned(result)
+}
+
+/// What the [Localizer] makes of a message without arguments in the [PSEUDO_LANGUAGE].
+#[cfg(test)]
+pub(crate) fn pseudo_localize(text: &str) -> String {
+    format!("[{}]", pseudo_transform(text))
+}
+
+/// The messages of the [DEFAULT_LANGUAGE], pseudo localized when they are formatted.
+/// Uses the plural rules and number formatting of the default language.
+fn create_pseudo_bundle(resource: Arc<FluentResource>) -> Result<Bundle, String> {
+    let mut bundle = create_bundle(DEFAULT_LANGUAGE, resource)?;
+    bundle.set_transform(Some(pseudo_transform));
+    Ok(bundle)
+}
+
+/// Copies the value, so it no longer borrows from the arguments it was given in.
+fn owned_value(value: &FluentValue<'_>) -> FluentValue<'static> {
+    match value {
+        FluentValue::String(text) => FluentValue::String(Cow::Owned(text.to_string())),
+        FluentValue::Number(number) => FluentValue::Number(number.clone()),
+        FluentValue::Custom(custom) => FluentValue::Custom(custom.duplicate()),
+        FluentValue::None => FluentValue::None,
+        FluentValue::Error => FluentValue::Error,
+    }
+}
+
+/// How a language writes numbers.
+///
+/// TODO (Wybe 2022-06-20): This is a stopgap. fluent-rs only applies the fraction digits of the
+/// [FluentNumberOptions], and writes every number like `1234.5`, whatever the language. Only
+/// [format_number] uses this, so it is the one place to change once Fluent formats by locale.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct NumberFormat {
+    decimal_separator: char,
+    grouping_separator: char,
+    /// Numbers with fewer digits before the decimal separator are not grouped.
+    minimum_grouping_length: usize,
+}
 
-    let id = lang_id_string
-        .parse::<LanguageIdentifier>()
-        .expect("Parsing language identifier failed");
+impl NumberFormat {
+    /// Languages that are not listed get the English format.
+    fn of_language(language: &LanguageIdentifier) -> Self {
+        let (decimal_separator, grouping_separator, minimum_grouping_length) =
+            match language.language.as_str() {
+                "de" | "it" | "nl" | "pt" => (',', '.', 4),
+                "es" => (',', '.', 5),
+                "fr" => (',', '\u{202F}', 4),
+                "ru" => (',', '\u{A0}', 4),
+                "pl" => (',', '\u{A0}', 5),
+                _ => ('.', ',', 4),
+            };
+        NumberFormat {
+            decimal_separator,
+            grouping_separator,
+            minimum_grouping_length,
+        }
+    }
+
+    fn format(&self, number: &FluentNumber) -> String {
+        // Already has the minimum fraction digits.
+        let text = number.as_string();
+        let (sign, unsigned) = match text.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", &*text),
+        };
+        let (integer, fraction) = match unsigned.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (unsigned, None),
+        };
+
+        let mut result = sign.to_string();
+        let grouped = number.options.use_grouping && integer.len() >= self.minimum_grouping_length;
+        for (i, digit) in integer.chars().enumerate() {
+            if grouped && i > 0 && (integer.len() - i) % 3 == 0 {
+                result.push(self.grouping_separator);
+            }
+            result.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            result.push(self.decimal_separator);
+            result.push_str(fraction);
+        }
+        result
+    }
+}
 
-    let mut bundle = FluentBundle::new_concurrent(vec![id.clone()]);
-    bundle.add_resource(Arc::new(resource)).map_err(|errors| {
+/// Lets the memoizer of a bundle keep the [NumberFormat] of its language.
+impl Memoizable for NumberFormat {
+    type Args = ();
+    type Error = ();
+
+    fn construct(language: LanguageIdentifier, _args: ()) -> Result<Self, ()> {
+        Ok(NumberFormat::of_language(&language))
+    }
+}
+
+/// Writes numbers in the [NumberFormat] of the bundle's language.
+fn format_number(value: &FluentValue<'_>, memoizer: &IntlLangMemoizer) -> Option<String> {
+    match value {
+        FluentValue::Number(number) => memoizer
+            .with_try_get::<NumberFormat, _, _>((), |format| format.format(number))
+            .ok(),
+        _ => None,
+    }
+}
+
+fn create_bundle(id: LanguageIdentifier, resource: Arc<FluentResource>) -> Result<Bundle, String> {
+    let mut bundle = FluentBundle::new_concurrent(vec![id]);
+    bundle.set_formatter(Some(format_number));
+    bundle.add_resource(resource).map_err(|errors| {
         errors
             .iter()
             .enumerate()
@@ -187,5 +678,533 @@ fn load_language_file(
             .collect::<String>()
     })?;
 
-    Ok((id, bundle))
+    Ok(bundle)
+}
+
+/// Something that is wrong with the localization files.
+#[derive(Debug, PartialEq)]
+pub enum LocalizationProblem {
+    /// A message that the [DEFAULT_LANGUAGE] has, or that the game needs, is missing.
+    MissingMessage {
+        language: LanguageIdentifier,
+        id: String,
+    },
+    /// A message that the [DEFAULT_LANGUAGE] does not have. Probably an old or misspelled id.
+    ExtraMessage {
+        language: LanguageIdentifier,
+        id: String,
+    },
+    /// The message uses different variables (like `{$seconds}`) than the one in the
+    /// [DEFAULT_LANGUAGE], so it either shows less information, or variables that are never set.
+    MismatchedVariables {
+        language: LanguageIdentifier,
+        id: String,
+        expected: BTreeSet<String>,
+        found: BTreeSet<String>,
+    },
+}
+
+impl fmt::Display for LocalizationProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalizationProblem::MissingMessage { language, id } => {
+                write!(f, "{}: missing message `{}`", language, id)
+            }
+            LocalizationProblem::ExtraMessage { language, id } => {
+                write!(
+                    f,
+                    "{}: message `{}` is not in {}",
+                    language, id, DEFAULT_LANGUAGE
+                )
+            }
+            LocalizationProblem::MismatchedVariables {
+                language,
+                id,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}: message `{}` uses variables {:?}, but {} uses {:?}",
+                language, id, found, DEFAULT_LANGUAGE, expected
+            ),
+        }
+    }
+}
+
+/// Compares every language with the [DEFAULT_LANGUAGE], and checks that all languages have the
+/// `required_ids`, such as the titles of all card types.
+/// Terms (messages starting with `-`) are left out, because they are only used by the other
+/// messages of the same language.
+pub fn check_localizations(
+    resources: &[(LanguageIdentifier, FluentResource)],
+    required_ids: &[String],
+) -> Vec<LocalizationProblem> {
+    let messages: Vec<(&LanguageIdentifier, BTreeMap<String, BTreeSet<String>>)> = resources
+        .iter()
+        .map(|(language, resource)| (language, message_variables(resource)))
+        .collect();
+    let default_messages = messages
+        .iter()
+        .find(|(language, _)| **language == DEFAULT_LANGUAGE)
+        .map(|(_, messages)| messages.clone())
+        .unwrap_or_default();
+
+    let mut problems = vec![];
+    for (language, language_messages) in messages.iter() {
+        let missing_ids: BTreeSet<&String> = default_messages
+            .keys()
+            .chain(required_ids.iter())
+            .filter(|id| !language_messages.contains_key(*id))
+            .collect();
+        for id in missing_ids {
+            problems.push(LocalizationProblem::MissingMessage {
+                language: (*language).clone(),
+                id: id.clone(),
+            });
+        }
+
+        for (id, found) in language_messages.iter() {
+            match default_messages.get(id) {
+                None => problems.push(LocalizationProblem::ExtraMessage {
+                    language: (*language).clone(),
+                    id: id.clone(),
+                }),
+                Some(expected) if expected != found => {
+                    problems.push(LocalizationProblem::MismatchedVariables {
+                        language: (*language).clone(),
+                        id: id.clone(),
+                        expected: expected.clone(),
+                        found: found.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    problems
+}
+
+/// The id of every message in the resource, with the variables it uses.
+fn message_variables(resource: &FluentResource) -> BTreeMap<String, BTreeSet<String>> {
+    resource
+        .entries()
+        .filter_map(|entry| match entry {
+            ast::Entry::Message(message) => {
+                let mut variables = BTreeSet::new();
+                let patterns = message
+                    .value
+                    .iter()
+                    .chain(message.attributes.iter().map(|attribute| &attribute.value));
+                for pattern in patterns {
+                    add_pattern_variables(pattern, &mut variables);
+                }
+                Some((message.id.name.to_string(), variables))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn add_pattern_variables(pattern: &ast::Pattern<&str>, variables: &mut BTreeSet<String>) {
+    for element in pattern.elements.iter() {
+        if let ast::PatternElement::Placeable { expression } = element {
+            add_expression_variables(expression, variables);
+        }
+    }
+}
+
+fn add_expression_variables(expression: &ast::Expression<&str>, variables: &mut BTreeSet<String>) {
+    match expression {
+        ast::Expression::Select { selector, variants } => {
+            add_inline_expression_variables(selector, variables);
+            for variant in variants {
+                add_pattern_variables(&variant.value, variables);
+            }
+        }
+        ast::Expression::Inline(inline) => add_inline_expression_variables(inline, variables),
+    }
+}
+
+fn add_inline_expression_variables(
+    expression: &ast::InlineExpression<&str>,
+    variables: &mut BTreeSet<String>,
+) {
+    match expression {
+        ast::InlineExpression::VariableReference { id } => {
+            variables.insert(id.name.to_string());
+        }
+        ast::InlineExpression::FunctionReference { arguments, .. } => {
+            for argument in arguments
+                .positional
+                .iter()
+                .chain(arguments.named.iter().map(|named| &named.value))
+            {
+                add_inline_expression_variables(argument, variables);
+            }
+        }
+        ast::InlineExpression::Placeable { expression } => {
+            add_expression_variables(expression, variables)
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::card_types::CardTypeDefinitions;
+    use crate::localization::{
+        check_localizations, create_bundle, create_pseudo_bundle, decimal_number,
+        language_changed_event_system, language_of_file, localization_cache_invalidation_system,
+        parse_fluent_resource, pseudo_localize, LanguageChangedEvent, LocalizationCache,
+        LocalizationProblem, Localizer, NumberFormat, DEFAULT_LANGUAGE, FLUENT_FILE_EXTENSION,
+        LOCALIZATION_ASSETS_PATH, PSEUDO_LANGUAGE,
+    };
+    use crate::recipe::{base_recipes, RECIPE_TITLE_LOCALIZATION_PREFIX};
+    use crate::stack::stack_utils::{
+        CARD_DESCRIPTION_LOCALIZATION_PREFIX, CARD_TITLE_LOCALIZATION_PREFIX,
+    };
+    use bevy::prelude::*;
+    use fluent::types::{FluentNumber, FluentNumberOptions};
+    use fluent::{FluentResource, FluentValue};
+    use std::collections::{BTreeSet, HashMap};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use unic_langid::{langid, LanguageIdentifier};
+
+    fn resource(text: &str) -> FluentResource {
+        parse_fluent_resource(text.to_string()).unwrap()
+    }
+
+    fn localizer(languages: &[(LanguageIdentifier, &str)]) -> Localizer {
+        Localizer::new(
+            languages
+                .iter()
+                .map(|(language, text)| {
+                    let bundle = create_bundle(language.clone(), Arc::new(resource(text))).unwrap();
+                    (language.clone(), bundle)
+                })
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    /// Ids of the messages that the card types and recipes of the base game need.
+    fn required_message_ids() -> Vec<String> {
+        let card_types: CardTypeDefinitions =
+            ron::from_str(include_str!("../assets/cards/base.cards.ron")).unwrap();
+        let mut ids: Vec<String> = card_types
+            .0
+            .iter()
+            .flat_map(|definition| {
+                [
+                    CARD_TITLE_LOCALIZATION_PREFIX.to_string() + &definition.id,
+                    CARD_DESCRIPTION_LOCALIZATION_PREFIX.to_string() + &definition.id,
+                ]
+            })
+            .collect();
+
+        ids.extend(
+            base_recipes(&mut World::default())
+                .in_matching_order()
+                .iter()
+                .map(|(id, _)| RECIPE_TITLE_LOCALIZATION_PREFIX.to_string() + id.0),
+        );
+
+        ids
+    }
+
+    #[test]
+    fn test_localization_files_are_complete() {
+        let folder = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(LOCALIZATION_ASSETS_PATH);
+        let mut resources = vec![];
+        for entry in fs::read_dir(&folder).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|extension| extension.to_str())
+                == Some(FLUENT_FILE_EXTENSION)
+            {
+                let text = fs::read_to_string(&path).unwrap();
+                let resource = parse_fluent_resource(text)
+                    .unwrap_or_else(|e| panic!("Could not parse `{}`: {}", path.display(), e));
+                let language = language_of_file(&path).unwrap_or_else(|| {
+                    panic!("`{}` is not named after a language", path.display())
+                });
+                resources.push((language, resource));
+            }
+        }
+        assert!(resources
+            .iter()
+            .any(|(language, _)| *language == DEFAULT_LANGUAGE));
+
+        let problems = check_localizations(&resources, &required_message_ids());
+        let report: Vec<String> = problems.iter().map(|problem| problem.to_string()).collect();
+        assert!(problems.is_empty(), "\n{}", report.join("\n"));
+    }
+
+    #[test]
+    fn test_check_localizations() {
+        let resources = vec![
+            (
+                DEFAULT_LANGUAGE,
+                resource("a = A\nb = B {$count}\n-term = T\n"),
+            ),
+            (
+                langid!("nl-NL"),
+                resource("b = B {$amount}\nc = C\n-other-term = T\n"),
+            ),
+        ];
+
+        let problems = check_localizations(&resources, &["a".to_string(), "d".to_string()]);
+        assert_eq!(
+            problems,
+            vec![
+                LocalizationProblem::MissingMessage {
+                    language: DEFAULT_LANGUAGE,
+                    id: "d".to_string()
+                },
+                LocalizationProblem::MissingMessage {
+                    language: langid!("nl-NL"),
+                    id: "a".to_string()
+                },
+                LocalizationProblem::MissingMessage {
+                    language: langid!("nl-NL"),
+                    id: "d".to_string()
+                },
+                LocalizationProblem::MismatchedVariables {
+                    language: langid!("nl-NL"),
+                    id: "b".to_string(),
+                    expected: BTreeSet::from(["count".to_string()]),
+                    found: BTreeSet::from(["amount".to_string()]),
+                },
+                LocalizationProblem::ExtraMessage {
+                    language: langid!("nl-NL"),
+                    id: "c".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_localization_fallback() {
+        let mut localizer = localizer(&[
+            (DEFAULT_LANGUAGE, "a = A\nb = B\nc = C\n"),
+            (langid!("nl"), "a = nl A\nb = nl B\n"),
+            (langid!("nl-NL"), "a = nl-NL A\n"),
+        ]);
+
+        localizer.select_language(langid!("nl-NL"));
+        assert_eq!(localizer.localize("a"), "nl-NL A");
+        assert_eq!(localizer.localize("b"), "nl B");
+        assert_eq!(localizer.localize("c"), "C");
+        assert_eq!(localizer.localize("d"), "d");
+
+        // Not available, so the closest variant is picked.
+        localizer.select_language(langid!("nl-BE"));
+        assert_eq!(localizer.current_language(), langid!("nl"));
+        assert_eq!(localizer.localize("a"), "nl A");
+
+        localizer.select_language(langid!("fr-FR"));
+        assert_eq!(localizer.current_language(), langid!("nl"));
+    }
+
+    #[test]
+    fn test_title_fonts() {
+        let mut localizer = localizer(&[
+            (
+                DEFAULT_LANGUAGE,
+                "language_name = English\n    .title-fonts = fonts/a.otf\n",
+            ),
+            (
+                langid!("ja"),
+                "language_name = 日本語\n    .title-fonts = fonts/b.otf, fonts/a.otf\n",
+            ),
+            (langid!("nl-NL"), "language_name = Nederlands\n"),
+        ]);
+
+        assert_eq!(localizer.title_fonts(), ["fonts/a.otf"]);
+
+        localizer.select_language(langid!("ja"));
+        assert_eq!(localizer.title_fonts(), ["fonts/b.otf", "fonts/a.otf"]);
+
+        // Without fonts of its own, the fonts of the default language are used.
+        localizer.select_language(langid!("nl-NL"));
+        assert_eq!(localizer.title_fonts(), ["fonts/a.otf"]);
+    }
+
+    #[test]
+    fn test_pseudo_language() {
+        let resource = Arc::new(resource(
+            "tree = Tree\nday = Day {$day}\nseconds = { $seconds ->\n [one] {$seconds} second\n *[other] {$seconds} seconds\n}\n",
+        ));
+        let mut localizer = Localizer::new(HashMap::from([
+            (
+                DEFAULT_LANGUAGE,
+                create_bundle(DEFAULT_LANGUAGE, resource.clone()).unwrap(),
+            ),
+            (PSEUDO_LANGUAGE, create_pseudo_bundle(resource).unwrap()),
+        ]));
+        localizer.select_language(PSEUDO_LANGUAGE);
+
+        assert_eq!(localizer.localize("tree"), "[Ťřḗḗ~~]");
+        assert_eq!(localizer.localize("tree"), pseudo_localize("Tree"));
+        assert_eq!(
+            localizer.localize_with_args("day", &[("day", 3.into())]),
+            "[Ďåý~ \u{2068}3\u{2069}]"
+        );
+        // Plural rules of the default language.
+        assert_eq!(
+            localizer.localize_with_args("seconds", &[("seconds", 1.into())]),
+            "[\u{2068}1\u{2069} šḗçöñď~~~]"
+        );
+    }
+
+    #[test]
+    fn test_language_options() {
+        let localizer = localizer(&[
+            (DEFAULT_LANGUAGE, "language_name = English\n"),
+            (langid!("nl-NL"), ""),
+        ]);
+
+        let options = localizer.language_options();
+        assert_eq!(options[&DEFAULT_LANGUAGE], "English");
+        // Falls back on the language id when the name is missing.
+        assert_eq!(options[&langid!("nl-NL")], "nl-NL");
+    }
+
+    #[test]
+    fn test_plurals_and_numbers() {
+        let mut localizer = localizer(&[
+            (
+                DEFAULT_LANGUAGE,
+                "seconds = { $seconds ->\n [one] {$seconds} second\n *[other] {$seconds} seconds\n}",
+            ),
+            (
+                langid!("nl-NL"),
+                "seconds = { $seconds ->\n [one] {$seconds} seconde\n *[other] {$seconds} seconden\n}",
+            ),
+        ]);
+        let localize = |localizer: &Localizer, value: FluentValue<'static>| {
+            localizer
+                .localize_with_args("seconds", &[("seconds", value)])
+                .replace(['\u{2068}', '\u{2069}'], "")
+        };
+
+        assert_eq!(localize(&localizer, 1.into()), "1 second");
+        assert_eq!(localize(&localizer, 3.into()), "3 seconds");
+        assert_eq!(
+            localize(&localizer, decimal_number(2.345, 1)),
+            "2.3 seconds"
+        );
+        assert_eq!(localize(&localizer, decimal_number(2.6, 0)), "3 seconds");
+        // Fraction digits make it plural.
+        assert_eq!(localize(&localizer, decimal_number(1.0, 1)), "1.0 seconds");
+        assert_eq!(localize(&localizer, "many".into()), "many seconds");
+
+        localizer.select_language(langid!("nl-NL"));
+        assert_eq!(localize(&localizer, 1.into()), "1 seconde");
+        assert_eq!(localize(&localizer, decimal_number(2.5, 1)), "2,5 seconden");
+        assert_eq!(
+            localize(&localizer, decimal_number(1234.5, 1)),
+            "1.234,5 seconden"
+        );
+    }
+
+    #[test]
+    fn test_number_grouping() {
+        let format = |language: LanguageIdentifier, value: f64| {
+            NumberFormat::of_language(&language).format(&FluentNumber::new(value, default()))
+        };
+
+        assert_eq!(format(langid!("en-US"), 1234.5), "1,234.5");
+        assert_eq!(format(langid!("en-US"), -1234567.0), "-1,234,567");
+        assert_eq!(format(langid!("en-US"), 123.0), "123");
+        assert_eq!(format(langid!("nl-NL"), 1234.5), "1.234,5");
+        assert_eq!(format(langid!("de"), 1234567.25), "1.234.567,25");
+        assert_eq!(format(langid!("fr-FR"), 1234.5), "1\u{202F}234,5");
+        // Spanish only groups from five digits on.
+        assert_eq!(format(langid!("es-ES"), 1234.5), "1234,5");
+        assert_eq!(format(langid!("es-ES"), 12345.0), "12.345");
+        // Unknown languages are written like English.
+        assert_eq!(format(langid!("ja"), 1234.5), "1,234.5");
+
+        let without_grouping = FluentNumber::new(
+            1234.0,
+            FluentNumberOptions {
+                use_grouping: false,
+                minimum_fraction_digits: Some(2),
+                ..default()
+            },
+        );
+        assert_eq!(
+            NumberFormat::of_language(&langid!("nl-NL")).format(&without_grouping),
+            "1234,00"
+        );
+    }
+
+    #[test]
+    fn test_language_change_clears_cache() {
+        let mut world = World::default();
+        let mut post_update_stage =
+            SystemStage::parallel().with_system(language_changed_event_system);
+        let mut pre_update_stage =
+            SystemStage::parallel().with_system(localization_cache_invalidation_system);
+
+        world.insert_resource(localizer(&[
+            (DEFAULT_LANGUAGE, "a = A\nb = B {$count}\n"),
+            (langid!("nl-NL"), "a = nl A\nb = nl B {$count}\n"),
+        ]));
+        world.insert_resource(Events::<LanguageChangedEvent>::default());
+        world.insert_resource(LocalizationCache::default());
+
+        world.resource_scope(|world, mut cache: Mut<LocalizationCache>| {
+            let localizer = world.resource::<Localizer>();
+            assert_eq!(cache.localize(localizer, "a"), "A");
+            assert_eq!(
+                cache.localize_with_args(localizer, "b", &[("count", 1.into())]),
+                "B \u{2068}1\u{2069}"
+            );
+            // Different arguments are localized again.
+            assert_eq!(
+                cache.localize_with_args(localizer, "b", &[("count", 2.into())]),
+                "B \u{2068}2\u{2069}"
+            );
+        });
+
+        post_update_stage.run(&mut world);
+        assert!(world
+            .resource_mut::<Events<LanguageChangedEvent>>()
+            .drain()
+            .next()
+            .is_none());
+
+        world
+            .resource_mut::<Localizer>()
+            .select_language(langid!("nl-NL"));
+        post_update_stage.run(&mut world);
+        let events: Vec<LanguageChangedEvent> = world
+            .resource_mut::<Events<LanguageChangedEvent>>()
+            .drain()
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].language, langid!("nl-NL"));
+
+        // Only sent once per change.
+        post_update_stage.run(&mut world);
+        assert!(world
+            .resource_mut::<Events<LanguageChangedEvent>>()
+            .drain()
+            .next()
+            .is_none());
+
+        world
+            .resource_mut::<Events<LanguageChangedEvent>>()
+            .send(LanguageChangedEvent {
+                language: langid!("nl-NL"),
+            });
+        pre_update_stage.run(&mut world);
+        world.resource_scope(|world, mut cache: Mut<LocalizationCache>| {
+            assert_eq!(cache.localize(world.resource::<Localizer>(), "a"), "nl A");
+        });
+    }
 }
@@ -1,10 +1,41 @@
+//! Usage: `the_stacks [--seed <seed>]`
+//!
+//! The seed can be a number, or any other text.
+
+use bevy::asset::AssetServerSettings;
 use bevy::prelude::App;
 use bevy::DefaultPlugins;
+use std::process::exit;
+use the_stacks::procedural::seed_from_text;
 use the_stacks::TheStacksPlugin;
 
 fn main() {
+    let mut seed = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => match args.next() {
+                Some(value) => seed = Some(seed_from_text(&value)),
+                None => fail("`--seed` needs a value"),
+            },
+            _ => fail(&format!("Unknown argument `{}`", arg)),
+        }
+    }
+
     App::new()
+        // Lets designers see changes to the assets, such as the language files, while playing.
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: cfg!(debug_assertions),
+            ..Default::default()
+        })
         .add_plugins(DefaultPlugins)
-        .add_plugin(TheStacksPlugin)
+        .add_plugin(TheStacksPlugin { seed })
         .run();
 }
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("Usage: the_stacks [--seed <seed>]");
+    exit(1);
+}
@@ -1,40 +1,131 @@
 use bevy::prelude::*;
-use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
 
 pub struct ProceduralPlugin;
 
 impl Plugin for ProceduralPlugin {
     fn build(&self, app: &mut App) {
-        // TODO (Wybe 2022-06-05): generate a seed.
+        // Replaced when the player starts a new game, or loads one.
         app.insert_resource(SeededHasherResource::new(42));
     }
 }
 
+/// Turns what the player typed as a seed into a seed.
+/// Numbers are used as they are, so the seed shown in the game can be typed back in.
+/// Any other text is hashed, so players can also share words as seeds.
+pub fn seed_from_text(text: &str) -> u32 {
+    let text = text.trim();
+    text.parse().unwrap_or_else(|_| {
+        let mut hasher = StableHasher::default();
+        text.hash(&mut hasher);
+        hasher.finish() as u32
+    })
+}
+
+/// A seed for when the player did not pick one. Based on the current time.
+pub fn random_seed() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+    let mut hasher = StableHasher::default();
+    nanos.hash(&mut hasher);
+    hasher.finish() as u32
+}
+
+/// 64 bit FNV-1a hasher.
+/// Unlike the standard library's `DefaultHasher`, its output is specified, so it stays the same
+/// across Rust versions and platforms. Numbers are always hashed as little endian, and `usize`
+/// as 64 bits.
+#[derive(Clone)]
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// SplitMix64, used to turn a single hash into the state of a [HashedRng].
+fn split_mix_64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 /// Resource providing pre-seeded hashers for procedural generation.
-/// TODO (Wybe 2022-06-05): Is the default hasher good for semi-random generation?
-pub struct SeededHasherResource(DefaultHasher);
+pub struct SeededHasherResource {
+    seed: u32,
+    hasher: StableHasher,
+}
 
 impl SeededHasherResource {
     pub fn new(seed: u32) -> Self {
-        let mut hasher = DefaultHasher::new();
+        let mut hasher = StableHasher::default();
         seed.hash(&mut hasher);
-        SeededHasherResource(hasher)
+        SeededHasherResource { seed, hasher }
+    }
+
+    /// The seed this resource was created with.
+    pub fn seed(&self) -> u32 {
+        self.seed
     }
 
     pub fn with<T>(&self, value: T) -> HashedRng
     where
         T: Hash,
     {
-        let mut hasher = self.0.clone();
+        let mut hasher = self.hasher.clone();
         value.hash(&mut hasher);
 
-        HashedRng(hasher)
+        HashedRng {
+            hasher,
+            state: None,
+        }
     }
 }
 
-/// A pseudo-random number generator that is based purely on hashing.
+/// A pseudo-random number generator that is seeded by hashing.
 /// It is pre-seeded by getting one from the [SeededHasherResource].
 /// This means that each time a new HashedRng is created, and provided with the same arguments,
 /// it will provide the same "random" values.
@@ -42,6 +133,9 @@ impl SeededHasherResource {
 /// module of the code gets called before, and which one after, because each module will
 /// have its own pre-seeded `HashedRng`.
 ///
+/// The values come from a xoshiro256** generator, seeded with a FNV-1a hash of the arguments.
+/// Both algorithms are fixed, so a seed gives the same game with every version of Rust.
+///
 /// ```
 /// # use crate::the_stacks::procedural::SeededHasherResource;
 ///
@@ -57,27 +151,217 @@ impl SeededHasherResource {
 ///
 /// assert_ne!(value1, value2);
 /// ```
-pub struct HashedRng(DefaultHasher);
+#[derive(Clone)]
+pub struct HashedRng {
+    hasher: StableHasher,
+    /// xoshiro256** state. Created from the hash when the first value is requested.
+    state: Option<[u64; 4]>,
+}
 
 impl HashedRng {
+    /// Hashes another value into the generator.
+    /// This restarts the sequence of values, based on everything hashed so far.
     pub fn with<T>(&mut self, value: T)
     where
         T: Hash,
     {
-        value.hash(&mut self.0);
+        value.hash(&mut self.hasher);
+        self.state = None;
     }
 
     pub fn value(&mut self) -> u64 {
-        let value = self.0.finish();
-        // Advance the hasher, so the next call to this function will have a completely different value.
-        // TODO (Wybe 2022-06-05): is this an appropriate way to do hashed "random" number generation?
-        1.hash(&mut self.0);
+        let hasher = &self.hasher;
+        let s = self.state.get_or_insert_with(|| {
+            let mut seed = hasher.finish();
+            [
+                split_mix_64(&mut seed),
+                split_mix_64(&mut seed),
+                split_mix_64(&mut seed),
+                split_mix_64(&mut seed),
+            ]
+        });
 
-        value
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        result
     }
 
+    /// Every value in the range is equally likely.
+    /// Panics when the range is empty.
     pub fn value_in_range(&mut self, range: Range<usize>) -> usize {
-        let range_size = range.end - range.start;
-        range.start + self.value() as usize % range_size
+        assert!(!range.is_empty(), "Cannot pick a value in an empty range");
+        range.start + self.value_below((range.end - range.start) as u64) as usize
+    }
+
+    /// Uniformly distributed value in `0..bound`, using Lemire's method.
+    /// Values that would make some results more likely than others are thrown away.
+    fn value_below(&mut self, bound: u64) -> u64 {
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let product = self.value() as u128 * bound as u128;
+            if product as u64 >= threshold {
+                return (product >> 64) as u64;
+            }
+        }
+    }
+
+    /// Value in `0.0..1.0`.
+    pub fn value_f32(&mut self) -> f32 {
+        // An f32 has 24 bits of precision.
+        (self.value() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn value_in_range_f32(&mut self, range: Range<f32>) -> f32 {
+        range.start + (range.end - range.start) * self.value_f32()
+    }
+
+    /// Picks one of the items, where the chance of each item is its weight divided by the total
+    /// weight. Returns `None` when the total weight is 0.
+    pub fn choose_weighted<'a, T>(
+        &mut self,
+        items: &'a [T],
+        weight: impl Fn(&T) -> usize,
+    ) -> Option<&'a T> {
+        let total_weight: usize = items.iter().map(&weight).sum();
+        if total_weight == 0 {
+            return None;
+        }
+
+        let mut roll = self.value_in_range(0..total_weight);
+        items.iter().find(|item| {
+            let item_weight = weight(*item);
+            if roll < item_weight {
+                true
+            } else {
+                roll -= item_weight;
+                false
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::procedural::{seed_from_text, SeededHasherResource, StableHasher};
+    use std::hash::Hasher;
+
+    /// Chi-squared value above which a uniform distribution with 9 degrees of freedom is rejected,
+    /// at a significance of 0.001.
+    const CHI_SQUARED_9_DEGREES: f64 = 27.88;
+
+    /// First values given by `SeededHasherResource::new(42).with(0)`.
+    const STABLE_VALUES: [u64; 3] = [
+        0x88b8_70f0_8de2_9dd4,
+        0xbf4d_6e40_9d77_91f7,
+        0x6cba_7de3_74cc_5b97,
+    ];
+
+    #[test]
+    fn test_stable_hasher() {
+        // Test vectors of 64 bit FNV-1a.
+        let mut hasher = StableHasher::default();
+        hasher.write(b"");
+        assert_eq!(hasher.finish(), 0xcbf2_9ce4_8422_2325);
+
+        let mut hasher = StableHasher::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let mut hasher = StableHasher::default();
+        hasher.write(b"foobar");
+        assert_eq!(hasher.finish(), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_values_are_stable() {
+        // If this fails, the same seed no longer gives the same game.
+        let mut rng = SeededHasherResource::new(42).with(0);
+        let values: Vec<u64> = (0..3).map(|_| rng.value()).collect();
+        assert_eq!(values, STABLE_VALUES);
+    }
+
+    #[test]
+    fn test_value_in_range_distribution() {
+        let mut rng = SeededHasherResource::new(42).with("distribution");
+        let samples = 100_000;
+        let mut counts = [0usize; 10];
+        for _ in 0..samples {
+            counts[rng.value_in_range(10..20) - 10] += 1;
+        }
+
+        let expected = samples as f64 / counts.len() as f64;
+        let chi_squared: f64 = counts
+            .iter()
+            .map(|&count| (count as f64 - expected).powi(2) / expected)
+            .sum();
+        assert!(chi_squared < CHI_SQUARED_9_DEGREES, "{:?}", counts);
+    }
+
+    #[test]
+    fn test_float_distribution() {
+        let mut rng = SeededHasherResource::new(42).with("float");
+        let samples = 100_000;
+        let mut counts = [0usize; 10];
+        for _ in 0..samples {
+            let value = rng.value_in_range_f32(-1.0..1.0);
+            assert!((-1.0..1.0).contains(&value));
+            counts[((value + 1.0) * 5.0) as usize] += 1;
+        }
+
+        let expected = samples as f64 / counts.len() as f64;
+        let chi_squared: f64 = counts
+            .iter()
+            .map(|&count| (count as f64 - expected).powi(2) / expected)
+            .sum();
+        assert!(chi_squared < CHI_SQUARED_9_DEGREES, "{:?}", counts);
+    }
+
+    #[test]
+    fn test_choose_weighted() {
+        let mut rng = SeededHasherResource::new(42).with("weighted");
+        let items = [("never", 0), ("rare", 1), ("common", 3)];
+
+        let samples = 40_000;
+        let mut rare = 0;
+        for _ in 0..samples {
+            match rng.choose_weighted(&items, |item| item.1) {
+                Some(("rare", _)) => rare += 1,
+                Some(("common", _)) => {}
+                other => panic!("Picked {:?}", other),
+            }
+        }
+        // Expected is 10_000, with a standard deviation of about 87.
+        assert!((9_500..10_500).contains(&rare), "{}", rare);
+
+        assert_eq!(rng.choose_weighted(&items[..1], |item| item.1), None);
+    }
+
+    #[test]
+    fn test_seed_from_text() {
+        assert_eq!(seed_from_text("1234"), 1234);
+        assert_eq!(seed_from_text(" 1234\n"), 1234);
+        assert_eq!(seed_from_text("forest"), seed_from_text("forest"));
+        assert_ne!(seed_from_text("forest"), seed_from_text("farm"));
+    }
+
+    #[test]
+    fn test_same_arguments_give_same_values() {
+        let hasher = SeededHasherResource::new(7);
+        let mut rng1 = hasher.with((3, "tree"));
+        let mut rng2 = hasher.with((3, "tree"));
+        let mut rng3 = hasher.with((4, "tree"));
+
+        let values1: Vec<u64> = (0..10).map(|_| rng1.value()).collect();
+        let values2: Vec<u64> = (0..10).map(|_| rng2.value()).collect();
+        let values3: Vec<u64> = (0..10).map(|_| rng3.value()).collect();
+        assert_eq!(values1, values2);
+        assert_ne!(values1, values3);
     }
 }
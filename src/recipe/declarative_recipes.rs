@@ -0,0 +1,643 @@
+use crate::card_types::{CardCategory, CardTypeAssets, CardTypeDefinitions, CardTypeId, CardTypes};
+use crate::procedural::{HashedRng, SeededHasherResource};
+use crate::recipe::{
+    CancelRecipeMarker, FinishRecipeMarker, RecipeAssets, RecipeUses, Recipes, RecipesBuilder,
+    RepeatPolicy, StackCheck, DEFAULT_RESUME_GRACE_SECONDS,
+};
+use crate::stack::stack_utils::delete_cards;
+use crate::stack::{Card, CardStack, CreateStackEvent};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// Extension of files containing [RecipeDefinitions].
+const RECIPES_FILE_EXTENSION: &str = "recipes.ron";
+
+/// A recipe described purely by data, as written in a `.recipes.ron` file.
+/// A stack is valid for the recipe when every card in it is matched by one of the `inputs`,
+/// and every input matches the right amount of cards.
+#[derive(Deserialize, Clone, Debug)]
+pub struct RecipeDefinition {
+    pub id: String,
+    /// Time the recipe takes, in seconds.
+    /// When `None` the recipe is instant.
+    #[serde(default)]
+    pub seconds: Option<f32>,
+    /// See [Recipe::priority](crate::recipe::Recipe::priority).
+    #[serde(default)]
+    pub priority: i32,
+    /// See [Recipe::repeat](crate::recipe::Recipe::repeat).
+    #[serde(default)]
+    pub repeat: RepeatPolicy,
+    pub inputs: Vec<RecipeInput>,
+    #[serde(default)]
+    pub outputs: Vec<RecipeOutput>,
+    /// When set, the outputs move onto a stack nearby that needs them to become valid for
+    /// a recipe, instead of onto a stack of the same cards.
+    #[serde(default)]
+    pub feed_outputs: bool,
+    /// Partial refund given when the recipe is cancelled, for example because the player split
+    /// the stack. The chance of each output is scaled by how far along the recipe was.
+    #[serde(default)]
+    pub cancel_outputs: Vec<RecipeOutput>,
+    /// See [Recipe::resume_grace_seconds](crate::recipe::Recipe::resume_grace_seconds).
+    #[serde(default = "default_resume_grace_seconds")]
+    pub resume_grace_seconds: Option<f32>,
+}
+
+fn default_resume_grace_seconds() -> Option<f32> {
+    Some(DEFAULT_RESUME_GRACE_SECONDS)
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RecipeInput {
+    pub cards: CardMatcher,
+    #[serde(default)]
+    pub amount: InputAmount,
+    #[serde(default)]
+    pub usage: InputUsage,
+}
+
+/// Which cards an input accepts.
+/// Inputs of a single recipe should not match the same cards.
+#[derive(Deserialize, Clone, Debug)]
+pub enum CardMatcher {
+    Type(String),
+    Category(CardCategory),
+}
+
+impl CardMatcher {
+    pub fn matches(&self, card: &Card) -> bool {
+        match self {
+            CardMatcher::Type(id) => card.type_id.0 == id,
+            CardMatcher::Category(category) => card.category == *category,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum InputAmount {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl Default for InputAmount {
+    fn default() -> Self {
+        InputAmount::Exactly(1)
+    }
+}
+
+impl InputAmount {
+    fn allows(&self, amount: usize) -> bool {
+        match *self {
+            InputAmount::Exactly(wanted) => amount == wanted,
+            InputAmount::AtLeast(minimum) => amount >= minimum,
+        }
+    }
+}
+
+/// What happens to the input cards when the recipe finishes.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputUsage {
+    /// The cards stay as they are.
+    Kept,
+    /// All matched cards are deleted.
+    Consumed,
+    /// The first matched card loses one of its [RecipeUses], and is deleted when it runs out.
+    UseDecremented,
+}
+
+impl Default for InputUsage {
+    fn default() -> Self {
+        InputUsage::Kept
+    }
+}
+
+/// A possible result of a recipe.
+/// When the output is produced, one of the `options` is picked based on their weights.
+#[derive(Deserialize, Clone, Debug)]
+pub struct RecipeOutput {
+    /// Chance, out of 100, that this output is produced at all.
+    #[serde(default = "always")]
+    pub chance_percentage: usize,
+    pub options: Vec<WeightedCards>,
+}
+
+fn always() -> usize {
+    100
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WeightedCards {
+    pub card_type: String,
+    #[serde(default = "one")]
+    pub amount: usize,
+    #[serde(default = "one")]
+    pub weight: usize,
+}
+
+fn one() -> usize {
+    1
+}
+
+impl RecipeDefinition {
+    pub fn is_valid(&self, cards: &StackCheck) -> bool {
+        !cards.is_empty()
+            && cards
+                .iter()
+                .all(|card| self.inputs.iter().any(|input| input.cards.matches(card)))
+            && self.inputs.iter().all(|input| {
+                input.amount.allows(
+                    cards
+                        .iter()
+                        .filter(|&card| input.cards.matches(card))
+                        .count(),
+                )
+            })
+    }
+
+    /// Lists the card types this recipe refers to, which are not in the given list.
+    fn unknown_card_types<'a>(&'a self, known_card_types: &[&str]) -> Vec<&'a str> {
+        let inputs = self.inputs.iter().filter_map(|input| match &input.cards {
+            CardMatcher::Type(id) => Some(id.as_str()),
+            CardMatcher::Category(_) => None,
+        });
+        let outputs = self
+            .outputs
+            .iter()
+            .chain(&self.cancel_outputs)
+            .flat_map(|output| {
+                output
+                    .options
+                    .iter()
+                    .map(|option| option.card_type.as_str())
+            });
+
+        inputs
+            .chain(outputs)
+            .filter(|id| !known_card_types.contains(id))
+            .collect()
+    }
+}
+
+/// Asset containing all recipes defined in a single file.
+#[derive(Deserialize, TypeUuid, Clone, Debug)]
+#[uuid = "a3c2f1e4-6b0d-4c8e-8f3a-2d9e5b7c1a40"]
+pub struct RecipeDefinitions(pub Vec<RecipeDefinition>);
+
+#[derive(Default)]
+pub struct RecipeDefinitionsLoader;
+
+impl AssetLoader for RecipeDefinitionsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let definitions: RecipeDefinitions = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(definitions));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[RECIPES_FILE_EXTENSION]
+    }
+}
+
+impl<'a> RecipesBuilder<'a> {
+    /// Adds a recipe that is fully described by data.
+    pub fn add_recipe_definition(&mut self, definition: RecipeDefinition) {
+        // Recipe ids are used for the lifetime of the game, and are only loaded once.
+        let id: &'static str = Box::leak(definition.id.clone().into_boxed_str());
+        let seconds = definition.seconds;
+        let priority = definition.priority;
+        let repeat = definition.repeat;
+        let resume_grace_seconds = definition.resume_grace_seconds;
+
+        let definition = Arc::new(definition);
+        let valid_definition = definition.clone();
+        let cancel_definition = definition.clone();
+
+        self.new_recipe(
+            id,
+            seconds,
+            move |cards| valid_definition.is_valid(cards),
+            move |mut commands: Commands,
+                  recipe_stack_query: Query<
+                (Entity, &CardStack, &GlobalTransform),
+                With<FinishRecipeMarker>,
+            >,
+                  mut card_query: Query<(&Card, Option<&mut RecipeUses>)>,
+                  seeded_hash: Res<SeededHasherResource>,
+                  card_types: Res<CardTypes>,
+                  mut creation: EventWriter<CreateStackEvent>| {
+                for (root, stack, global_transform) in recipe_stack_query.iter() {
+                    // Seed on the contents of the stack, so repeating a recipe does not
+                    // always give the same result.
+                    let mut rng = seeded_hash.with(root);
+                    rng.with(id);
+                    for &card_entity in stack.iter() {
+                        rng.with(card_entity);
+                        if let Ok((_, Some(uses))) = card_query.get(card_entity) {
+                            rng.with(uses.0);
+                        }
+                    }
+
+                    let mut cards_to_delete: Vec<Entity> = vec![];
+                    for input in definition.inputs.iter() {
+                        let mut matching = stack.iter().copied().filter(|&e| {
+                            card_query
+                                .get(e)
+                                .map(|(card, _)| input.cards.matches(card))
+                                .unwrap_or(false)
+                        });
+
+                        match input.usage {
+                            InputUsage::Kept => {}
+                            InputUsage::Consumed => cards_to_delete.extend(matching),
+                            InputUsage::UseDecremented => {
+                                if let Some(card_entity) = matching.next() {
+                                    if let Ok((_, Some(mut uses))) = card_query.get_mut(card_entity)
+                                    {
+                                        if uses.0 <= 1 {
+                                            cards_to_delete.push(card_entity);
+                                        } else {
+                                            uses.0 -= 1;
+                                        }
+                                    } else {
+                                        // A card without uses can only be used once.
+                                        cards_to_delete.push(card_entity);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    cards_to_delete.sort();
+                    cards_to_delete.dedup();
+                    if !cards_to_delete.is_empty() {
+                        delete_cards(&mut commands, &cards_to_delete, root, stack);
+                    }
+
+                    for (card_type, amount) in
+                        choose_outputs(id, &definition.outputs, 1.0, &mut rng, &card_types)
+                    {
+                        creation.send(CreateStackEvent {
+                            position: global_transform.translation.truncate(),
+                            card_type,
+                            amount,
+                            feed_waiting_stack: definition.feed_outputs,
+                        });
+                    }
+                }
+            },
+        );
+        self.set_priority(id, priority);
+        self.set_repeat(id, repeat);
+        self.set_resume_grace_seconds(id, resume_grace_seconds);
+
+        if !cancel_definition.cancel_outputs.is_empty() {
+            self.set_on_cancel(
+                id,
+                move |recipe_stack_query: Query<(
+                    Entity,
+                    &CancelRecipeMarker,
+                    &GlobalTransform,
+                )>,
+                      seeded_hash: Res<SeededHasherResource>,
+                      card_types: Res<CardTypes>,
+                      mut creation: EventWriter<CreateStackEvent>| {
+                    for (root, CancelRecipeMarker(recipe), global_transform) in
+                        recipe_stack_query.iter()
+                    {
+                        let mut rng = seeded_hash.with(root);
+                        rng.with(id);
+                        rng.with(recipe.timer.elapsed());
+
+                        for (card_type, amount) in choose_outputs(
+                            id,
+                            &cancel_definition.cancel_outputs,
+                            recipe.timer.percent(),
+                            &mut rng,
+                            &card_types,
+                        ) {
+                            creation.send(CreateStackEvent {
+                                position: global_transform.translation.truncate(),
+                                card_type,
+                                amount,
+                                feed_waiting_stack: false,
+                            });
+                        }
+                    }
+                },
+            );
+        }
+    }
+}
+
+/// Picks which of the `outputs` are produced, and the amount of cards for each of them.
+/// The chance of each output is multiplied by `chance_factor`.
+fn choose_outputs(
+    id: &str,
+    outputs: &[RecipeOutput],
+    chance_factor: f32,
+    rng: &mut HashedRng,
+    card_types: &CardTypes,
+) -> Vec<(CardTypeId, usize)> {
+    let mut chosen = vec![];
+    for output in outputs.iter() {
+        let chance = (output.chance_percentage as f32 * chance_factor) as usize;
+        if rng.value_in_range(0..100) >= chance {
+            continue;
+        }
+
+        let maybe_option = rng.choose_weighted(&output.options, |option| option.weight);
+
+        if let Some(option) = maybe_option {
+            if let Some(card_type) = card_types.get_by_name(&option.card_type) {
+                chosen.push((card_type.id, option.amount));
+            } else {
+                warn!(
+                    "Recipe `{}` tried to create unknown card type `{}`",
+                    id, option.card_type
+                );
+            }
+        }
+    }
+    chosen
+}
+
+/// Adds the recipes from the recipe definition files to the [Recipes] resource.
+/// Has to be exclusive, because creating a recipe needs full access to the [World].
+pub fn recipe_definitions_loading_system(world: &mut World) {
+    let handle = world.resource::<RecipeAssets>().base.clone();
+    // Can call `unwrap()` because the asset_loader will have caught any missing assets already.
+    let definitions = world
+        .resource::<Assets<RecipeDefinitions>>()
+        .get(handle)
+        .unwrap()
+        .clone();
+
+    let card_types_handle = world.resource::<CardTypeAssets>().base.clone();
+    let known_card_types: Vec<String> = world
+        .resource::<Assets<CardTypeDefinitions>>()
+        .get(card_types_handle)
+        .map(|card_types| card_types.0.iter().map(|c| c.id.clone()).collect())
+        .unwrap_or_default();
+
+    add_recipe_definitions(world, definitions.0, &known_card_types);
+}
+
+/// Adds the given recipes to the [Recipes] resource.
+/// Recipes referring to card types that are not in `known_card_types` are still added,
+/// but an error is logged for them.
+pub fn add_recipe_definitions(
+    world: &mut World,
+    definitions: Vec<RecipeDefinition>,
+    known_card_types: &[String],
+) {
+    let known_card_types: Vec<&str> = known_card_types.iter().map(String::as_str).collect();
+    for definition in definitions.iter() {
+        for unknown in definition.unknown_card_types(&known_card_types) {
+            error!(
+                "Recipe `{}` refers to unknown card type `{}`",
+                definition.id, unknown
+            );
+        }
+    }
+
+    let recipes = world.remove_resource::<Recipes>().unwrap_or_default();
+    let mut builder = RecipesBuilder::from_recipes(world, recipes);
+    for definition in definitions {
+        builder.add_recipe_definition(definition);
+    }
+    let recipes = builder.build();
+    world.insert_resource(recipes);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::card_types::{
+        CardCategory, CardTypeDefinitions, CardTypeId, CardTypes, APPLE, CLAY, PLANK,
+    };
+    use crate::procedural::SeededHasherResource;
+    use crate::recipe::declarative_recipes::{add_recipe_definitions, RecipeDefinitions};
+    use crate::recipe::{
+        recipe_cancelled_exclusive_system, recipe_check_system, recipe_finished_exclusive_system,
+        FinishedRecipeEvent, OngoingRecipe, RecipeCancelledEvent, RecipeCancelledMarker, RecipeId,
+        RecipeReadyMarker, RecipeUses, StackCheck,
+    };
+    use crate::stack::{Card, CardStack, CreateStackEvent};
+    use bevy::ecs::event::Events;
+    use bevy::prelude::*;
+    use std::time::Duration;
+
+    fn card(type_id: &'static str, category: CardCategory) -> Card {
+        Card {
+            type_id: CardTypeId(type_id),
+            category,
+            value: None,
+            buys: None,
+        }
+    }
+
+    /// World with the card types of the base game, and the given recipe definitions.
+    fn recipe_world(definitions: &str) -> World {
+        let mut world = World::default();
+
+        let card_type_definitions: CardTypeDefinitions =
+            ron::from_str(include_str!("../../assets/cards/base.cards.ron")).unwrap();
+        let known_card_types: Vec<String> = card_type_definitions
+            .0
+            .iter()
+            .map(|definition| definition.id.clone())
+            .collect();
+        let (card_types, _) = CardTypes::from_definitions(&card_type_definitions.0, None, None);
+        world.insert_resource(card_types);
+        world.insert_resource(SeededHasherResource::new(42));
+        world.insert_resource(Events::<CreateStackEvent>::default());
+        world.insert_resource(Events::<FinishedRecipeEvent>::default());
+        world.insert_resource(Events::<RecipeCancelledEvent>::default());
+
+        let definitions: RecipeDefinitions = ron::from_str(definitions).unwrap();
+        add_recipe_definitions(&mut world, definitions.0, &known_card_types);
+        world
+    }
+
+    #[test]
+    fn test_recipe_definition_is_valid() {
+        let definitions: RecipeDefinitions = ron::from_str(
+            r#"[(
+                id: "cut_tree",
+                seconds: Some(2.0),
+                inputs: [
+                    (cards: Category(Worker)),
+                    (cards: Type("tree"), amount: AtLeast(1), usage: UseDecremented),
+                ],
+                outputs: [(options: [(card_type: "log")])],
+            )]"#,
+        )
+        .unwrap();
+        let recipe = &definitions.0[0];
+
+        let villager = card("villager", CardCategory::Worker);
+        let tree = card("tree", CardCategory::Nature);
+        let log = card("log", CardCategory::Resource);
+
+        assert!(recipe.is_valid(&StackCheck(vec![tree, villager])));
+        assert!(recipe.is_valid(&StackCheck(vec![villager, tree, tree])));
+        // Missing inputs.
+        assert!(!recipe.is_valid(&StackCheck(vec![villager])));
+        assert!(!recipe.is_valid(&StackCheck(vec![])));
+        // Too many workers.
+        assert!(!recipe.is_valid(&StackCheck(vec![villager, villager, tree])));
+        // Cards that are not part of the recipe.
+        assert!(!recipe.is_valid(&StackCheck(vec![villager, tree, log])));
+    }
+
+    #[test]
+    fn test_recipe_without_grace_period_is_cancelled_immediately() {
+        let mut world = recipe_world(
+            r#"[(
+                id: "cut_tree",
+                seconds: Some(2.0),
+                resume_grace_seconds: None,
+                inputs: [(cards: Category(Worker)), (cards: Type("tree"))],
+            )]"#,
+        );
+        let mut check_stage = SystemStage::parallel().with_system(recipe_check_system);
+
+        let villager = world
+            .spawn()
+            .insert(card("villager", CardCategory::Worker))
+            .id();
+        let tree = world
+            .spawn()
+            .insert(card("tree", CardCategory::Nature))
+            .id();
+        let root = world.spawn().insert(CardStack(vec![villager, tree])).id();
+        check_stage.run(&mut world);
+        assert!(world.get::<OngoingRecipe>(root).is_some());
+
+        world.entity_mut(root).insert(CardStack(vec![villager]));
+        check_stage.run(&mut world);
+        assert!(world.get::<OngoingRecipe>(root).is_none());
+        assert!(world.get::<RecipeCancelledMarker>(root).is_some());
+    }
+
+    #[test]
+    fn test_cancelled_create_villager_leaves_clay_behind() {
+        let mut world = recipe_world(include_str!("../../assets/recipes/base.recipes.ron"));
+
+        let cancel_recipes = |world: &mut World, elapsed_seconds: u64| {
+            for _ in 0..20 {
+                let mut recipe = OngoingRecipe::new(RecipeId("create_villager"), 5.0);
+                recipe.timer.tick(Duration::from_secs(elapsed_seconds));
+                world
+                    .spawn()
+                    .insert(GlobalTransform::default())
+                    .insert(RecipeCancelledMarker(recipe));
+            }
+            recipe_cancelled_exclusive_system(world);
+
+            let mut events = world.resource_mut::<Events<CreateStackEvent>>();
+            let created: Vec<(CardTypeId, usize)> = events
+                .iter_current_update_events()
+                .map(|event| (event.card_type, event.amount))
+                .collect();
+            events.clear();
+            created
+        };
+
+        // Nothing is left behind by a recipe that only just started.
+        assert!(cancel_recipes(&mut world, 0).is_empty());
+
+        // A recipe that was almost done has a 50% chance.
+        let created = cancel_recipes(&mut world, 5);
+        assert!(!created.is_empty() && created.len() < 20);
+        assert!(created.iter().all(|&created| created == (CLAY, 1)));
+    }
+
+    #[test]
+    fn test_finished_recipe_uses_inputs_and_creates_outputs() {
+        let mut world = recipe_world(
+            r#"[(
+                id: "build",
+                seconds: Some(2.0),
+                inputs: [
+                    (cards: Category(Worker)),
+                    (cards: Type("tree"), amount: AtLeast(1), usage: UseDecremented),
+                    (cards: Type("log"), amount: AtLeast(1), usage: Consumed),
+                ],
+                outputs: [
+                    (options: [(card_type: "plank", amount: 2)]),
+                    (chance_percentage: 0, options: [(card_type: "coin")]),
+                    (options: [(card_type: "clay", weight: 0), (card_type: "apple")]),
+                ],
+            )]"#,
+        );
+
+        let mut spawn_card = |type_id: &'static str, category: CardCategory| {
+            world.spawn().insert(card(type_id, category)).id()
+        };
+        let villager_a = spawn_card("villager", CardCategory::Worker);
+        let tree_a = spawn_card("tree", CardCategory::Nature);
+        let logs_a = [
+            spawn_card("log", CardCategory::Resource),
+            spawn_card("log", CardCategory::Resource),
+        ];
+        let villager_b = spawn_card("villager", CardCategory::Worker);
+        let tree_b = spawn_card("tree", CardCategory::Nature);
+        let log_b = spawn_card("log", CardCategory::Resource);
+        world.entity_mut(tree_a).insert(RecipeUses(2));
+        world.entity_mut(tree_b).insert(RecipeUses(1));
+
+        // Both stacks finish in the same frame.
+        let mut spawn_finished_stack = |cards: Vec<Entity>| {
+            world
+                .spawn()
+                .insert(CardStack(cards))
+                .insert(GlobalTransform::default())
+                .insert(RecipeReadyMarker(RecipeId("build")))
+                .id()
+        };
+        let root_a = spawn_finished_stack(vec![villager_a, tree_a, logs_a[0], logs_a[1]]);
+        let root_b = spawn_finished_stack(vec![villager_b, tree_b, log_b]);
+
+        recipe_finished_exclusive_system(&mut world);
+
+        // All logs are consumed, and the trees lose a use.
+        for log in logs_a.iter().chain([&log_b]) {
+            assert!(world.get_entity(*log).is_none());
+        }
+        assert_eq!(world.get::<RecipeUses>(tree_a).unwrap().0, 1);
+        assert!(world.get_entity(tree_b).is_none());
+        assert_eq!(
+            world.get::<CardStack>(root_a).unwrap().0,
+            vec![villager_a, tree_a]
+        );
+        assert_eq!(world.get::<CardStack>(root_b).unwrap().0, vec![villager_b]);
+
+        // Outputs that can't be picked are never created.
+        let created: Vec<(CardTypeId, usize)> = world
+            .resource::<Events<CreateStackEvent>>()
+            .iter_current_update_events()
+            .map(|event| (event.card_type, event.amount))
+            .collect();
+        assert_eq!(
+            created,
+            vec![(PLANK, 2), (APPLE, 1), (PLANK, 2), (APPLE, 1)]
+        );
+
+        let finished: Vec<Entity> = world
+            .resource::<Events<FinishedRecipeEvent>>()
+            .iter_current_update_events()
+            .map(|FinishedRecipeEvent(_, root)| *root)
+            .collect();
+        assert_eq!(finished.len(), 2);
+        assert!(finished.contains(&root_a) && finished.contains(&root_b));
+    }
+}
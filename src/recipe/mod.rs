@@ -1,12 +1,22 @@
-mod recipe_defines;
+mod declarative_recipes;
+pub(crate) mod recipe_defines;
+mod scripted_recipes;
 
-use crate::card_types::{CardCategory, CardType};
-use crate::stack::{Card, CardStack, CardVisualSize, DELTA_Z, STACK_DRAG_Z};
-use crate::{is_time_running, GameState, TimeSpeed};
+use crate::card_types::{CardCategory, CardTypeId};
+use crate::history::{History, Operation};
+use crate::stack::{Card, CardFonts, CardStack, CardVisualSize, DELTA_Z, STACK_DRAG_Z};
+use crate::{is_time_running, FrameTime, GameState, TimeSpeed};
 use bevy::ecs::event::Events;
 use bevy::prelude::*;
+use bevy_asset_loader::AssetCollection;
+pub use declarative_recipes::{add_recipe_definitions, RecipeDefinitions};
+use declarative_recipes::{recipe_definitions_loading_system, RecipeDefinitionsLoader};
 use recipe_defines::build_recipes;
-use std::collections::{HashMap, HashSet};
+pub use scripted_recipes::{add_recipe_scripts, read_recipe_scripts, RecipeScript};
+use scripted_recipes::{recipe_scripts_loading_system, RecipeScriptLoader};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
 /// Progress bars are located just underneath the dragged stacks on the z order.
@@ -18,23 +28,34 @@ const RECIPE_PROGRESS_BAR_Z: f32 = STACK_DRAG_Z - 10.;
 const RECIPE_PROGRESS_BAR_HEIGHT: f32 = 20.;
 const RECIPE_PROGRESS_BAR_FOREGROUND: Color = Color::WHITE;
 const RECIPE_PROGRESS_BAR_BACKGROUND: Color = Color::rgb(0.1, 0.1, 0.1);
+const RECIPE_PROGRESS_BAR_PAUSED: Color = Color::GRAY;
+
+/// How long a recipe waits for its stack to become valid again, before it is cancelled.
+/// Lets the player take a card off a stack and put it back, without losing the progress.
+pub const DEFAULT_RESUME_GRACE_SECONDS: f32 = 3.0;
 
 /// Prefix used in front of the recipe id when requesting the localized recipe title.
 /// For example, a recipe with id `cut_tree` will have it's localized title stored under `rt_tree`.
 pub const RECIPE_TITLE_LOCALIZATION_PREFIX: &str = "rt_";
 
 /// Handles recipes on card stacks
-/// Requires [CardPlugin].
-pub struct RecipePlugin;
+/// Requires [StackLogicPlugin](crate::stack::StackLogicPlugin).
+pub struct RecipeLogicPlugin;
 
-impl Plugin for RecipePlugin {
+impl Plugin for RecipeLogicPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<FinishedRecipeEvent>()
+            .add_event::<RecipeCancelledEvent>()
             .add_system_set(
                 SystemSet::on_update(GameState::Run)
                     .with_system(recipe_check_system)
-                    .with_system(recipe_timer_graphics_system)
-                    .with_system(recipe_finished_exclusive_system.exclusive_system().at_end()),
+                    .with_system(recipe_cancelled_log_system)
+                    .with_system(recipe_finished_exclusive_system.exclusive_system().at_end())
+                    .with_system(
+                        recipe_cancelled_exclusive_system
+                            .exclusive_system()
+                            .at_end(),
+                    ),
             )
             .add_system_set(
                 SystemSet::on_update(GameState::Run)
@@ -47,15 +68,138 @@ impl Plugin for RecipePlugin {
     }
 }
 
+/// Loads the recipe definition files, and shows the progress of recipes.
+/// Requires the [RecipeLogicPlugin].
+pub struct RecipePlugin;
+
+impl Plugin for RecipePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<RecipeDefinitions>()
+            .init_asset_loader::<RecipeDefinitionsLoader>()
+            .add_asset::<RecipeScript>()
+            .init_asset_loader::<RecipeScriptLoader>()
+            .add_system_set(
+                SystemSet::on_exit(GameState::AssetLoading)
+                    .with_system(recipe_definitions_loading_system.exclusive_system())
+                    .with_system(recipe_scripts_loading_system.exclusive_system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Run).with_system(recipe_timer_graphics_system),
+            );
+    }
+}
+
+/// To be loaded by an [AssetLoader](bevy_asset_loader::AssetLoader).
+#[derive(AssetCollection)]
+pub struct RecipeAssets {
+    #[asset(path = "recipes/base.recipes.ron")]
+    pub base: Handle<RecipeDefinitions>,
+    /// Every `<recipe id>.recipe.rhai` file in the folder is a recipe.
+    #[asset(path = "recipes/scripts", folder(typed))]
+    pub scripts: Vec<Handle<RecipeScript>>,
+}
+
 /// This id is used to identify the recipe,
 /// and to retrieve localized strings belonging to the recipe, such as its name.
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
 pub struct RecipeId(pub(crate) &'static str);
 
+/// Next free [RecipeInstanceId].
+static NEXT_RECIPE_INSTANCE: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a single run of a recipe, so copies of it made by splitting a stack can be told
+/// apart from other stacks that happen to run the same recipe.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub struct RecipeInstanceId(pub u64);
+
+impl RecipeInstanceId {
+    pub fn next() -> Self {
+        RecipeInstanceId(NEXT_RECIPE_INSTANCE.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// For ids that were saved. Makes sure [RecipeInstanceId::next] never hands them out again.
+    pub fn reserve(id: u64) -> Self {
+        NEXT_RECIPE_INSTANCE.fetch_max(id + 1, Ordering::Relaxed);
+        RecipeInstanceId(id)
+    }
+}
+
 #[derive(Component, Clone)]
 pub struct OngoingRecipe {
     pub id: RecipeId,
+    /// Shared by the copies of this recipe that are made when splitting the stack.
+    pub instance: RecipeInstanceId,
     pub timer: Timer,
+    /// Set while the stack is not valid for the recipe. The `timer` does not run while paused.
+    /// When this grace timer finishes the recipe is cancelled,
+    /// unless the stack became valid for the recipe again.
+    pub paused: Option<Timer>,
+}
+
+impl OngoingRecipe {
+    pub fn new(id: RecipeId, seconds: f32) -> Self {
+        OngoingRecipe {
+            id,
+            instance: RecipeInstanceId::next(),
+            timer: Timer::new(Duration::from_secs_f32(seconds), false),
+            paused: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+}
+
+/// How often a recipe starts again after it finishes, if the stack is still valid for it.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepeatPolicy {
+    /// Runs once. Only starts again when the player changes the stack.
+    Once,
+    /// Keeps repeating for as long as the stack is valid.
+    WhileValid,
+    /// Runs this many times in a row.
+    Times(u32),
+}
+
+impl Default for RepeatPolicy {
+    fn default() -> Self {
+        RepeatPolicy::WhileValid
+    }
+}
+
+/// Component that remembers how many more times the recipe on a stack starts again,
+/// after it finishes. Is placed together with the recipe, and kept after it finishes.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecipeRepetitions {
+    pub id: RecipeId,
+    /// `None` when the recipe repeats for as long as the stack is valid.
+    pub left: Option<u32>,
+}
+
+impl RecipeRepetitions {
+    /// Repetitions of a recipe that starts for the first time.
+    pub fn first(id: RecipeId, policy: RepeatPolicy) -> Self {
+        let left = match policy {
+            RepeatPolicy::Once => Some(0),
+            RepeatPolicy::WhileValid => None,
+            RepeatPolicy::Times(times) => Some(times.saturating_sub(1)),
+        };
+        RecipeRepetitions { id, left }
+    }
+
+    /// Repetitions after starting the recipe once more.
+    /// `None` when the recipe should not start again.
+    pub fn repeated(&self) -> Option<Self> {
+        match self.left {
+            None => Some(*self),
+            Some(0) => None,
+            Some(left) => Some(RecipeRepetitions {
+                id: self.id,
+                left: Some(left - 1),
+            }),
+        }
+    }
 }
 
 /// Component indicating a progress bar hovering over a currently ongoing recipe.
@@ -76,6 +220,17 @@ pub struct RecipeReadyMarker(RecipeId);
 #[derive(Component)]
 pub struct FinishRecipeMarker;
 
+/// Component that marks a stack with a recipe that was cancelled, and the state it was in.
+/// This is read by [recipe_cancelled_exclusive_system], which calls the recipe's `cancel_system`.
+#[derive(Component)]
+pub struct RecipeCancelledMarker(pub OngoingRecipe);
+
+/// Marker that indicates which stacks had their recipe cancelled when a [Recipe]'s `cancel_system` is called.
+/// Contains the recipe as it was when it was cancelled, so the `cancel_system` can see how far along it was.
+/// Are placed by [recipe_cancelled_exclusive_system], and any stray ones are automatically cleaned up.
+#[derive(Component)]
+pub struct CancelRecipeMarker(pub OngoingRecipe);
+
 /// Generic component that can be used by recipes to track how many "uses" there are left in a card.
 /// For example: A worker could chop wood from a tree multiple times, before the tree is deleted.
 #[derive(Component)]
@@ -85,6 +240,16 @@ pub struct RecipeUses(pub u32);
 /// Contains the relevant recipe id, and the root entity of the relevant stack.
 pub struct FinishedRecipeEvent(RecipeId, Entity);
 
+/// Event that happens when an ongoing recipe was cancelled, either by the player,
+/// or because its stack was not valid for it anymore.
+/// The `root` entity might not be a stack anymore, if the cancel hook of the recipe removed it.
+pub struct RecipeCancelledEvent {
+    pub id: RecipeId,
+    pub root: Entity,
+    /// How long the recipe had been going.
+    pub elapsed: Duration,
+}
+
 pub struct RecipesBuilder<'a> {
     world: &'a mut World,
     recipes: HashMap<RecipeId, Recipe>,
@@ -92,9 +257,14 @@ pub struct RecipesBuilder<'a> {
 
 impl<'a> RecipesBuilder<'a> {
     pub fn new(world: &'a mut bevy::prelude::World) -> Self {
+        Self::from_recipes(world, Recipes::default())
+    }
+
+    /// Continues building on a set of already existing recipes.
+    pub fn from_recipes(world: &'a mut bevy::prelude::World, recipes: Recipes) -> Self {
         RecipesBuilder {
             world,
-            recipes: HashMap::new(),
+            recipes: recipes.0,
         }
     }
 
@@ -122,13 +292,51 @@ impl<'a> RecipesBuilder<'a> {
         self.new_recipe(id, Some(seconds), valid_callback, finished_system);
     }
 
+    /// Recipes with a higher priority are tried first when a stack matches multiple recipes.
+    /// All recipes start out with priority `0`.
+    pub fn set_priority(&mut self, id: &'static str, priority: i32) {
+        if let Some(recipe) = self.recipes.get_mut(&RecipeId(id)) {
+            recipe.priority = priority;
+        }
+    }
+
+    /// All recipes start out repeating while the stack is valid for them.
+    pub fn set_repeat(&mut self, id: &'static str, repeat: RepeatPolicy) {
+        if let Some(recipe) = self.recipes.get_mut(&RecipeId(id)) {
+            recipe.repeat = repeat;
+        }
+    }
+
+    /// Sets a system that is called when the recipe is cancelled.
+    /// Works like the `finished_system`, but the stacks are marked with a [CancelRecipeMarker].
+    pub fn set_on_cancel<Params>(
+        &mut self,
+        id: &'static str,
+        cancel_system: impl IntoSystem<(), (), Params> + 'static,
+    ) {
+        if let Some(recipe) = self.recipes.get_mut(&RecipeId(id)) {
+            let mut boxed_system = Box::new(IntoSystem::into_system(cancel_system));
+            boxed_system.initialize(self.world);
+            recipe.cancel_system = Some(boxed_system);
+        }
+    }
+
+    /// How long the recipe waits for its stack to become valid again, before it is cancelled.
+    /// When `None`, the recipe is cancelled as soon as the stack is not valid for it anymore.
+    /// All recipes start out with [DEFAULT_RESUME_GRACE_SECONDS].
+    pub fn set_resume_grace_seconds(&mut self, id: &'static str, seconds: Option<f32>) {
+        if let Some(recipe) = self.recipes.get_mut(&RecipeId(id)) {
+            recipe.resume_grace_seconds = seconds;
+        }
+    }
+
     /// `id` is used to identify the recipe,
     /// and to retrieve localized strings such as the recipe name.
     fn new_recipe<Params>(
         &mut self,
         id: &'static str,
         seconds: Option<f32>,
-        valid_callback: fn(&StackCheck) -> bool,
+        valid_callback: impl Fn(&StackCheck) -> bool + Send + Sync + 'static,
         finished_system: impl IntoSystem<(), (), Params> + 'static,
     ) {
         let mut boxed_system = Box::new(IntoSystem::into_system(finished_system));
@@ -138,8 +346,12 @@ impl<'a> RecipesBuilder<'a> {
 
         let new_recipe = Recipe {
             seconds,
-            is_valid: valid_callback,
+            priority: 0,
+            repeat: RepeatPolicy::default(),
+            resume_grace_seconds: Some(DEFAULT_RESUME_GRACE_SECONDS),
+            is_valid: Box::new(valid_callback),
             finish_system: boxed_system,
+            cancel_system: None,
         };
 
         self.recipes.insert(id, new_recipe);
@@ -154,13 +366,132 @@ impl<'a> RecipesBuilder<'a> {
 #[derive(Default, Deref, DerefMut)]
 pub struct Recipes(HashMap<RecipeId, Recipe>);
 
+impl Recipes {
+    /// All recipes, in the order in which they should be checked against a stack.
+    /// Highest priority first, with equal priorities sorted by id, so the order never depends on
+    /// the order the recipes happen to be stored in.
+    pub fn in_matching_order(&self) -> Vec<(RecipeId, &Recipe)> {
+        let mut recipes: Vec<(RecipeId, &Recipe)> =
+            self.0.iter().map(|(&id, recipe)| (id, recipe)).collect();
+        recipes.sort_by(|(id1, recipe1), (id2, recipe2)| {
+            recipe2
+                .priority
+                .cmp(&recipe1.priority)
+                .then_with(|| id1.cmp(id2))
+        });
+        recipes
+    }
+
+    /// Tries every combination of the given cards, up to `max_stack_size` cards,
+    /// and reports pairs of recipes with the same priority that are valid for the same stack.
+    /// For such stacks it is ambiguous which recipe the player wanted.
+    ///
+    /// Every card is tried as the bottom card, because some recipes care about it.
+    /// The rest of the stack is always in the same order.
+    ///
+    /// This is too slow to do every time the game starts, so it is only done by the tests.
+    #[cfg(test)]
+    pub fn find_overlapping_recipes(
+        &self,
+        card_pool: &[Card],
+        max_stack_size: usize,
+    ) -> Vec<RecipeOverlap> {
+        let recipes = self.in_matching_order();
+        let mut overlaps: BTreeMap<(RecipeId, RecipeId), Vec<Card>> = BTreeMap::new();
+
+        for_each_multiset(card_pool.len(), max_stack_size, &mut |indices| {
+            let mut tried_bottom_cards = vec![];
+            for (position, &bottom) in indices.iter().enumerate() {
+                if tried_bottom_cards.contains(&bottom) {
+                    continue;
+                }
+                tried_bottom_cards.push(bottom);
+
+                let mut cards = vec![card_pool[bottom]];
+                cards.extend(
+                    indices
+                        .iter()
+                        .enumerate()
+                        .filter(|&(i, _)| i != position)
+                        .map(|(_, &index)| card_pool[index]),
+                );
+                let stack_check = StackCheck(cards);
+
+                let valid: Vec<&(RecipeId, &Recipe)> = recipes
+                    .iter()
+                    .filter(|(_, recipe)| (recipe.is_valid)(&stack_check))
+                    .collect();
+                for (i, (id1, recipe1)) in valid.iter().enumerate() {
+                    for (id2, recipe2) in valid.iter().skip(i + 1) {
+                        if recipe1.priority == recipe2.priority {
+                            overlaps
+                                .entry((*id1, *id2))
+                                .or_insert_with(|| stack_check.0.clone());
+                        }
+                    }
+                }
+            }
+        });
+
+        overlaps
+            .into_iter()
+            .map(|(recipes, example_stack)| RecipeOverlap {
+                recipes,
+                example_stack,
+            })
+            .collect()
+    }
+}
+
+/// Two recipes which are both valid for the `example_stack`.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct RecipeOverlap {
+    pub recipes: (RecipeId, RecipeId),
+    pub example_stack: Vec<Card>,
+}
+
+/// Calls `f` with every non-empty multiset of indices into a pool of `pool_size`,
+/// containing at most `max_size` indices. Indices are given in ascending order.
+#[cfg(test)]
+fn for_each_multiset(pool_size: usize, max_size: usize, f: &mut impl FnMut(&[usize])) {
+    fn recurse(
+        start: usize,
+        pool_size: usize,
+        max_size: usize,
+        current: &mut Vec<usize>,
+        f: &mut impl FnMut(&[usize]),
+    ) {
+        if !current.is_empty() {
+            f(current);
+        }
+        if current.len() == max_size {
+            return;
+        }
+        for index in start..pool_size {
+            current.push(index);
+            recurse(index, pool_size, max_size, current, f);
+            current.pop();
+        }
+    }
+
+    recurse(0, pool_size, max_size, &mut vec![], f);
+}
+
 pub struct Recipe {
     /// Time the recipe takes, in seconds.
     /// When `None` the recipe is instant, and can be done even if the in-game time is paused.
     pub seconds: Option<f32>,
+    /// When a stack is valid for multiple recipes, the one with the highest priority is picked.
+    pub priority: i32,
+    /// Whether the recipe starts again after it finishes, if the stack is still valid for it.
+    pub repeat: RepeatPolicy,
+    /// How long the recipe waits for its stack to become valid again, before it is cancelled.
+    /// When `None`, the recipe is cancelled as soon as the stack is not valid for it anymore.
+    pub resume_grace_seconds: Option<f32>,
     /// This callback is called when cards are added or removed from stacks.
     /// Should return `true` if the given stack contents are valid for this recipe.
-    pub is_valid: fn(&StackCheck) -> bool,
+    pub is_valid: Box<dyn Fn(&StackCheck) -> bool + Send + Sync>,
     /// System that applies the effects of a recipe.
     /// Only called a maximum of once per frame.
     /// The stacks that need to be handled will be indicated by a [FinishRecipeMarker].
@@ -170,6 +501,10 @@ pub struct Recipe {
     /// Do not worry about leaving a [FinishRecipeMarker] lying around,
     /// it will be cleaned up automatically.
     finish_system: Box<dyn System<In = (), Out = ()>>,
+    /// Optional system that is called when an ongoing recipe is cancelled.
+    /// The stacks that need to be handled will be indicated by a [CancelRecipeMarker].
+    /// Same rules as the `finish_system` apply.
+    cancel_system: Option<Box<dyn System<In = (), Out = ()>>>,
 }
 
 /// Convenience structure passed to the `is_valid` function of [Recipe]s, for checking various info about a stack.
@@ -177,20 +512,20 @@ pub struct Recipe {
 pub struct StackCheck(pub Vec<Card>);
 
 impl StackCheck {
-    fn bottom_card_is_type(&self, card_type: CardType) -> bool {
+    fn bottom_card_is_type(&self, card_type: CardTypeId) -> bool {
         if let Some(card) = self.0.get(0) {
-            card.is_type(&card_type)
+            card.is_type(card_type)
         } else {
             false
         }
     }
 
-    fn contains_exactly_one_of_type(&self, card_type: CardType) -> bool {
-        self.0.iter().filter(|&c| c.is_type(&card_type)).count() == 1
+    fn contains_exactly_one_of_type(&self, card_type: CardTypeId) -> bool {
+        self.0.iter().filter(|&c| c.is_type(card_type)).count() == 1
     }
 
-    fn contains_n_of_type(&self, card_type: CardType, amount: usize) -> bool {
-        self.0.iter().filter(|&c| c.is_type(&card_type)).count() == amount
+    fn contains_n_of_type(&self, card_type: CardTypeId, amount: usize) -> bool {
+        self.0.iter().filter(|&c| c.is_type(card_type)).count() == amount
     }
 
     fn contains_exactly_one_of_category(&self, category: CardCategory) -> bool {
@@ -199,12 +534,19 @@ impl StackCheck {
 }
 
 /// Checks whether stacks are valid recipes or not.
+///
+/// When a stack is no longer valid for its ongoing recipe, the recipe is paused for a while,
+/// so the player can split off a card and put it back without losing progress.
+/// Splitting a stack gives both halves a copy of the recipe, so only one copy is kept.
+///
+/// A recipe that just finished only starts again if its [RepeatPolicy] allows it.
 pub fn recipe_check_system(
     mut commands: Commands,
     changed_stacks: Query<(
         Entity,
         &CardStack,
         Option<&OngoingRecipe>,
+        Option<&RecipeRepetitions>,
         ChangeTrackers<CardStack>,
     )>,
     cards: Query<&Card>,
@@ -216,8 +558,12 @@ pub fn recipe_check_system(
         .map(|FinishedRecipeEvent(_, root)| *root)
         .collect();
 
-    for (root, stack, maybe_ongoing_recipe, stack_changed) in changed_stacks.iter() {
-        if !stack_changed.is_changed() && !finished_recipe_roots.contains(&root) {
+    let mut stacks_to_check = vec![];
+    for (root, stack, maybe_ongoing_recipe, maybe_repetitions, stack_changed) in
+        changed_stacks.iter()
+    {
+        let recipe_finished = finished_recipe_roots.contains(&root);
+        if !stack_changed.is_changed() && !recipe_finished {
             // This stack didn't change, nor did it have a recipe finish.
             // No need to check it again.
             continue;
@@ -229,36 +575,114 @@ pub fn recipe_check_system(
             .copied()
             .collect();
         let stack_check = StackCheck(cards_in_stack);
-
-        let mut recipe_found =
+        let ongoing_is_valid =
             is_ongoing_recipe_valid_for_stack(maybe_ongoing_recipe, &stack_check, &recipes);
 
-        if !recipe_found {
-            for (&id, recipe) in recipes.iter() {
-                if (recipe.is_valid)(&stack_check) {
-                    if let Some(seconds) = recipe.seconds {
-                        commands.entity(root).insert(OngoingRecipe {
-                            id,
-                            timer: Timer::new(Duration::from_secs_f32(seconds), false),
-                        });
-                    } else {
-                        // This recipe is instant, so it is immediately ready.
-                        commands.entity(root).insert(RecipeReadyMarker(id));
-                    }
+        // Repetitions only carry over to the recipe that just finished.
+        let maybe_finished_repetitions = maybe_repetitions.filter(|_| recipe_finished);
 
-                    // Stop at the first recipe found (best not to have overlapping recipes)
-                    recipe_found = true;
-                    break;
-                }
+        stacks_to_check.push((
+            root,
+            maybe_ongoing_recipe,
+            maybe_finished_repetitions,
+            stack_check,
+            ongoing_is_valid,
+        ));
+    }
+    // Ordered, so the same copy of a split recipe is always kept.
+    stacks_to_check.sort_by_key(|(root, ..)| *root);
+
+    // Recipes that are still going on one of the stacks.
+    let mut kept_recipes: HashSet<RecipeInstanceId> = stacks_to_check
+        .iter()
+        .filter(|(.., ongoing_is_valid)| *ongoing_is_valid)
+        .filter_map(|(_, maybe_ongoing_recipe, ..)| {
+            maybe_ongoing_recipe.map(|recipe| recipe.instance)
+        })
+        .collect();
+
+    for (root, maybe_ongoing_recipe, maybe_finished_repetitions, stack_check, ongoing_is_valid) in
+        stacks_to_check
+    {
+        if ongoing_is_valid {
+            if let Some(ongoing) = maybe_ongoing_recipe.filter(|recipe| recipe.is_paused()) {
+                // Stack is valid again. Continue where the recipe left off.
+                commands.entity(root).insert(OngoingRecipe {
+                    paused: None,
+                    ..ongoing.clone()
+                });
+            }
+            continue;
+        }
+
+        // Stop at the first recipe found. Overlapping recipes are caught by the
+        // `test_base_recipes_do_not_overlap` test.
+        let maybe_new_recipe = recipes
+            .in_matching_order()
+            .into_iter()
+            .find(|(_, recipe)| (recipe.is_valid)(&stack_check))
+            .and_then(|(id, recipe)| {
+                let repetitions = match maybe_finished_repetitions.filter(|r| r.id == id) {
+                    Some(finished_repetitions) => finished_repetitions.repeated()?,
+                    None => RecipeRepetitions::first(id, recipe.repeat),
+                };
+                Some((id, recipe, repetitions))
+            });
+
+        if let Some(ongoing) = maybe_ongoing_recipe {
+            let is_copy = !kept_recipes.insert(ongoing.instance);
+            let grace_seconds = recipes
+                .get(&ongoing.id)
+                .and_then(|recipe| recipe.resume_grace_seconds);
+
+            if is_copy {
+                // Another stack already has this recipe. Nothing was lost, so nothing is cancelled.
+                commands.entity(root).remove::<OngoingRecipe>();
+            } else if maybe_new_recipe.is_some() || grace_seconds.is_none() {
+                cancel_recipe(&mut commands, root, ongoing);
+            } else if let (Some(seconds), false) = (grace_seconds, ongoing.is_paused()) {
+                commands.entity(root).insert(OngoingRecipe {
+                    paused: Some(Timer::new(Duration::from_secs_f32(seconds), false)),
+                    ..ongoing.clone()
+                });
             }
         }
 
-        if !recipe_found {
-            commands.entity(root).remove::<OngoingRecipe>();
+        if let Some((id, recipe, repetitions)) = maybe_new_recipe {
+            commands.entity(root).insert(repetitions);
+            if let Some(seconds) = recipe.seconds {
+                commands
+                    .entity(root)
+                    .insert(OngoingRecipe::new(id, seconds));
+            } else {
+                // This recipe is instant, so it is immediately ready.
+                commands.entity(root).insert(RecipeReadyMarker(id));
+            }
         }
     }
 }
 
+/// Stops the recipe on the stack, and lets the [recipe_cancelled_exclusive_system] handle the rest.
+/// Effects are applied via `Commands`, which means it is visible next update.
+pub fn cancel_recipe(commands: &mut Commands, root: Entity, recipe: &OngoingRecipe) {
+    commands
+        .entity(root)
+        .insert(RecipeCancelledMarker(recipe.clone()))
+        .remove::<OngoingRecipe>();
+}
+
+/// Writes down which recipes were cancelled, and how far along they were.
+fn recipe_cancelled_log_system(mut events: EventReader<RecipeCancelledEvent>) {
+    for event in events.iter() {
+        info!(
+            "Recipe `{}` on stack {:?} was cancelled after {:.1} seconds",
+            event.id.0,
+            event.root,
+            event.elapsed.as_secs_f32()
+        );
+    }
+}
+
 pub fn is_ongoing_recipe_valid_for_stack(
     maybe_ongoing: Option<&OngoingRecipe>,
     stack_check: &StackCheck,
@@ -274,12 +698,21 @@ pub fn is_ongoing_recipe_valid_for_stack(
 pub fn recipe_timer_update_system(
     mut commands: Commands,
     mut ongoing_recipes: Query<(Entity, &mut OngoingRecipe), With<CardStack>>,
-    time: Res<Time>,
+    frame_time: Res<FrameTime>,
     speed: Res<TimeSpeed>,
 ) {
     for (root, mut recipe) in ongoing_recipes.iter_mut() {
-        let progress = time.delta_seconds() * speed.as_factor();
-        recipe.timer.tick(Duration::from_secs_f32(progress));
+        let progress = Duration::from_secs_f32(frame_time.delta_seconds() * speed.as_factor());
+
+        if let Some(grace_timer) = &mut recipe.paused {
+            grace_timer.tick(progress);
+            if grace_timer.finished() {
+                cancel_recipe(&mut commands, root, &recipe);
+            }
+            continue;
+        }
+
+        recipe.timer.tick(progress);
 
         if recipe.timer.finished() {
             commands
@@ -298,11 +731,15 @@ pub fn recipe_timer_graphics_system(
     >,
     recipe_progress_bar_backgrounds: Query<(Entity, &Parent), With<RecipeProgressBarBackground>>,
     ongoing_recipes: Query<&OngoingRecipe, With<CardStack>>,
-    stacks_with_new_recipes: Query<Entity, (With<CardStack>, Added<OngoingRecipe>)>,
+    stacks_with_new_recipes: Query<
+        (Entity, Option<&RecipeRepetitions>),
+        (With<CardStack>, Added<OngoingRecipe>),
+    >,
     card_visual_size: Res<CardVisualSize>,
+    card_fonts: Res<CardFonts>,
 ) {
     // Create new progress bars
-    for root in stacks_with_new_recipes.iter() {
+    for (root, maybe_repetitions) in stacks_with_new_recipes.iter() {
         let bar_y_pos = card_visual_size.y * 0.5 + RECIPE_PROGRESS_BAR_HEIGHT;
 
         let progress_bar = commands
@@ -330,6 +767,38 @@ pub fn recipe_timer_graphics_system(
             .insert(RecipeProgressBarBackground)
             .id();
 
+        // Show how many times the recipe will start again, next to the bar.
+        let repetitions_left = maybe_repetitions
+            .and_then(|repetitions| repetitions.left)
+            .filter(|&left| left > 0);
+        if let Some(left) = repetitions_left {
+            let repetitions_text = commands
+                .spawn_bundle(Text2dBundle {
+                    text: Text::with_section(
+                        format!("+{}", left),
+                        TextStyle {
+                            font: card_fonts.title.clone(),
+                            font_size: RECIPE_PROGRESS_BAR_HEIGHT,
+                            color: RECIPE_PROGRESS_BAR_FOREGROUND,
+                        },
+                        TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Left,
+                        },
+                    ),
+                    transform: Transform::from_xyz(
+                        card_visual_size.x * 0.5 + RECIPE_PROGRESS_BAR_HEIGHT * 0.25,
+                        0.,
+                        DELTA_Z,
+                    ),
+                    ..default()
+                })
+                .id();
+            commands
+                .entity(progress_bar_background)
+                .add_child(repetitions_text);
+        }
+
         commands
             .entity(root)
             .add_child(progress_bar)
@@ -341,6 +810,11 @@ pub fn recipe_timer_graphics_system(
         if let Ok(recipe) = ongoing_recipes.get(root.0) {
             let new_width = recipe.timer.percent() * card_visual_size.x;
 
+            sprite.color = if recipe.is_paused() {
+                RECIPE_PROGRESS_BAR_PAUSED
+            } else {
+                RECIPE_PROGRESS_BAR_FOREGROUND
+            };
             sprite.custom_size = Some(Vec2::new(new_width, RECIPE_PROGRESS_BAR_HEIGHT));
             transform.translation.x = (-card_visual_size.x * 0.5) + (new_width * 0.5);
         } else {
@@ -366,7 +840,8 @@ pub fn recipe_finished_exclusive_system(world: &mut World) {
     let mut ready_recipes = world.query::<(Entity, &RecipeReadyMarker)>();
 
     // See which recipes have been finished, and the stacks that they apply to.
-    let mut finished_recipes: HashMap<RecipeId, Vec<Entity>> = HashMap::new();
+    // Ordered, so recipes are always finished in the same order.
+    let mut finished_recipes: BTreeMap<RecipeId, Vec<Entity>> = BTreeMap::new();
     for (root, RecipeReadyMarker(id)) in ready_recipes.iter(world) {
         if let Some(roots) = finished_recipes.get_mut(&id) {
             roots.push(root);
@@ -412,7 +887,383 @@ pub fn recipe_finished_exclusive_system(world: &mut World) {
                         e.remove::<FinishRecipeMarker>();
                     }
                 }
+
+                if recipe.seconds.is_none() {
+                    if let Some(mut history) = world.get_resource_mut::<History>() {
+                        history.add_to_last(Operation::InstantRecipe(id));
+                    }
+                }
             }
         }
     });
 }
+
+/// System that handles recipes that were cancelled.
+/// Works the same as the [recipe_finished_exclusive_system], but calls the `cancel_system` of
+/// the recipes instead.
+pub fn recipe_cancelled_exclusive_system(world: &mut World) {
+    let mut cancelled_recipes_query = world.query::<(Entity, &RecipeCancelledMarker)>();
+
+    // Ordered, so recipes are always cancelled in the same order.
+    let mut cancelled_recipes: BTreeMap<RecipeId, Vec<(Entity, OngoingRecipe)>> = BTreeMap::new();
+    for (root, RecipeCancelledMarker(recipe)) in cancelled_recipes_query.iter(world) {
+        cancelled_recipes
+            .entry(recipe.id)
+            .or_default()
+            .push((root, recipe.clone()));
+    }
+
+    world.resource_scope(
+        |world, mut recipe_cancelled_events: Mut<Events<RecipeCancelledEvent>>| {
+            for (&id, stacks) in cancelled_recipes.iter() {
+                for (root, recipe) in stacks {
+                    if let Some(mut root_mut) = world.get_entity_mut(*root) {
+                        root_mut.remove::<RecipeCancelledMarker>();
+                        recipe_cancelled_events.send(RecipeCancelledEvent {
+                            id,
+                            root: *root,
+                            elapsed: recipe.timer.elapsed(),
+                        });
+                    }
+                }
+            }
+        },
+    );
+
+    world.resource_scope(|world, mut recipes: Mut<Recipes>| {
+        for (id, stacks) in cancelled_recipes {
+            let cancel_system = match recipes
+                .get_mut(&id)
+                .and_then(|recipe| recipe.cancel_system.as_mut())
+            {
+                Some(cancel_system) => cancel_system,
+                None => continue,
+            };
+
+            for (root, recipe) in stacks.iter() {
+                if let Some(mut e) = world.get_entity_mut(*root) {
+                    e.insert(CancelRecipeMarker(recipe.clone()));
+                }
+            }
+
+            cancel_system.run((), world);
+            cancel_system.apply_buffers(world);
+
+            for (root, _) in stacks.iter() {
+                if let Some(mut e) = world.get_entity_mut(*root) {
+                    e.remove::<CancelRecipeMarker>();
+                }
+            }
+        }
+    });
+}
+
+/// The recipes of the base game, as they are loaded from the assets.
+#[cfg(test)]
+pub(crate) fn base_recipes(world: &mut World) -> Recipes {
+    let definitions: RecipeDefinitions =
+        ron::from_str(include_str!("../../assets/recipes/base.recipes.ron")).unwrap();
+    let scripts = read_recipe_scripts(
+        &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/recipes/scripts"),
+    )
+    .unwrap();
+
+    let recipes = build_recipes(world);
+    let mut builder = RecipesBuilder::from_recipes(world, recipes);
+    for definition in definitions.0 {
+        builder.add_recipe_definition(definition);
+    }
+    for script in scripts {
+        let id: &'static str = Box::leak(script.id.into_boxed_str());
+        builder.add_scripted_recipe(id, &script.source).unwrap();
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::card_types::{CardCategory, CardTypeDefinitions, CardTypeId};
+    use crate::recipe::{
+        base_recipes, recipe_cancelled_exclusive_system, recipe_check_system,
+        recipe_timer_update_system, CancelRecipeMarker, FinishedRecipeEvent, OngoingRecipe,
+        RecipeCancelledEvent, RecipeDefinitions, RecipeId, RecipeRepetitions, RecipesBuilder,
+        RepeatPolicy,
+    };
+    use crate::stack::{Card, CardStack};
+    use crate::{FrameTime, Speed, TimeSpeed};
+    use bevy::ecs::event::Events;
+    use bevy::prelude::*;
+    use std::time::Duration;
+
+    /// Largest stack that is tried for overlapping recipes. Should be at least as large as the
+    /// largest fixed-size recipe.
+    const OVERLAP_CHECK_STACK_SIZE: usize = 5;
+
+    /// Stacks and progress of the recipes seen by the cancel hook.
+    #[derive(Default)]
+    struct CancelledRecipes(Vec<(Entity, Duration)>);
+
+    fn base_card_pool() -> Vec<Card> {
+        let definitions: CardTypeDefinitions =
+            ron::from_str(include_str!("../../assets/cards/base.cards.ron")).unwrap();
+        definitions
+            .0
+            .into_iter()
+            .map(|definition| Card {
+                type_id: CardTypeId(Box::leak(definition.id.into_boxed_str())),
+                category: definition.category,
+                value: definition.value,
+                buys: definition
+                    .buys
+                    .map(|id| CardTypeId(Box::leak(id.into_boxed_str()))),
+            })
+            .collect()
+    }
+
+    /// World with the recipes added by `add_recipes`, and a stage that checks the stacks for them.
+    fn recipe_test_world(add_recipes: impl FnOnce(&mut RecipesBuilder)) -> (World, SystemStage) {
+        let mut world = World::default();
+        let mut builder = RecipesBuilder::new(&mut world);
+        add_recipes(&mut builder);
+        let recipes = builder.build();
+        world.insert_resource(recipes);
+        world.insert_resource(Events::<FinishedRecipeEvent>::default());
+
+        let check_stage = SystemStage::parallel().with_system(recipe_check_system);
+        (world, check_stage)
+    }
+
+    fn spawn_card(world: &mut World, type_id: &'static str) -> Entity {
+        world
+            .spawn()
+            .insert(Card {
+                type_id: CardTypeId(type_id),
+                category: CardCategory::Nature,
+                value: None,
+                buys: None,
+            })
+            .id()
+    }
+
+    #[test]
+    fn test_base_recipes_do_not_overlap() {
+        let recipes = base_recipes(&mut World::default());
+
+        let overlaps =
+            recipes.find_overlapping_recipes(&base_card_pool(), OVERLAP_CHECK_STACK_SIZE);
+        assert!(overlaps.is_empty(), "{:?}", overlaps);
+    }
+
+    #[test]
+    fn test_overlapping_recipes_are_found() {
+        let definitions: RecipeDefinitions = ron::from_str(
+            r#"[
+                (id: "a", inputs: [(cards: Category(Worker))]),
+                (id: "b", inputs: [(cards: Type("villager"))]),
+                (id: "c", priority: 1, inputs: [(cards: Category(Worker), amount: AtLeast(1))]),
+            ]"#,
+        )
+        .unwrap();
+
+        let mut world = World::default();
+        let mut builder = RecipesBuilder::new(&mut world);
+        for definition in definitions.0 {
+            builder.add_recipe_definition(definition);
+        }
+        let recipes = builder.build();
+
+        let overlaps = recipes.find_overlapping_recipes(&base_card_pool(), 2);
+        // `c` overlaps with both, but has a higher priority, so that is not ambiguous.
+        assert_eq!(overlaps.len(), 1);
+        assert_eq!(overlaps[0].recipes.0 .0, "a");
+        assert_eq!(overlaps[0].recipes.1 .0, "b");
+
+        let order: Vec<&str> = recipes
+            .in_matching_order()
+            .iter()
+            .map(|(id, _)| id.0)
+            .collect();
+        assert_eq!(order, vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_split_recipe_is_paused_resumed_and_cancelled() {
+        let (mut world, mut check_stage) = recipe_test_world(|builder| {
+            builder.add_recipe(
+                "cut_tree",
+                10.0,
+                |stack| stack.len() == 2 && stack.contains_exactly_one_of_type(CardTypeId("tree")),
+                || {},
+            );
+            builder.set_on_cancel(
+                "cut_tree",
+                |query: Query<(Entity, &CancelRecipeMarker)>,
+                 mut cancelled: ResMut<CancelledRecipes>| {
+                    for (root, CancelRecipeMarker(recipe)) in query.iter() {
+                        cancelled.0.push((root, recipe.timer.elapsed()));
+                    }
+                },
+            );
+        });
+        world.insert_resource(Events::<RecipeCancelledEvent>::default());
+        world.insert_resource(CancelledRecipes::default());
+        world.insert_resource(TimeSpeed {
+            running: true,
+            speed: Speed::NORMAL,
+        });
+
+        let mut timer_stage = SystemStage::parallel().with_system(recipe_timer_update_system);
+        let mut advance_time = |world: &mut World, seconds: f32| {
+            world.insert_resource(FrameTime {
+                delta_seconds: seconds,
+                fixed_timestep: None,
+            });
+            timer_stage.run(world);
+        };
+
+        let villager = spawn_card(&mut world, "villager");
+        let tree = spawn_card(&mut world, "tree");
+        let root = world.spawn().insert(CardStack(vec![villager, tree])).id();
+
+        check_stage.run(&mut world);
+        advance_time(&mut world, 4.0);
+
+        // Split the stack. Both halves get a copy of the recipe.
+        let recipe = world.get::<OngoingRecipe>(root).unwrap().clone();
+        world.entity_mut(root).insert(CardStack(vec![villager]));
+        let split_root = world
+            .spawn()
+            .insert(CardStack(vec![tree]))
+            .insert(recipe)
+            .id();
+        check_stage.run(&mut world);
+
+        // Only one copy is paused, the other one is dropped without cancelling anything.
+        assert!(world.get::<OngoingRecipe>(root).unwrap().is_paused());
+        assert!(world.get::<OngoingRecipe>(split_root).is_none());
+        advance_time(&mut world, 1.0);
+
+        // Merging within the grace period resumes the recipe where it left off.
+        world.despawn(split_root);
+        world
+            .entity_mut(root)
+            .insert(CardStack(vec![villager, tree]));
+        check_stage.run(&mut world);
+
+        let recipe = world.get::<OngoingRecipe>(root).unwrap();
+        assert!(!recipe.is_paused());
+        assert_eq!(recipe.timer.elapsed(), Duration::from_secs(4));
+
+        // Waiting too long cancels the recipe.
+        world.entity_mut(root).insert(CardStack(vec![villager]));
+        check_stage.run(&mut world);
+        advance_time(&mut world, 5.0);
+        recipe_cancelled_exclusive_system(&mut world);
+
+        assert!(world.get::<OngoingRecipe>(root).is_none());
+        assert_eq!(
+            world.resource::<CancelledRecipes>().0,
+            vec![(root, Duration::from_secs(4))]
+        );
+        let events: Vec<&RecipeCancelledEvent> = world
+            .resource::<Events<RecipeCancelledEvent>>()
+            .iter_current_update_events()
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id.0, "cut_tree");
+        assert_eq!(events[0].elapsed, Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_recipe_repetitions() {
+        let id = RecipeId("cut_tree");
+
+        let once = RecipeRepetitions::first(id, RepeatPolicy::Once);
+        assert_eq!(once.left, Some(0));
+        assert_eq!(once.repeated(), None);
+
+        let while_valid = RecipeRepetitions::first(id, RepeatPolicy::WhileValid);
+        assert_eq!(while_valid.repeated(), Some(while_valid));
+
+        let three_times = RecipeRepetitions::first(id, RepeatPolicy::Times(3));
+        assert_eq!(three_times.left, Some(2));
+        let second = three_times.repeated().unwrap();
+        assert_eq!(second.left, Some(1));
+        let third = second.repeated().unwrap();
+        assert_eq!(third.left, Some(0));
+        assert_eq!(third.repeated(), None);
+    }
+
+    #[test]
+    fn test_finished_recipe_repeats_according_to_policy() {
+        let (mut world, mut check_stage) = recipe_test_world(|builder| {
+            builder.add_recipe("cut_tree", 10.0, |stack| stack.len() == 2, || {});
+            builder.set_repeat("cut_tree", RepeatPolicy::Times(2));
+        });
+
+        let villager = spawn_card(&mut world, "villager");
+        let tree = spawn_card(&mut world, "tree");
+        let root = world.spawn().insert(CardStack(vec![villager, tree])).id();
+
+        let finish = |world: &mut World| {
+            world.entity_mut(root).remove::<OngoingRecipe>();
+            world
+                .resource_mut::<Events<FinishedRecipeEvent>>()
+                .send(FinishedRecipeEvent(RecipeId("cut_tree"), root));
+        };
+
+        check_stage.run(&mut world);
+        assert!(world.get::<OngoingRecipe>(root).is_some());
+        assert_eq!(world.get::<RecipeRepetitions>(root).unwrap().left, Some(1));
+
+        finish(&mut world);
+        check_stage.run(&mut world);
+        assert!(world.get::<OngoingRecipe>(root).is_some());
+        assert_eq!(world.get::<RecipeRepetitions>(root).unwrap().left, Some(0));
+
+        // Ran twice, so it does not start again.
+        finish(&mut world);
+        check_stage.run(&mut world);
+        assert!(world.get::<OngoingRecipe>(root).is_none());
+
+        // Until the player changes the stack.
+        world
+            .entity_mut(root)
+            .insert(CardStack(vec![tree, villager]));
+        check_stage.run(&mut world);
+        assert!(world.get::<OngoingRecipe>(root).is_some());
+        assert_eq!(world.get::<RecipeRepetitions>(root).unwrap().left, Some(1));
+    }
+
+    #[test]
+    fn test_separate_stacks_with_the_same_recipe_are_not_copies() {
+        let (mut world, mut check_stage) = recipe_test_world(|builder| {
+            builder.add_recipe("cut_tree", 10.0, |stack| stack.len() == 2, || {});
+        });
+
+        let stacks: Vec<(Entity, Entity)> = (0..2)
+            .map(|_| {
+                (
+                    spawn_card(&mut world, "villager"),
+                    spawn_card(&mut world, "tree"),
+                )
+            })
+            .collect();
+        let roots: Vec<Entity> = stacks
+            .iter()
+            .map(|&(villager, tree)| world.spawn().insert(CardStack(vec![villager, tree])).id())
+            .collect();
+
+        // Both recipes start in the same frame, so they are equally far along.
+        check_stage.run(&mut world);
+
+        for (&root, &(villager, _)) in roots.iter().zip(stacks.iter()) {
+            world.entity_mut(root).insert(CardStack(vec![villager]));
+        }
+        check_stage.run(&mut world);
+
+        for root in roots {
+            assert!(world.get::<OngoingRecipe>(root).unwrap().is_paused());
+        }
+    }
+}
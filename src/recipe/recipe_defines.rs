@@ -1,152 +1,12 @@
-use crate::card_packs::{BUY_FOREST_PACK, FOREST_PACK};
-use crate::card_types::{CardCategory, APPLE, CLAY_PATCH};
-use crate::card_types::{CLAY, COIN, LOG, MARKET, PLANK, TREE, VILLAGER};
-use crate::procedural::SeededHasherResource;
-use crate::recipe::{FinishRecipeMarker, RecipeUses, Recipes, RecipesBuilder};
+use crate::card_types::{CardCategory, COIN, MARKET};
+use crate::recipe::{FinishRecipeMarker, Recipes, RecipesBuilder};
 use crate::stack::stack_utils::delete_cards;
 use crate::stack::{Card, CardStack, CreateStackEvent};
 use bevy::prelude::*;
 
+/// Recipes that are too involved to be described in a recipe definition file.
 pub fn build_recipes(world: &mut World) -> Recipes {
     let mut builder = RecipesBuilder::new(world);
-    builder.add_recipe(
-        "cut_tree",
-        2.,
-        |cards| {
-            // Contains only trees and workers
-            cards.contains_exactly_one_of_category(CardCategory::Worker)
-                && cards.contains_n_of_type(TREE, cards.len() - 1)
-                && cards.len() > 1
-        },
-        |mut commands: Commands,
-         recipe_stack_query: Query<
-            (Entity, &CardStack, &GlobalTransform),
-            With<FinishRecipeMarker>,
-        >,
-         mut card_query: Query<(&Card, &mut RecipeUses)>,
-         seeded_hash: Res<SeededHasherResource>,
-         mut creation: EventWriter<CreateStackEvent>| {
-            for (root, stack, global_transform) in recipe_stack_query.iter() {
-                for &card_entity in stack.iter() {
-                    if let Ok((card, mut uses)) = card_query.get_mut(card_entity) {
-                        if card.is_type(&TREE) {
-                            creation.send(CreateStackEvent {
-                                position: global_transform.translation.truncate(),
-                                card_type: &LOG,
-                                amount: 1,
-                            });
-
-                            // Sometimes, a tree also drops an apple.
-                            let apple_percentage = 30;
-                            let mut rng = seeded_hash.with(card_entity);
-                            rng.with(uses.0);
-                            if rng.value_in_range(0..100) < apple_percentage {
-                                creation.send(CreateStackEvent {
-                                    position: global_transform.translation.truncate(),
-                                    card_type: &APPLE,
-                                    amount: 1,
-                                });
-                            }
-
-                            // The recipe consumes 1 use of a tree.
-                            if uses.0 == 1 {
-                                delete_cards(&mut commands, &[card_entity], root, stack);
-                            } else {
-                                uses.0 -= 1;
-                            }
-                            break;
-                        }
-                    }
-                }
-            }
-        },
-    );
-    builder.add_recipe(
-        "gather_clay",
-        2.,
-        |cards| {
-            // Contains only clay patches and workers
-            cards.contains_exactly_one_of_category(CardCategory::Worker)
-                && cards.contains_n_of_type(CLAY_PATCH, cards.len() - 1)
-                && cards.len() > 1
-        },
-        |mut commands: Commands,
-         recipe_stack_query: Query<
-            (Entity, &CardStack, &GlobalTransform),
-            With<FinishRecipeMarker>,
-        >,
-         mut card_query: Query<(&Card, &mut RecipeUses)>,
-         seeded_hash: Res<SeededHasherResource>,
-         mut creation: EventWriter<CreateStackEvent>| {
-            for (root, stack, global_transform) in recipe_stack_query.iter() {
-                for &card_entity in stack.iter() {
-                    if let Ok((card, mut uses)) = card_query.get_mut(card_entity) {
-                        if card.is_type(&CLAY_PATCH) {
-                            creation.send(CreateStackEvent {
-                                position: global_transform.translation.truncate(),
-                                card_type: &CLAY,
-                                amount: 1,
-                            });
-
-                            // Sometimes, a clay patch also drops a coin.
-                            let apple_percentage = 25;
-                            let mut rng = seeded_hash.with(card_entity);
-                            rng.with(uses.0);
-                            if rng.value_in_range(0..100) < apple_percentage {
-                                creation.send(CreateStackEvent {
-                                    position: global_transform.translation.truncate(),
-                                    card_type: &COIN,
-                                    amount: 1,
-                                });
-                            }
-
-                            // The recipe consumes 1 use of a clay patch.
-                            if uses.0 == 1 {
-                                delete_cards(&mut commands, &[card_entity], root, stack);
-                            } else {
-                                uses.0 -= 1;
-                            }
-                            break;
-                        }
-                    }
-                }
-            }
-        },
-    );
-    builder.add_recipe(
-        "make_plank",
-        3.,
-        |cards| {
-            cards.len() == 2
-                && cards.contains_exactly_one_of_type(LOG)
-                && cards.contains_exactly_one_of_category(CardCategory::Worker)
-        },
-        |mut commands: Commands,
-         recipe_stack_query: Query<
-            (Entity, &CardStack, &GlobalTransform),
-            With<FinishRecipeMarker>,
-        >,
-         card_query: Query<&Card>,
-         mut creation: EventWriter<CreateStackEvent>| {
-            for (root, stack, global_transform) in recipe_stack_query.iter() {
-                for &card_entity in stack.iter() {
-                    if let Ok(card) = card_query.get(card_entity) {
-                        if card.is_type(&LOG) {
-                            // The recipe consumes a single log.
-                            delete_cards(&mut commands, &[card_entity], root, stack);
-
-                            creation.send(CreateStackEvent {
-                                position: global_transform.translation.truncate(),
-                                card_type: &PLANK,
-                                amount: 1,
-                            });
-                            break;
-                        }
-                    }
-                }
-            }
-        },
-    );
     builder.add_instant_recipe(
         "sell_cards",
         |cards| {
@@ -196,8 +56,9 @@ pub fn build_recipes(world: &mut World) -> Recipes {
                 if total_value > 0 {
                     creation.send(CreateStackEvent {
                         position: global_transform.translation.truncate(),
-                        card_type: &COIN,
+                        card_type: COIN,
                         amount: total_value,
+                        feed_waiting_stack: false,
                     });
                 }
             }
@@ -209,14 +70,13 @@ pub fn build_recipes(world: &mut World) -> Recipes {
             // Bottom card is one of the card pack buy cards, and there are enough coins.
 
             let bottom_card = cards.first().unwrap();
-            let cost = if bottom_card.is_type(&BUY_FOREST_PACK) {
-                bottom_card.value.unwrap()
-            } else {
+            let cost = match (bottom_card.buys, bottom_card.value) {
+                (Some(_), Some(cost)) => cost,
                 // Card is not one of the cards that allow buying packs.
-                return false;
+                _ => return false,
             };
             // Enough coins?
-            cards.iter().filter(|c| c.is_type(&COIN)).count() >= cost
+            cards.iter().filter(|c| c.is_type(COIN)).count() >= cost
         },
         |mut commands: Commands,
          recipe_stack_query: Query<
@@ -226,13 +86,17 @@ pub fn build_recipes(world: &mut World) -> Recipes {
          card_query: Query<&Card>,
          mut creation: EventWriter<CreateStackEvent>| {
             for (root, stack, global_transform) in recipe_stack_query.iter() {
-                let pack_cost = card_query.get(stack[0]).unwrap().value.unwrap();
+                let buy_card = card_query.get(stack[0]).unwrap();
+                let (pack, pack_cost) = match (buy_card.buys, buy_card.value) {
+                    (Some(pack), Some(cost)) => (pack, cost),
+                    _ => continue,
+                };
 
                 let coins_to_delete: Vec<Entity> = stack
                     .iter()
                     .filter_map(|&entity| {
                         if let Ok(card) = card_query.get(entity) {
-                            if card.is_type(&COIN) {
+                            if card.is_type(COIN) {
                                 Some(entity)
                             } else {
                                 None
@@ -256,48 +120,9 @@ pub fn build_recipes(world: &mut World) -> Recipes {
                 // Spawn pack.
                 creation.send(CreateStackEvent {
                     position: global_transform.translation.truncate(),
-                    card_type: &FOREST_PACK,
-                    amount: 1,
-                });
-            }
-        },
-    );
-    builder.add_recipe(
-        "create_villager",
-        5.0,
-        |cards| {
-            // 1 of a worker category, 2 clay and 2 coins
-            cards.len() == 5
-                && cards.contains_exactly_one_of_category(CardCategory::Worker)
-                && cards.contains_n_of_type(CLAY, 2)
-                && cards.contains_n_of_type(COIN, 2)
-        },
-        |mut commands: Commands,
-         recipe_stack_query: Query<
-            (Entity, &CardStack, &GlobalTransform),
-            With<FinishRecipeMarker>,
-        >,
-         card_query: Query<&Card>,
-         mut creation: EventWriter<CreateStackEvent>| {
-            for (root, stack, global_transform) in recipe_stack_query.iter() {
-                let cards_to_be_deleted: Vec<Entity> = stack
-                    .iter()
-                    .filter(|&&e| {
-                        card_query
-                            .get(e)
-                            .ok()
-                            .map(|c| c.is_type(&CLAY) || c.is_type(&COIN))
-                            .unwrap_or(false)
-                    })
-                    .copied()
-                    .collect();
-
-                delete_cards(&mut commands, &cards_to_be_deleted, root, stack);
-
-                creation.send(CreateStackEvent {
-                    position: global_transform.translation.truncate(),
-                    card_type: &VILLAGER,
+                    card_type: pack,
                     amount: 1,
+                    feed_waiting_stack: false,
                 });
             }
         },
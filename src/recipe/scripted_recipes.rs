@@ -0,0 +1,501 @@
+use crate::card_types::CardTypes;
+use crate::procedural::{HashedRng, SeededHasherResource};
+use crate::recipe::{
+    FinishRecipeMarker, RecipeAssets, RecipeUses, Recipes, RecipesBuilder, StackCheck,
+};
+use crate::stack::stack_utils::delete_cards;
+use crate::stack::{Card, CardStack, CreateStackEvent};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, ParseError, Scope, AST};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Extension of files containing a [RecipeScript].
+const RECIPE_SCRIPT_FILE_EXTENSION: &str = "recipe.rhai";
+
+/// Limits how much work a single script call can do, so a broken script can't freeze the game.
+const MAX_SCRIPT_OPERATIONS: u64 = 100_000;
+
+/// Name of the script function that decides whether a stack is valid for the recipe.
+/// Is called with an array of cards, bottom card first.
+const IS_VALID_FUNCTION: &str = "is_valid";
+/// Name of the script function that is called when the recipe finishes.
+/// Is called with `this` set to the [ScriptedStack] the recipe finished on.
+const FINISH_FUNCTION: &str = "finish";
+/// Name of the script constant with the time the recipe takes, in seconds.
+/// When a script does not define it, the recipe is instant.
+const SECONDS_CONSTANT: &str = "SECONDS";
+
+/// A recipe written in a [rhai](https://rhai.rs) script, see [RecipesBuilder::add_scripted_recipe].
+#[derive(TypeUuid, Clone, Debug)]
+#[uuid = "a9181726-5204-412f-8d81-151ff7c3a25e"]
+pub struct RecipeScript {
+    pub id: String,
+    pub source: String,
+}
+
+impl RecipeScript {
+    /// The id of the recipe is taken from the file name, so `plant_tree.recipe.rhai` contains
+    /// the recipe `plant_tree`. Returns `None` when the file is not named like that.
+    pub fn from_file(path: &Path, source: String) -> Option<Self> {
+        let id = path
+            .file_name()?
+            .to_str()?
+            .strip_suffix(RECIPE_SCRIPT_FILE_EXTENSION)?
+            .strip_suffix('.')
+            .filter(|id| !id.is_empty())?;
+
+        Some(RecipeScript {
+            id: id.to_string(),
+            source,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct RecipeScriptLoader;
+
+impl AssetLoader for RecipeScriptLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let source = String::from_utf8(bytes.to_vec())?;
+            let script = RecipeScript::from_file(load_context.path(), source).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "`{}` is not named after a recipe, like `plant_tree.{}`",
+                    load_context.path().display(),
+                    RECIPE_SCRIPT_FILE_EXTENSION
+                )
+            })?;
+            load_context.set_default_asset(LoadedAsset::new(script));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[RECIPE_SCRIPT_FILE_EXTENSION]
+    }
+}
+
+/// A stack, as seen by a script when a recipe finishes.
+/// Scripts cannot change the world directly. Instead the changes they ask for are collected,
+/// and applied once the script is done.
+#[derive(Clone)]
+pub struct ScriptedStack {
+    /// Bottom card first. Each card is a map with `type_id`, `category`, `value` and `uses`.
+    cards: Array,
+    rng: HashedRng,
+    spawns: Vec<(String, usize)>,
+    deletions: Vec<usize>,
+    uses: Vec<(usize, u32)>,
+}
+
+impl ScriptedStack {
+    fn new(cards: Array, rng: HashedRng) -> Self {
+        ScriptedStack {
+            cards,
+            rng,
+            spawns: vec![],
+            deletions: vec![],
+            uses: vec![],
+        }
+    }
+}
+
+/// Converts a card to what a script gets to see of it.
+fn card_to_script(card: &Card, uses: Option<u32>) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("type_id".into(), Dynamic::from(card.type_id.0.to_string()));
+    map.insert(
+        "category".into(),
+        Dynamic::from(format!("{:?}", card.category)),
+    );
+    map.insert(
+        "value".into(),
+        card.value
+            .map_or(Dynamic::UNIT, |v| Dynamic::from(v as i64)),
+    );
+    map.insert(
+        "uses".into(),
+        uses.map_or(Dynamic::UNIT, |u| Dynamic::from(u as i64)),
+    );
+    Dynamic::from(map)
+}
+
+/// The engine only exposes the functions registered here, so scripts have no access to
+/// the file system or the rest of the game.
+fn new_script_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+
+    engine
+        .register_type_with_name::<ScriptedStack>("Stack")
+        .register_get("cards", |stack: &mut ScriptedStack| stack.cards.clone())
+        .register_fn(
+            "spawn",
+            |stack: &mut ScriptedStack, card_type: &str, amount: i64| {
+                stack
+                    .spawns
+                    .push((card_type.to_string(), amount.max(0) as usize));
+            },
+        )
+        .register_fn("delete", |stack: &mut ScriptedStack, index: i64| {
+            stack.deletions.push(index as usize);
+        })
+        .register_fn(
+            "set_uses",
+            |stack: &mut ScriptedStack, index: i64, uses: i64| {
+                stack.uses.push((index as usize, uses.max(0) as u32));
+            },
+        )
+        // Random number in the range `min..max`.
+        .register_fn("random", |stack: &mut ScriptedStack, min: i64, max: i64| {
+            if max <= min {
+                return min;
+            }
+            min + stack.rng.value_in_range(0..(max - min) as usize) as i64
+        });
+
+    engine
+}
+
+fn call_is_valid(
+    engine: &Engine,
+    ast: &AST,
+    cards: &StackCheck,
+) -> Result<bool, Box<EvalAltResult>> {
+    let cards: Array = cards
+        .iter()
+        .map(|card| card_to_script(card, None))
+        .collect();
+    engine.call_fn(&mut Scope::new(), ast, IS_VALID_FUNCTION, (cards,))
+}
+
+/// Runs the finish function, and returns the stack with the changes the script asked for.
+fn call_finish(
+    engine: &Engine,
+    ast: &AST,
+    stack: ScriptedStack,
+) -> Result<ScriptedStack, Box<EvalAltResult>> {
+    let mut this = Dynamic::from(stack);
+    let options = CallFnOptions::new()
+        .eval_ast(false)
+        .rewind_scope(true)
+        .bind_this_ptr(&mut this);
+    engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, FINISH_FUNCTION, ())?;
+    this.try_cast::<ScriptedStack>()
+        .ok_or_else(|| "`this` was replaced by the script".into())
+}
+
+/// Reads the time a recipe takes from the `SECONDS` constant of its script.
+fn seconds_of_script(ast: &AST) -> Option<f32> {
+    let (_, _, value) = ast
+        .iter_literal_variables(true, false)
+        .find(|(name, ..)| *name == SECONDS_CONSTANT)?;
+    value
+        .as_float()
+        .ok()
+        .or_else(|| value.as_int().ok().map(|seconds| seconds as f64))
+        .map(|seconds| seconds as f32)
+}
+
+impl<'a> RecipesBuilder<'a> {
+    /// Adds a recipe whose behaviour is written in a [rhai](https://rhai.rs) script.
+    /// The time the recipe takes is the `SECONDS` constant of the script.
+    /// When there is no such constant the recipe is instant.
+    ///
+    /// The script defines two functions:
+    /// - `is_valid(cards)`: Gets the cards of the stack, bottom card first,
+    ///   and returns whether the recipe can be done on them.
+    ///   Each card is a map with the `type_id`, `category`, `value` and `uses` of the card.
+    /// - `finish()`: Called when the recipe is done. `this` is the stack, which has
+    ///   `this.cards`, `this.spawn(card_type, amount)`, `this.delete(card_index)`,
+    ///   `this.set_uses(card_index, uses)` and `this.random(min, max)`.
+    ///
+    /// ```rhai
+    /// const SECONDS = 3.0;
+    ///
+    /// fn is_valid(cards) {
+    ///     cards.len() == 2 && cards.all(|card| card.type_id == "log")
+    /// }
+    ///
+    /// fn finish() {
+    ///     this.delete(0);
+    ///     this.delete(1);
+    ///     this.spawn("plank", this.random(1, 3));
+    /// }
+    /// ```
+    pub fn add_scripted_recipe(
+        &mut self,
+        id: &'static str,
+        script: &str,
+    ) -> Result<(), ParseError> {
+        let engine = Arc::new(new_script_engine());
+        let ast = Arc::new(engine.compile(script)?);
+        let seconds = seconds_of_script(&ast);
+
+        let valid_engine = engine.clone();
+        let valid_ast = ast.clone();
+
+        self.new_recipe(
+            id,
+            seconds,
+            move |cards| {
+                call_is_valid(&valid_engine, &valid_ast, cards).unwrap_or_else(|e| {
+                    warn!("Script of recipe `{}` failed: {}", id, e);
+                    false
+                })
+            },
+            move |mut commands: Commands,
+                  recipe_stack_query: Query<
+                (Entity, &CardStack, &GlobalTransform),
+                With<FinishRecipeMarker>,
+            >,
+                  mut card_query: Query<(&Card, Option<&mut RecipeUses>)>,
+                  seeded_hash: Res<SeededHasherResource>,
+                  card_types: Res<CardTypes>,
+                  mut creation: EventWriter<CreateStackEvent>| {
+                for (root, stack, global_transform) in recipe_stack_query.iter() {
+                    let mut rng = seeded_hash.with(root);
+                    rng.with(id);
+
+                    let cards: Array = stack
+                        .iter()
+                        .filter_map(|&e| card_query.get(e).ok())
+                        .map(|(card, maybe_uses)| card_to_script(card, maybe_uses.map(|u| u.0)))
+                        .collect();
+
+                    let result = match call_finish(&engine, &ast, ScriptedStack::new(cards, rng)) {
+                        Ok(result) => result,
+                        Err(e) => {
+                            warn!("Script of recipe `{}` failed: {}", id, e);
+                            continue;
+                        }
+                    };
+
+                    for (index, uses) in result.uses {
+                        if let Some(Ok((_, Some(mut card_uses)))) =
+                            stack.get(index).map(|&e| card_query.get_mut(e))
+                        {
+                            card_uses.0 = uses;
+                        }
+                    }
+
+                    let mut cards_to_delete: Vec<Entity> = result
+                        .deletions
+                        .iter()
+                        .filter_map(|&index| stack.get(index).copied())
+                        .collect();
+                    cards_to_delete.sort();
+                    cards_to_delete.dedup();
+                    if !cards_to_delete.is_empty() {
+                        delete_cards(&mut commands, &cards_to_delete, root, stack);
+                    }
+
+                    for (card_type_name, amount) in result.spawns {
+                        if let Some(card_type) = card_types.get_by_name(&card_type_name) {
+                            creation.send(CreateStackEvent {
+                                position: global_transform.translation.truncate(),
+                                card_type: card_type.id,
+                                amount,
+                                feed_waiting_stack: false,
+                            });
+                        } else {
+                            warn!(
+                                "Recipe `{}` tried to create unknown card type `{}`",
+                                id, card_type_name
+                            );
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(())
+    }
+}
+
+/// Adds the recipes from the recipe script files to the [Recipes] resource.
+/// Has to be exclusive, because creating a recipe needs full access to the [World].
+pub fn recipe_scripts_loading_system(world: &mut World) {
+    let handles = world.resource::<RecipeAssets>().scripts.clone();
+    let scripts: Vec<RecipeScript> = {
+        let assets = world.resource::<Assets<RecipeScript>>();
+        handles
+            .iter()
+            .filter_map(|handle| assets.get(handle))
+            .cloned()
+            .collect()
+    };
+
+    add_recipe_scripts(world, scripts);
+}
+
+/// Reads every recipe script in the given folder, without going through the asset server.
+pub fn read_recipe_scripts(folder: &Path) -> std::io::Result<Vec<RecipeScript>> {
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(folder)? {
+        paths.push(entry?.path());
+    }
+    // Sorted, so the recipes are always added in the same order.
+    paths.sort();
+
+    let mut scripts = vec![];
+    for path in paths {
+        let source = std::fs::read_to_string(&path)?;
+        if let Some(script) = RecipeScript::from_file(&path, source) {
+            scripts.push(script);
+        }
+    }
+    Ok(scripts)
+}
+
+/// Adds the given scripted recipes to the [Recipes] resource.
+/// Scripts that do not compile are left out, and an error is logged for them.
+pub fn add_recipe_scripts(world: &mut World, scripts: Vec<RecipeScript>) {
+    let recipes = world.remove_resource::<Recipes>().unwrap_or_default();
+    let mut builder = RecipesBuilder::from_recipes(world, recipes);
+    for script in scripts {
+        // Recipe ids are used for the lifetime of the game, and are only loaded once.
+        let id: &'static str = Box::leak(script.id.into_boxed_str());
+        if let Err(e) = builder.add_scripted_recipe(id, &script.source) {
+            error!("Could not compile the script of recipe `{}`: {}", id, e);
+        }
+    }
+    let recipes = builder.build();
+    world.insert_resource(recipes);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::card_types::{CardCategory, CardTypeId};
+    use crate::procedural::SeededHasherResource;
+    use crate::recipe::scripted_recipes::{
+        add_recipe_scripts, call_finish, call_is_valid, card_to_script, new_script_engine,
+        read_recipe_scripts, RecipeScript, ScriptedStack,
+    };
+    use crate::recipe::{RecipeId, Recipes, StackCheck};
+    use crate::stack::Card;
+    use bevy::prelude::*;
+    use std::path::Path;
+
+    const SCRIPT: &str = r#"
+        fn is_valid(cards) {
+            cards.len() == 2 && cards.all(|card| card.type_id == "log")
+        }
+
+        fn finish() {
+            this.delete(0);
+            this.set_uses(1, this.cards[1].uses - 1);
+            this.spawn("plank", this.random(1, 3));
+        }
+    "#;
+
+    fn card(type_id: &'static str, category: CardCategory) -> Card {
+        Card {
+            type_id: CardTypeId(type_id),
+            category,
+            value: Some(1),
+            buys: None,
+        }
+    }
+
+    fn log() -> Card {
+        card("log", CardCategory::Resource)
+    }
+
+    #[test]
+    fn test_scripted_recipe() {
+        let engine = new_script_engine();
+        let ast = engine.compile(SCRIPT).unwrap();
+
+        assert!(call_is_valid(&engine, &ast, &StackCheck(vec![log(), log()])).unwrap());
+        assert!(!call_is_valid(&engine, &ast, &StackCheck(vec![log()])).unwrap());
+
+        let cards = vec![
+            card_to_script(&log(), None),
+            card_to_script(&log(), Some(3)),
+        ];
+        let rng = SeededHasherResource::new(42).with(0);
+        let result = call_finish(&engine, &ast, ScriptedStack::new(cards, rng)).unwrap();
+
+        assert_eq!(result.deletions, vec![0]);
+        assert_eq!(result.uses, vec![(1, 2)]);
+        assert_eq!(result.spawns.len(), 1);
+        let (card_type, amount) = &result.spawns[0];
+        assert_eq!(card_type, "plank");
+        assert!((1..3).contains(amount));
+    }
+
+    #[test]
+    fn test_recipe_id_is_taken_from_file_name() {
+        let script = |path: &str| {
+            RecipeScript::from_file(Path::new(path), String::new()).map(|script| script.id)
+        };
+
+        assert_eq!(
+            script("recipes/scripts/plant_tree.recipe.rhai"),
+            Some("plant_tree".to_string())
+        );
+        assert_eq!(script("recipes/scripts/.recipe.rhai"), None);
+        assert_eq!(script("recipes/scripts/plant_tree.rhai"), None);
+        assert_eq!(script("recipes/base.recipes.ron"), None);
+    }
+
+    #[test]
+    fn test_recipe_scripts_in_the_assets_load() {
+        let scripts = read_recipe_scripts(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/recipes/scripts"),
+        )
+        .unwrap();
+        let source = scripts
+            .iter()
+            .find(|script| script.id == "plant_tree")
+            .unwrap()
+            .source
+            .clone();
+
+        let mut world = World::default();
+        add_recipe_scripts(&mut world, scripts);
+
+        let recipes = world.resource::<Recipes>();
+        let plant_tree = recipes.get(&RecipeId("plant_tree")).unwrap();
+        assert_eq!(plant_tree.seconds, Some(4.0));
+
+        let villager = card("villager", CardCategory::Worker);
+        let apple = card("apple", CardCategory::Food);
+        assert!((plant_tree.is_valid)(&StackCheck(vec![
+            villager, apple, apple
+        ])));
+        assert!(!(plant_tree.is_valid)(&StackCheck(vec![villager])));
+        assert!(!(plant_tree.is_valid)(&StackCheck(vec![
+            villager, villager, apple
+        ])));
+        assert!(!(plant_tree.is_valid)(&StackCheck(vec![
+            villager,
+            apple,
+            log()
+        ])));
+
+        // All apples are planted.
+        let engine = new_script_engine();
+        let ast = engine.compile(source).unwrap();
+        let cards = vec![
+            card_to_script(&villager, None),
+            card_to_script(&apple, None),
+            card_to_script(&apple, None),
+        ];
+        let rng = SeededHasherResource::new(42).with(0);
+        let result = call_finish(&engine, &ast, ScriptedStack::new(cards, rng)).unwrap();
+        assert_eq!(result.deletions, vec![1, 2]);
+        assert!(result
+            .spawns
+            .iter()
+            .all(|(card_type, amount)| card_type == "tree" && (1..=2).contains(amount)));
+    }
+}
@@ -0,0 +1,552 @@
+use crate::card_packs::CardPack;
+use crate::card_types::CardTypes;
+use crate::history::History;
+use crate::localization::Localizer;
+use crate::procedural::SeededHasherResource;
+use crate::recipe::{OngoingRecipe, RecipeInstanceId, RecipeRepetitions, RecipeUses, Recipes};
+use crate::stack::stack_utils::spawn_stack;
+use crate::stack::{Card, CardFonts, CardImages, CardStack, CardVisualSize};
+use crate::{GameState, TimeOfDay};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Version of the save game format that is written by this version of the game.
+/// Bump this whenever the format changes, and add a migration to [SAVE_GAME_MIGRATIONS].
+pub const SAVE_GAME_VERSION: u32 = 2;
+
+/// Migrations that bring an older save game up to date.
+/// The migration at index `i` converts a save of version `i + 1` to version `i + 2`.
+/// Migrations work on the raw json, so they don't need to know about older versions of the
+/// [SaveGame] structure.
+const SAVE_GAME_MIGRATIONS: &[fn(&mut Value)] = &[add_recipe_instances_and_repetitions];
+
+const SAVE_GAME_FOLDER: &str = "the_stacks";
+const SAVE_GAME_FILE_NAME: &str = "save_game.json";
+
+pub struct SaveGamePlugin;
+
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveGameEvent>()
+            .add_event::<LoadGameEvent>()
+            .add_system_set(
+                SystemSet::on_update(GameState::PauseMenu)
+                    .with_system(save_game_system)
+                    .with_system(load_game_system),
+            );
+    }
+}
+
+/// Event that requests the current board to be written to the save game file.
+pub struct SaveGameEvent;
+
+/// Event that requests the current board to be replaced by the one in the save game file.
+pub struct LoadGameEvent;
+
+/// Everything needed to rebuild the board.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SaveGame {
+    pub version: u32,
+    pub seed: u32,
+    pub day: u32,
+    pub time_of_day: f32,
+    /// In the order they should be spawned.
+    pub stacks: Vec<SavedStack>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SavedStack {
+    pub position: (f32, f32),
+    /// Bottom card first.
+    pub cards: Vec<SavedCard>,
+    pub ongoing_recipe: Option<SavedRecipe>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SavedCard {
+    pub type_id: String,
+    #[serde(default)]
+    pub recipe_uses: Option<u32>,
+    /// Amount of cards left, if this card is a card pack.
+    #[serde(default)]
+    pub card_pack_cards: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SavedRecipe {
+    pub id: String,
+    pub elapsed_seconds: f32,
+    /// See [RecipeInstanceId].
+    pub instance: u64,
+    /// How many more times the recipe starts again, if it does not repeat for as long as the
+    /// stack is valid. See [RecipeRepetitions].
+    pub repetitions_left: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum SaveGameError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    MissingVersion,
+    /// The save was made by a newer version of the game.
+    UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for SaveGameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveGameError::Io(e) => write!(f, "{}", e),
+            SaveGameError::Json(e) => write!(f, "{}", e),
+            SaveGameError::MissingVersion => write!(f, "save game has no version"),
+            SaveGameError::UnsupportedVersion(version) => write!(
+                f,
+                "save game version {} is newer than the supported version {}",
+                version, SAVE_GAME_VERSION
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for SaveGameError {
+    fn from(e: std::io::Error) -> Self {
+        SaveGameError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SaveGameError {
+    fn from(e: serde_json::Error) -> Self {
+        SaveGameError::Json(e)
+    }
+}
+
+impl SaveGame {
+    pub fn to_json(&self) -> Result<String, SaveGameError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parses a save game, migrating it to the current [SAVE_GAME_VERSION] if it is older.
+    pub fn from_json(json: &str) -> Result<Self, SaveGameError> {
+        let mut value: Value = serde_json::from_str(json)?;
+        migrate_save_game(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// Applies all the migrations needed to bring the save game up to the current version.
+fn migrate_save_game(save: &mut Value) -> Result<(), SaveGameError> {
+    apply_migrations(save, SAVE_GAME_MIGRATIONS, SAVE_GAME_VERSION)
+}
+
+/// Applies the `migrations` that are newer than the version of the save, in order,
+/// and marks it as `current_version`. See [SAVE_GAME_MIGRATIONS] for how they are indexed.
+fn apply_migrations(
+    save: &mut Value,
+    migrations: &[fn(&mut Value)],
+    current_version: u32,
+) -> Result<(), SaveGameError> {
+    let version = save
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or(SaveGameError::MissingVersion)? as u32;
+
+    if version > current_version {
+        return Err(SaveGameError::UnsupportedVersion(version));
+    }
+
+    for (from_version, migration) in migrations.iter().enumerate() {
+        if version <= from_version as u32 + 1 {
+            migration(save);
+        }
+    }
+    save["version"] = Value::from(current_version);
+
+    Ok(())
+}
+
+/// Version 1 to 2: Adds the [RecipeInstanceId] and [RecipeRepetitions] of ongoing recipes.
+/// Recipes of version 1 always repeated for as long as the stack was valid.
+fn add_recipe_instances_and_repetitions(save: &mut Value) {
+    let recipes = save
+        .get_mut("stacks")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(|stack| stack.get_mut("ongoing_recipe"))
+        .filter(|recipe| recipe.is_object());
+
+    for (instance, recipe) in recipes.enumerate() {
+        recipe["instance"] = Value::from(instance as u64);
+        recipe["repetitions_left"] = Value::Null;
+    }
+}
+
+/// Location of the save game. Falls back on the working directory if there is no
+/// data directory on this platform.
+fn save_game_path() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join(SAVE_GAME_FOLDER))
+        .unwrap_or_default()
+        .join(SAVE_GAME_FILE_NAME)
+}
+
+pub fn save_game_system(
+    mut events: EventReader<SaveGameEvent>,
+    stack_query: Query<(
+        &CardStack,
+        &Transform,
+        Option<&OngoingRecipe>,
+        Option<&RecipeRepetitions>,
+    )>,
+    card_query: Query<(&Card, Option<&RecipeUses>, Option<&CardPack>)>,
+    seeded_hasher: Res<SeededHasherResource>,
+    time_of_day: Res<TimeOfDay>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    let mut stacks: Vec<(f32, SavedStack)> = stack_query
+        .iter()
+        .map(|(stack, transform, maybe_recipe, maybe_repetitions)| {
+            let cards = stack
+                .iter()
+                .filter_map(|&e| card_query.get(e).ok())
+                .map(|(card, maybe_uses, maybe_pack)| SavedCard {
+                    type_id: card.type_id.0.to_string(),
+                    recipe_uses: maybe_uses.map(|uses| uses.0),
+                    card_pack_cards: maybe_pack.map(|pack| pack.cards),
+                })
+                .collect();
+
+            let saved_stack = SavedStack {
+                position: (transform.translation.x, transform.translation.y),
+                cards,
+                ongoing_recipe: maybe_recipe.map(|recipe| SavedRecipe {
+                    id: recipe.id.0.to_string(),
+                    elapsed_seconds: recipe.timer.elapsed_secs(),
+                    instance: recipe.instance.0,
+                    repetitions_left: maybe_repetitions
+                        .filter(|repetitions| repetitions.id == recipe.id)
+                        .and_then(|repetitions| repetitions.left),
+                }),
+            };
+            (transform.translation.z, saved_stack)
+        })
+        .collect();
+    // Stacks are saved from the lowest to the highest, so they keep their overlap order.
+    stacks.sort_by(|(z1, _), (z2, _)| z1.total_cmp(z2));
+
+    let save = SaveGame {
+        version: SAVE_GAME_VERSION,
+        seed: seeded_hasher.seed(),
+        day: time_of_day.day,
+        time_of_day: time_of_day.time_of_day,
+        stacks: stacks.into_iter().map(|(_, stack)| stack).collect(),
+    };
+
+    let path = save_game_path();
+    let result = save.to_json().and_then(|json| {
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        Ok(fs::write(&path, json)?)
+    });
+
+    match result {
+        Ok(()) => info!("Saved game to `{}`", path.display()),
+        Err(e) => error!("Could not save game to `{}`: {}", path.display(), e),
+    }
+}
+
+/// Replaces the whole board with the one from the save game.
+/// Card types and recipes that no longer exist are skipped.
+pub fn load_game_system(
+    mut commands: Commands,
+    mut events: EventReader<LoadGameEvent>,
+    existing_stacks: Query<Entity, With<CardStack>>,
+    recipes: Res<Recipes>,
+    mut seeded_hasher: ResMut<SeededHasherResource>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    card_types: Res<CardTypes>,
+    card_images: Res<CardImages>,
+    card_fonts: Res<CardFonts>,
+    visual_size: Res<CardVisualSize>,
+    localizer: Res<Localizer>,
+    mut history: ResMut<History>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    let path = save_game_path();
+    let save = match fs::read_to_string(&path)
+        .map_err(SaveGameError::from)
+        .and_then(|json| SaveGame::from_json(&json))
+    {
+        Ok(save) => save,
+        Err(e) => {
+            error!("Could not load game from `{}`: {}", path.display(), e);
+            return;
+        }
+    };
+
+    for root in existing_stacks.iter() {
+        commands.entity(root).despawn_recursive();
+    }
+    // The history refers to the cards that were just removed.
+    history.clear();
+
+    *seeded_hasher = SeededHasherResource::new(save.seed);
+    time_of_day.day = save.day;
+    time_of_day.time_of_day = save.time_of_day;
+
+    for saved_stack in save.stacks {
+        let (cards, card_types): (Vec<&SavedCard>, Vec<_>) = saved_stack
+            .cards
+            .iter()
+            .filter_map(|saved_card| {
+                let maybe_card_type = card_types.get_by_name(&saved_card.type_id);
+                if maybe_card_type.is_none() {
+                    warn!(
+                        "Card type `{}` no longer exists, skipping it while loading",
+                        saved_card.type_id
+                    );
+                }
+                maybe_card_type.map(|card_type| (saved_card, card_type))
+            })
+            .unzip();
+
+        let (x, y) = saved_stack.position;
+        let (root, card_entities) = match spawn_stack(
+            &mut commands,
+            Vec2::new(x, y),
+            &card_types,
+            false,
+            &card_images,
+            &card_fonts,
+            visual_size.0,
+            &localizer,
+        ) {
+            Some(spawned) => spawned,
+            None => continue,
+        };
+
+        // These are inserted after the card type's starting components, so they overwrite them.
+        for (saved_card, entity) in cards.iter().zip(card_entities) {
+            if let Some(uses) = saved_card.recipe_uses {
+                commands.entity(entity).insert(RecipeUses(uses));
+            }
+            if let Some(cards) = saved_card.card_pack_cards {
+                commands.entity(entity).insert(CardPack { cards });
+            }
+        }
+
+        if let Some(saved_recipe) = saved_stack.ongoing_recipe {
+            let maybe_recipe = recipes
+                .iter()
+                .find(|(id, _)| id.0 == saved_recipe.id)
+                .and_then(|(&id, recipe)| recipe.seconds.map(|seconds| (id, seconds)));
+
+            if let Some((id, seconds)) = maybe_recipe {
+                let mut timer = Timer::new(Duration::from_secs_f32(seconds), false);
+                timer.set_elapsed(Duration::from_secs_f32(saved_recipe.elapsed_seconds));
+                commands.entity(root).insert(OngoingRecipe {
+                    id,
+                    instance: RecipeInstanceId::reserve(saved_recipe.instance),
+                    timer,
+                    paused: None,
+                });
+                if let Some(left) = saved_recipe.repetitions_left {
+                    commands.entity(root).insert(RecipeRepetitions {
+                        id,
+                        left: Some(left),
+                    });
+                }
+            } else {
+                warn!(
+                    "Recipe `{}` no longer exists, it will not be resumed",
+                    saved_recipe.id
+                );
+            }
+        }
+    }
+
+    info!("Loaded game from `{}`", path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::save_game::{
+        apply_migrations, SaveGame, SaveGameError, SavedCard, SavedRecipe, SavedStack,
+        SAVE_GAME_MIGRATIONS, SAVE_GAME_VERSION,
+    };
+    use serde_json::{json, Value};
+
+    fn example_save() -> SaveGame {
+        SaveGame {
+            version: SAVE_GAME_VERSION,
+            seed: 42,
+            day: 3,
+            time_of_day: 0.5,
+            stacks: vec![SavedStack {
+                position: (10.0, -20.0),
+                cards: vec![
+                    SavedCard {
+                        type_id: "tree".to_string(),
+                        recipe_uses: Some(2),
+                        card_pack_cards: None,
+                    },
+                    SavedCard {
+                        type_id: "villager".to_string(),
+                        recipe_uses: None,
+                        card_pack_cards: None,
+                    },
+                ],
+                ongoing_recipe: Some(SavedRecipe {
+                    id: "cut_tree".to_string(),
+                    elapsed_seconds: 1.5,
+                    instance: 7,
+                    repetitions_left: Some(2),
+                }),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_save_game_round_trip() {
+        let save = example_save();
+        let loaded = SaveGame::from_json(&save.to_json().unwrap()).unwrap();
+        assert_eq!(loaded, save);
+    }
+
+    #[test]
+    fn test_save_game_from_the_future_is_rejected() {
+        let json = save_with_version(SAVE_GAME_VERSION + 1);
+        assert!(matches!(
+            SaveGame::from_json(&json),
+            Err(SaveGameError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_every_older_version_has_a_migration() {
+        assert_eq!(SAVE_GAME_MIGRATIONS.len() as u32 + 1, SAVE_GAME_VERSION);
+    }
+
+    #[test]
+    fn test_version_1_save_game_is_migrated() {
+        // Written by hand, the way version 1 of the game would have saved it.
+        // Card types that no longer exist are skipped when the board is rebuilt,
+        // so they have to survive parsing.
+        let json = r#"{
+            "version": 1,
+            "seed": 42,
+            "day": 3,
+            "time_of_day": 0.5,
+            "stacks": [
+                {
+                    "position": [10.0, -20.0],
+                    "cards": [
+                        {"type_id": "tree"},
+                        {"type_id": "removed_card_type"}
+                    ],
+                    "ongoing_recipe": {"id": "cut_tree", "elapsed_seconds": 1.5}
+                },
+                {
+                    "position": [0.0, 0.0],
+                    "cards": [{"type_id": "villager"}],
+                    "ongoing_recipe": null
+                },
+                {
+                    "position": [30.0, 0.0],
+                    "cards": [{"type_id": "villager"}, {"type_id": "clay_patch"}],
+                    "ongoing_recipe": {"id": "gather_clay", "elapsed_seconds": 0.5}
+                }
+            ]
+        }"#;
+
+        let save = SaveGame::from_json(json).unwrap();
+        assert_eq!(save.version, SAVE_GAME_VERSION);
+        let stack = &save.stacks[0];
+        assert_eq!(stack.cards[1].type_id, "removed_card_type");
+        assert!(stack
+            .cards
+            .iter()
+            .all(|card| card.recipe_uses.is_none() && card.card_pack_cards.is_none()));
+
+        assert!(save.stacks[1].ongoing_recipe.is_none());
+        let recipes: Vec<&SavedRecipe> = save
+            .stacks
+            .iter()
+            .filter_map(|stack| stack.ongoing_recipe.as_ref())
+            .collect();
+        assert_eq!(recipes.len(), 2);
+        // Every recipe gets its own instance, and keeps repeating like it used to.
+        assert_ne!(recipes[0].instance, recipes[1].instance);
+        assert!(recipes
+            .iter()
+            .all(|recipe| recipe.repetitions_left.is_none()));
+    }
+
+    #[test]
+    fn test_recipe_without_instance_is_rejected() {
+        // Version 2 always has recipe instances.
+        let json = r#"{
+            "version": 2,
+            "seed": 42,
+            "day": 3,
+            "time_of_day": 0.5,
+            "stacks": [{
+                "position": [10.0, -20.0],
+                "cards": [{"type_id": "tree"}],
+                "ongoing_recipe": {"id": "cut_tree", "elapsed_seconds": 1.5}
+            }]
+        }"#;
+
+        assert!(matches!(
+            SaveGame::from_json(json),
+            Err(SaveGameError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn test_only_newer_migrations_are_applied_in_order() {
+        fn applied(save: &mut Value, name: &str) {
+            save["applied"].as_array_mut().unwrap().push(json!(name));
+        }
+        let migrations: &[fn(&mut Value)] = &[
+            |save| applied(save, "1 to 2"),
+            |save| applied(save, "2 to 3"),
+        ];
+
+        let mut from_first = json!({"version": 1, "applied": []});
+        apply_migrations(&mut from_first, migrations, 3).unwrap();
+        assert_eq!(
+            from_first,
+            json!({"version": 3, "applied": ["1 to 2", "2 to 3"]})
+        );
+
+        let mut from_second = json!({"version": 2, "applied": []});
+        apply_migrations(&mut from_second, migrations, 3).unwrap();
+        assert_eq!(from_second, json!({"version": 3, "applied": ["2 to 3"]}));
+
+        let mut current = json!({"version": 3, "applied": []});
+        apply_migrations(&mut current, migrations, 3).unwrap();
+        assert_eq!(current, json!({"version": 3, "applied": []}));
+
+        assert!(matches!(
+            apply_migrations(&mut json!({}), migrations, 3),
+            Err(SaveGameError::MissingVersion)
+        ));
+    }
+
+    fn save_with_version(version: u32) -> String {
+        let mut save = example_save();
+        save.version = version;
+        save.to_json().unwrap()
+    }
+}
@@ -0,0 +1,344 @@
+//! Settings that are kept between games, such as the language and the window size.
+
+use crate::camera::{MAX_ZOOMED_IN_SCALE, MAX_ZOOMED_OUT_SCALE, START_ZOOM};
+use crate::localization::Localizer;
+use crate::{GameState, LengthOfDay, DEFAULT_LENGTH_OF_DAY};
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::window::{WindowId, WindowMode, WindowResized};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use unic_langid::LanguageIdentifier;
+
+const CONFIG_FOLDER: &str = "the_stacks";
+const SETTINGS_FILE_NAME: &str = "settings.ron";
+/// A settings file that cannot be read is moved aside under this extension, instead of being
+/// overwritten, so the player can still fix it by hand.
+const CORRUPT_SETTINGS_EXTENSION: &str = "ron.corrupt";
+
+pub const LENGTH_OF_DAY_RANGE: RangeInclusive<f32> = 30.0..=600.0;
+pub const MIN_WINDOW_SIZE: f32 = 400.0;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Settings::load())
+            .add_system(apply_settings_system)
+            .add_system(window_resized_system)
+            .add_system_to_stage(CoreStage::Last, save_settings_on_exit_system)
+            .add_system_set(
+                SystemSet::on_exit(GameState::PauseMenu).with_system(save_settings_system),
+            );
+    }
+}
+
+/// Resource with the settings of the player.
+/// Settings that are missing from the file get their default value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    /// Like `en-US`. The default language is used when `None`.
+    pub language: Option<String>,
+    /// Zoom of the camera when a game starts.
+    pub start_zoom: f32,
+    /// In in-game seconds.
+    pub length_of_day: f32,
+    pub window: WindowSettings,
+    pub audio: AudioSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            language: None,
+            start_zoom: START_ZOOM,
+            length_of_day: DEFAULT_LENGTH_OF_DAY,
+            window: WindowSettings::default(),
+            audio: AudioSettings::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct WindowSettings {
+    /// Size when not fullscreen, in logical pixels.
+    pub width: f32,
+    pub height: f32,
+    pub fullscreen: bool,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        WindowSettings {
+            width: 1280.0,
+            height: 720.0,
+            fullscreen: false,
+        }
+    }
+}
+
+/// Volumes go from 0 to 1.
+/// The game does not play sounds yet, these are stored so they are in place when it does.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub effects_volume: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            master_volume: 1.0,
+            music_volume: 0.8,
+            effects_volume: 0.8,
+        }
+    }
+}
+
+impl Settings {
+    /// Parses the settings, and brings values that are out of range back to their default or
+    /// nearest allowed value.
+    fn from_ron(text: &str) -> anyhow::Result<Self> {
+        let settings: Settings = ron::from_str(text)?;
+        Ok(settings.sanitized())
+    }
+
+    fn sanitized(mut self) -> Self {
+        let defaults = Settings::default();
+
+        self.start_zoom = clamp_or(
+            self.start_zoom,
+            MAX_ZOOMED_IN_SCALE..=MAX_ZOOMED_OUT_SCALE,
+            defaults.start_zoom,
+        );
+        self.length_of_day = clamp_or(
+            self.length_of_day,
+            LENGTH_OF_DAY_RANGE,
+            defaults.length_of_day,
+        );
+        self.window.width = clamp_or(
+            self.window.width,
+            MIN_WINDOW_SIZE..=f32::MAX,
+            defaults.window.width,
+        );
+        self.window.height = clamp_or(
+            self.window.height,
+            MIN_WINDOW_SIZE..=f32::MAX,
+            defaults.window.height,
+        );
+        for (volume, default) in [
+            (&mut self.audio.master_volume, defaults.audio.master_volume),
+            (&mut self.audio.music_volume, defaults.audio.music_volume),
+            (
+                &mut self.audio.effects_volume,
+                defaults.audio.effects_volume,
+            ),
+        ] {
+            *volume = clamp_or(*volume, 0.0..=1.0, default);
+        }
+
+        self
+    }
+
+    /// Falls back on the default settings when there is no settings file, or it cannot be read.
+    fn load() -> Self {
+        let path = settings_path();
+        if !path.exists() {
+            return Settings::default();
+        }
+
+        let result = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Settings::from_ron(&text));
+        match result {
+            Ok(settings) => settings,
+            Err(e) => {
+                let corrupt_path = path.with_extension(CORRUPT_SETTINGS_EXTENSION);
+                warn!(
+                    "Could not read settings from `{}`, using the defaults. The file is moved to `{}`: {}",
+                    path.display(),
+                    corrupt_path.display(),
+                    e
+                );
+                if let Err(e) = fs::rename(&path, &corrupt_path) {
+                    warn!("Could not move `{}`: {}", path.display(), e);
+                }
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = settings_path();
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(anyhow::Error::from)
+            .and_then(|text| {
+                if let Some(folder) = path.parent() {
+                    fs::create_dir_all(folder)?;
+                }
+                Ok(fs::write(&path, text)?)
+            });
+
+        match result {
+            Ok(()) => info!("Saved settings to `{}`", path.display()),
+            Err(e) => error!("Could not save settings to `{}`: {}", path.display(), e),
+        }
+    }
+}
+
+/// Values that are not a number get the default, others are clamped to the range.
+fn clamp_or(value: f32, range: RangeInclusive<f32>, default: f32) -> f32 {
+    if value.is_finite() {
+        value.clamp(*range.start(), *range.end())
+    } else {
+        default
+    }
+}
+
+/// Location of a file in the game's config directory. Falls back on the working directory if
+/// there is no config directory on this platform.
+pub fn config_path(file_name: &str) -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join(CONFIG_FOLDER))
+        .unwrap_or_default()
+        .join(file_name)
+}
+
+fn settings_path() -> PathBuf {
+    config_path(SETTINGS_FILE_NAME)
+}
+
+/// Applies the settings whenever they change.
+/// The [Localizer] is only there after startup, so the language is also set when it is added.
+fn apply_settings_system(
+    settings: Res<Settings>,
+    mut length_of_day: ResMut<LengthOfDay>,
+    mut windows: ResMut<Windows>,
+    maybe_localizer: Option<ResMut<Localizer>>,
+) {
+    if let Some(mut localizer) = maybe_localizer {
+        if settings.is_changed() || localizer.is_added() {
+            let maybe_language = settings
+                .language
+                .as_ref()
+                .and_then(|language| language.parse::<LanguageIdentifier>().ok());
+            if let Some(language) = maybe_language {
+                if language != localizer.current_language() {
+                    localizer.select_language(language);
+                }
+            }
+        }
+    }
+
+    if !settings.is_changed() {
+        return;
+    }
+
+    length_of_day.0 = settings.length_of_day;
+
+    if let Some(window) = windows.get_primary_mut() {
+        let mode = if settings.window.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        };
+        if window.mode() != mode {
+            window.set_mode(mode);
+        }
+        if mode == WindowMode::Windowed
+            && (window.width(), window.height()) != (settings.window.width, settings.window.height)
+        {
+            window.set_resolution(settings.window.width, settings.window.height);
+        }
+    }
+}
+
+/// Remembers the size of the window when the player resizes it.
+fn window_resized_system(
+    mut events: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    mut settings: ResMut<Settings>,
+) {
+    let maybe_size = events
+        .iter()
+        .filter(|event| event.id == WindowId::primary())
+        .last()
+        .map(|event| (event.width, event.height));
+    let windowed = windows
+        .get_primary()
+        .map_or(false, |window| window.mode() == WindowMode::Windowed);
+
+    if let (Some((width, height)), true) = (maybe_size, windowed) {
+        // Only touch the settings when the size really changed, to not trigger
+        // `apply_settings_system` for nothing.
+        if (settings.window.width, settings.window.height) != (width, height) {
+            settings.window.width = width.max(MIN_WINDOW_SIZE);
+            settings.window.height = height.max(MIN_WINDOW_SIZE);
+        }
+    }
+}
+
+/// Settings are saved when the pause menu, where most of them are changed, is closed.
+fn save_settings_system(settings: Res<Settings>) {
+    settings.save();
+}
+
+/// Also saves the settings when the game is closed, to keep the window size.
+fn save_settings_on_exit_system(mut events: EventReader<AppExit>, settings: Res<Settings>) {
+    if events.iter().count() > 0 {
+        settings.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::MAX_ZOOMED_OUT_SCALE;
+    use crate::settings::{Settings, LENGTH_OF_DAY_RANGE};
+
+    #[test]
+    fn test_missing_settings_get_default_values() {
+        let settings =
+            Settings::from_ron("(language: Some(\"nl-NL\"), window: (fullscreen: true))").unwrap();
+
+        let defaults = Settings::default();
+        assert_eq!(settings.language.as_deref(), Some("nl-NL"));
+        assert!(settings.window.fullscreen);
+        assert_eq!(settings.window.width, defaults.window.width);
+        assert_eq!(settings.length_of_day, defaults.length_of_day);
+        assert_eq!(settings.audio, defaults.audio);
+    }
+
+    #[test]
+    fn test_settings_out_of_range() {
+        let settings = Settings::from_ron(
+            "(length_of_day: 1.0, start_zoom: 100.0, audio: (music_volume: 3.0))",
+        )
+        .unwrap();
+
+        assert_eq!(settings.length_of_day, *LENGTH_OF_DAY_RANGE.start());
+        assert_eq!(settings.start_zoom, MAX_ZOOMED_OUT_SCALE);
+        assert_eq!(settings.audio.music_volume, 1.0);
+    }
+
+    #[test]
+    fn test_corrupt_settings() {
+        assert!(Settings::from_ron("(length_of_day: \"long\")").is_err());
+        assert!(Settings::from_ron("(window: (").is_err());
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let mut settings = Settings::default();
+        settings.language = Some("nl-NL".to_string());
+        settings.window.fullscreen = true;
+
+        let text = ron::to_string(&settings).unwrap();
+        assert_eq!(Settings::from_ron(&text).unwrap(), settings);
+    }
+}
@@ -1,24 +1,30 @@
+pub mod spatial_index;
 pub mod stack_utils;
 mod tests;
 
-use crate::card_packs::BUY_FOREST_PACK;
-use crate::card_types::{CardCategory, CardType, CLAY, CLAY_PATCH, COIN, MARKET, TREE, VILLAGER};
-use crate::localization::Localizer;
+use crate::card_types::{
+    CardCategory, CardTypeId, CardTypes, CLAY_PATCH, COIN, MARKET, TREE, VILLAGER,
+};
+use crate::history::{HistoryRecorder, Operation};
+use crate::localization::{LanguageChangedEvent, Localizer};
 use crate::recipe::{is_ongoing_recipe_valid_for_stack, OngoingRecipe, Recipes, StackCheck};
 use crate::stack::stack_utils::{
-    card_title_text, spawn_stack, split_stack, stack_visual_size,
-    CARD_DESCRIPTION_LOCALIZATION_PREFIX, CARD_TITLE_LOCALIZATION_PREFIX,
-    CARD_VALUE_SPACING_FROM_CARD_EDGE,
+    card_title_size, font_has_letters, layout_card_title, spawn_stack, split_stack,
+    stack_visual_size, CARD_DESCRIPTION_LOCALIZATION_PREFIX, CARD_TITLE_LOCALIZATION_PREFIX,
 };
 use crate::ui::UiClaimsMouse;
-use crate::GameState;
+use crate::{are_assets_loaded, FrameTime, GameState};
 use bevy::math::{const_vec2, const_vec3};
 use bevy::prelude::*;
 use bevy::render::camera::Camera2d;
+use bevy::transform::TransformSystem;
+use bevy::utils::HashMap;
 use bevy_asset_loader::{AssetCollection, AssetLoader};
+use spatial_index::{stack_spatial_index_update_system, StackSpatialIndex};
 use stack_utils::{
-    get_semi_random_stack_root_z, global_center_of_top_card, merge_stacks,
-    relative_center_of_nth_card_in_stack, CARD_STACK_Y_SPACING, STACK_ROOT_Z_RANGE,
+    find_free_space, free_space_search_area, get_semi_random_stack_root_z,
+    global_center_of_top_card, merge_stacks, relative_center_of_nth_card_in_stack, stack_center,
+    OrientedRect, STACK_ROOT_Z_RANGE,
 };
 use std::collections::HashSet;
 
@@ -47,27 +53,47 @@ const STACK_AUTO_MOVEMENT_SPEED: f32 = 2000.0;
 const DROP_TARGET_SCALE_ANIMATION_AMOUNT: f32 = 0.02;
 const DROP_TARGET_SCALE_ANIMATION_SPEED: f32 = 4.0;
 
-const CARD_FOREGROUND_IMAGES_PATH: &str = "vector_images/card_foreground_images/";
-const CARD_FOREGROUND_IMAGES_EXTENSION: &str = ".png";
-
-pub struct StackPlugin;
+/// Handles how stacks behave on the board: merging, moving and keeping distance.
+/// Does not draw the stacks, nor let the player interact with them. That is done by the [StackPlugin].
+pub struct StackLogicPlugin;
 
-impl Plugin for StackPlugin {
+impl Plugin for StackLogicPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<StackDroppedEvent>()
-            .add_event::<CardPickedUpEvent>()
             .add_event::<CreateStackEvent>()
-            .insert_resource(MouseWorldPos(None))
             .insert_resource(CardVisualSize(Vec2::ONE))
-            .add_system_to_stage(CoreStage::PreUpdate, mouse_world_pos_update_system)
-            .add_system_set(
-                SystemSet::on_exit(GameState::AssetLoading).with_system(on_assets_loaded),
+            .init_resource::<StackSpatialIndex>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                stack_spatial_index_update_system.after(TransformSystem::TransformPropagate),
             )
             .add_system_set(
                 SystemSet::on_enter(GameState::Run)
                     .with_system(spawn_system_cards)
                     .with_system(spawn_test_cards),
             )
+            .add_system_set(
+                SystemSet::on_update(GameState::Run)
+                    .with_system(dropped_stack_merging_system)
+                    .with_system(stack_overlap_nudging_system)
+                    .with_system(find_stack_movement_target_system)
+                    .with_system(stack_move_to_target_system),
+            );
+    }
+}
+
+/// Shows the stacks, and lets the player drag them around.
+/// Requires the [StackLogicPlugin].
+pub struct StackPlugin;
+
+impl Plugin for StackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CardPickedUpEvent>()
+            .insert_resource(MouseWorldPos(None))
+            .add_system_to_stage(CoreStage::PreUpdate, mouse_world_pos_update_system)
+            .add_system_set(
+                SystemSet::on_exit(GameState::AssetLoading).with_system(on_assets_loaded),
+            )
             .add_system_set(
                 SystemSet::on_update(GameState::Run)
                     .with_system(stack_creation_system)
@@ -77,15 +103,14 @@ impl Plugin for StackPlugin {
                     .with_system(card_mouse_pickup_system)
                     .with_system(stack_mouse_drop_system)
                     .with_system(card_hover_system)
-                    .with_system(hover_drag_cursor_system)
-                    .with_system(dropped_stack_merging_system)
-                    .with_system(stack_overlap_nudging_system)
-                    .with_system(find_stack_movement_target_system)
-                    .with_system(stack_move_to_target_system),
+                    .with_system(hover_drag_cursor_system),
             )
-            .add_system_set(
-                SystemSet::on_update(GameState::PauseMenu)
-                    .with_system(card_title_relocalization_system),
+            // After `Update`, so new cards get their title laid out before they are first shown.
+            .add_system_set_to_stage(
+                CoreStage::PostUpdate,
+                SystemSet::new()
+                    .with_run_criteria(are_assets_loaded)
+                    .with_system(card_title_layout_system),
             );
     }
 }
@@ -110,8 +135,13 @@ pub struct CardImages {
 /// To be loaded by an [AssetLoader](bevy_asset_loader::AssetLoader).
 #[derive(AssetCollection)]
 pub struct CardFonts {
+    /// Used when none of the fonts of the language has all the letters of a title.
     #[asset(path = "fonts/FallingSky-JKwK.otf")]
     pub title: Handle<Font>,
+    /// This entry is here so the fonts that languages ask for get loaded. They are referenced by
+    /// their path, see [Localizer::title_fonts].
+    #[asset(path = "fonts", folder(typed))]
+    pub _fonts: Vec<Handle<Font>>,
 }
 
 /// Resource which indicates where in the world the mouse currently is.
@@ -121,26 +151,32 @@ pub struct MouseWorldPos(Option<Vec2>);
 #[derive(Deref, DerefMut)]
 pub struct CardVisualSize(pub(crate) Vec2);
 
-#[derive(Component, Clone, Copy, Eq, PartialEq)]
+#[derive(Component, Clone, Copy, Eq, PartialEq, Debug)]
 pub struct Card {
-    /// Id that indicates which [CardType] this card was created from.
-    pub type_id: &'static str,
+    /// Id that indicates which [CardType](crate::card_types::CardType) this card was created from.
+    pub type_id: CardTypeId,
     pub category: CardCategory,
     /// Value on a [CardCategory::SystemCard] means the cost to buy something.
     pub value: Option<usize>,
+    /// Card pack that can be bought with coins on this card.
+    pub buys: Option<CardTypeId>,
 }
 
 impl Card {
-    pub fn is_type(&self, card_type: &CardType) -> bool {
-        self.type_id == card_type.id && self.category == card_type.category
+    pub fn is_type(&self, card_type: CardTypeId) -> bool {
+        self.type_id == card_type
     }
 
-    pub fn localize_title(&self, localizer: &Localizer) -> String {
-        localizer.localize(&(CARD_TITLE_LOCALIZATION_PREFIX.to_owned() + self.type_id))
+    pub fn title_localization_id(&self) -> String {
+        CARD_TITLE_LOCALIZATION_PREFIX.to_owned() + self.type_id.0
     }
 
-    pub fn localize_description(&self, localizer: &Localizer) -> String {
-        localizer.localize(&(CARD_DESCRIPTION_LOCALIZATION_PREFIX.to_owned() + self.type_id))
+    pub fn description_localization_id(&self) -> String {
+        CARD_DESCRIPTION_LOCALIZATION_PREFIX.to_owned() + self.type_id.0
+    }
+
+    pub fn localize_title(&self, localizer: &Localizer) -> String {
+        localizer.localize(&self.title_localization_id())
     }
 }
 
@@ -153,11 +189,20 @@ pub struct StackPhysics;
 #[derive(Component)]
 pub struct StackLookingForMovementTarget;
 
-/// Marks a stack that is moving on it's own towards a target other stack.
-/// The goal of a stack moving towards another stack is to combine with that stack.
-/// TODO (Wybe 2022-05-25): allow moving towards a fixed location.
+/// Marks a new stack that wants to be added to a stack nearby, which would become valid for
+/// a recipe with these cards. Handled, and removed, by [find_stack_movement_target_system].
 #[derive(Component)]
-pub struct MovingStackTarget(Entity);
+pub struct FeedsWaitingStack;
+
+/// Marks a stack that is moving on it's own towards a target.
+/// [stack_move_to_target_system] handles these stacks.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub enum MovingStackTarget {
+    /// The goal of a stack moving towards another stack is to combine with that stack.
+    Stack(Entity),
+    /// Where the stack root should end up. Used to move stacks into free space.
+    Position(Vec2),
+}
 
 /// Indicates this is the root entity of a stack of cards.
 /// Contains all cards, in-order.
@@ -178,6 +223,8 @@ pub struct IsDropTargetOverlay;
 #[derive(Component)]
 pub struct IsCardTitle;
 
+/// Where the stack was picked up, relative to the center of its root card.
+/// Like [HoveredCard::relative_hover_pos], it is not scaled or rotated.
 #[derive(Component, Deref, DerefMut)]
 pub struct StackRelativeDragPosition(Vec2);
 
@@ -188,6 +235,8 @@ pub struct IsExclusiveBottomCard;
 /// Indicates a card is being hovered with the mouse.
 #[derive(Component, PartialEq, Debug)]
 pub struct HoveredCard {
+    /// Where the mouse is, relative to the center of the card.
+    /// Along the axes of the card, and without the scaling of the card.
     relative_hover_pos: Vec2,
 }
 
@@ -195,13 +244,16 @@ pub struct HoveredCard {
 /// Is picked up by the `stack_creation_system`.
 pub struct CreateStackEvent {
     pub(crate) position: Vec2,
-    pub(crate) card_type: &'static CardType,
+    pub(crate) card_type: CardTypeId,
     pub(crate) amount: usize,
+    /// When set, the new stack first tries to move onto a stack nearby that is waiting on these
+    /// cards, see [FeedsWaitingStack].
+    pub(crate) feed_waiting_stack: bool,
 }
 
 /// Event sent by the [card_mouse_drag_system] when the user drops a card.
 /// Contains the stack root entity, and it's global transform upon being dropped.
-pub struct StackDroppedEvent(Entity, GlobalTransform);
+pub struct StackDroppedEvent(pub(crate) Entity, pub(crate) GlobalTransform);
 
 /// Event sent by the [card_mouse_drag_system] when the user picks up a card.
 pub struct CardPickedUpEvent(Entity);
@@ -216,86 +268,91 @@ pub fn on_assets_loaded(
     commands.insert_resource(CardVisualSize(card_background.size()));
 }
 
-pub fn spawn_system_cards(mut creation: EventWriter<CreateStackEvent>) {
+/// Spawns the market, and a card for buying each type of card pack.
+pub fn spawn_system_cards(card_types: Res<CardTypes>, mut creation: EventWriter<CreateStackEvent>) {
     let top_row_zero = Vec2::new(0., 400.0);
     creation.send(CreateStackEvent {
         position: top_row_zero,
-        card_type: &MARKET,
-        amount: 1,
-    });
-    creation.send(CreateStackEvent {
-        position: top_row_zero,
-        card_type: &BUY_FOREST_PACK,
+        card_type: MARKET,
         amount: 1,
+        feed_waiting_stack: false,
     });
+
+    // Sorted, so the cards are always laid out in the same order.
+    let mut buy_cards: Vec<CardTypeId> = card_types
+        .ids()
+        .filter(|&id| card_types.get(id).map_or(false, |t| t.buys.is_some()))
+        .collect();
+    buy_cards.sort_by_key(|id| id.0);
+    for card_type in buy_cards {
+        creation.send(CreateStackEvent {
+            position: top_row_zero,
+            card_type,
+            amount: 1,
+            feed_waiting_stack: false,
+        });
+    }
 }
 
 pub fn spawn_test_cards(mut creation: EventWriter<CreateStackEvent>) {
     creation.send(CreateStackEvent {
         position: Vec2::ZERO,
-        card_type: &TREE,
+        card_type: TREE,
         amount: 3,
+        feed_waiting_stack: false,
     });
     creation.send(CreateStackEvent {
         position: Vec2::ZERO,
-        card_type: &VILLAGER,
+        card_type: VILLAGER,
         amount: 2,
+        feed_waiting_stack: false,
     });
     creation.send(CreateStackEvent {
         position: Vec2::ZERO,
-        card_type: &COIN,
+        card_type: COIN,
         amount: 3,
+        feed_waiting_stack: false,
     });
     creation.send(CreateStackEvent {
         position: Vec2::ZERO,
-        card_type: &CLAY_PATCH,
+        card_type: CLAY_PATCH,
         amount: 5,
+        feed_waiting_stack: false,
     });
 }
 
 pub fn stack_creation_system(
     mut commands: Commands,
-    image_assets: Res<Assets<Image>>,
+    card_types: Res<CardTypes>,
     card_images: Res<CardImages>,
     card_fonts: Res<CardFonts>,
     visual_size: Res<CardVisualSize>,
     localizer: Res<Localizer>,
     mut events: EventReader<CreateStackEvent>,
 ) {
-    let title_transform =
-        Transform::from_xyz(0., 0.5 * (visual_size.y - CARD_STACK_Y_SPACING), DELTA_Z);
-    let card_value_transform = Transform::from_xyz(
-        -0.5 * visual_size.x + CARD_VALUE_SPACING_FROM_CARD_EDGE,
-        -0.5 * visual_size.y + CARD_VALUE_SPACING_FROM_CARD_EDGE,
-        DELTA_Z,
-    );
-
     for event in events.iter() {
-        if event.amount == 0 {
-            continue;
-        }
-
-        // Card foreground images are named after the id of the card type.
-        // Because of how images work in bevy, it doesn't matter if the expected image doesn't
-        // exist, or isn't loaded yet. In that case it simply won't be displayed.
-        let foreground_image = image_assets.get_handle(
-            CARD_FOREGROUND_IMAGES_PATH.to_owned()
-                + event.card_type.id
-                + CARD_FOREGROUND_IMAGES_EXTENSION,
-        );
+        let card_type = match card_types.get(event.card_type) {
+            Some(card_type) => card_type,
+            None => {
+                warn!("Cannot create unknown card type `{}`", event.card_type.0);
+                continue;
+            }
+        };
+        let card_types = vec![card_type; event.amount];
 
-        spawn_stack(
+        let maybe_stack = spawn_stack(
             &mut commands,
             event.position,
-            event.card_type,
-            event.amount,
+            &card_types,
+            true,
             &card_images,
             &card_fonts,
-            title_transform,
-            card_value_transform,
-            foreground_image,
+            visual_size.0,
             &localizer,
         );
+        if let (Some((root, _)), true) = (maybe_stack, event.feed_waiting_stack) {
+            commands.entity(root).insert(FeedsWaitingStack);
+        }
     }
 }
 
@@ -355,8 +412,10 @@ pub fn stack_mouse_drag_system(
                 continue;
             }
 
-            transform.translation = (mouse_world_pos - drag_position.0).extend(STACK_DRAG_Z);
             transform.scale = STACK_DRAG_SCALE;
+            // Keep the spot where the stack was picked up under the mouse.
+            let drag_offset = transform.rotation * (transform.scale * drag_position.0.extend(0.0));
+            transform.translation = (mouse_world_pos - drag_offset.truncate()).extend(STACK_DRAG_Z);
         }
     }
 }
@@ -370,6 +429,7 @@ pub fn card_mouse_pickup_system(
     ui_claims_mouse: Res<UiClaimsMouse>,
     hovered_card_query: Query<(Entity, &Parent, &HoveredCard, &GlobalTransform), With<Card>>,
     stacks: Query<(&CardStack, Option<&OngoingRecipe>)>,
+    mut history: HistoryRecorder,
 ) {
     if mouse_button.just_pressed(MouseButton::Left) && !ui_claims_mouse.0 {
         for (card_entity, stack_root, hovered_card_component, global_transform) in
@@ -386,6 +446,7 @@ pub fn card_mouse_pickup_system(
                         .remove::<StackPhysics>();
                 } else {
                     // Picking up some other card in the stack, which means splitting it.
+                    history.record(Operation::Split);
                     let new_root = split_stack(
                         &mut commands,
                         stack_root.0,
@@ -536,6 +597,10 @@ fn would_merging_break_ongoing_recipes(
     card_query: &Query<&Card>,
     recipes: &Res<Recipes>,
 ) -> bool {
+    // Paused recipes are already broken, and will be cancelled unless they can resume.
+    let maybe_dropped_recipe = maybe_dropped_recipe.filter(|recipe| !recipe.is_paused());
+    let maybe_target_recipe = maybe_target_recipe.filter(|recipe| !recipe.is_paused());
+
     if maybe_dropped_recipe.is_none() && maybe_target_recipe.is_none() {
         // Can't break recipes that are not there.
         return false;
@@ -613,9 +678,12 @@ pub fn card_hover_system(
     mut commands: Commands,
     maybe_mouse_world_pos: Res<MouseWorldPos>,
     card_query: Query<(Entity, &GlobalTransform, &Children), With<Card>>,
+    previously_hovered_query: Query<(Entity, &Children), With<HoveredCard>>,
+    stack_query: Query<&CardStack>,
     stack_dragged_query: Query<(&StackRelativeDragPosition, &CardStack)>,
     mut card_hover_overlay_query: Query<&mut Visibility, With<IsCardHoverOverlay>>,
     card_visual_size: Res<CardVisualSize>,
+    index: Res<StackSpatialIndex>,
     ui_claims_mouse: Res<UiClaimsMouse>,
 ) {
     if let Some(mouse_world_pos) = maybe_mouse_world_pos.0 {
@@ -627,8 +695,15 @@ pub fn card_hover_system(
             hovered_card = Some((cards_in_stack[0], relative_drag_pos.0, global_transform));
         } else if !ui_claims_mouse.0 {
             // User isn't dragging a stack. See which card they are hovering.
-
-            for (entity, transform, _) in card_query.iter() {
+            // Only the cards of the stacks under the mouse can be hovered.
+            let cards_under_mouse = index
+                .at_point(mouse_world_pos)
+                .into_iter()
+                .filter_map(|stack_root| stack_query.get(stack_root).ok())
+                .flat_map(|stack| stack.iter())
+                .filter_map(|&card| card_query.get(card).ok());
+
+            for (entity, transform, _) in cards_under_mouse {
                 if let Some(relative_pos) =
                     in_bounds(card_visual_size.0, transform, mouse_world_pos)
                 {
@@ -662,7 +737,7 @@ pub fn card_hover_system(
         }
 
         // Clear all other hover markers, so there aren't any stray ones lying around.
-        for (entity, _, children) in card_query.iter() {
+        for (entity, children) in previously_hovered_query.iter() {
             if let Some((hovered_entity, _, _)) = hovered_card {
                 if entity == hovered_entity {
                     continue;
@@ -706,7 +781,9 @@ pub fn dropped_stack_merging_system(
     exclusive_bottom_cards: Query<&IsExclusiveBottomCard>,
     recipes: Res<Recipes>,
     card_visual_size: Res<CardVisualSize>,
+    index: Res<StackSpatialIndex>,
     mut stack_dropped_reader: EventReader<StackDroppedEvent>,
+    mut history: HistoryRecorder,
 ) {
     for StackDroppedEvent(dropped_stack_root, dropped_global_transform) in
         stack_dropped_reader.iter()
@@ -717,8 +794,15 @@ pub fn dropped_stack_merging_system(
             stack_query.get(*dropped_stack_root).unwrap();
 
         // Find which card we are overlapping the most.
-        for (stack_root, stack_global_transform, target_stack, maybe_target_recipe) in
-            stack_query.iter()
+        // Only stacks that are within a card of the drop position can have their top card there.
+        let nearby_stacks = index
+            .overlapping(&OrientedRect::axis_aligned(
+                dropped_global_transform.translation.truncate(),
+                card_visual_size.0,
+            ))
+            .into_iter()
+            .filter_map(|stack_root| stack_query.get(stack_root).ok());
+        for (stack_root, stack_global_transform, target_stack, maybe_target_recipe) in nearby_stacks
         {
             if stack_root == *dropped_stack_root {
                 // Cannot drop onto self.
@@ -743,7 +827,6 @@ pub fn dropped_stack_merging_system(
             let center_of_top_card =
                 global_center_of_top_card(stack_global_transform, target_stack.len());
 
-            // TODO (Wybe 2022-05-24): Also take into account rotating and scaling.
             if in_bounds(
                 card_visual_size.0,
                 &center_of_top_card,
@@ -751,6 +834,7 @@ pub fn dropped_stack_merging_system(
             )
             .is_some()
             {
+                history.record(Operation::Merge);
                 merge_stacks(
                     &mut commands,
                     *dropped_stack_root,
@@ -785,44 +869,50 @@ pub fn dropped_stack_merging_system(
 /// TODO (Wybe 2022-05-21): This currently nudges cards that were just dropped, but not yet added to a stack.
 ///      It would probably be better to add dropped cards to a stack right away. And to remove picked up cards from a stack right away,
 ///      instead of next frame.
-/// TODO (Wybe 2022-05-24): Take into account scaling and rotation?
 pub fn stack_overlap_nudging_system(
-    time: Res<Time>,
-    mut physics_stacks: Query<(&GlobalTransform, &mut Transform, &CardStack), With<StackPhysics>>,
-    card_visual_size: Res<CardVisualSize>,
+    frame_time: Res<FrameTime>,
+    mut physics_stacks: Query<(Entity, &mut Transform), With<StackPhysics>>,
+    index: Res<StackSpatialIndex>,
 ) {
-    let mut combinations = physics_stacks.iter_combinations_mut();
-    while let Some(
-        [(global_transform1, mut transform1, CardStack(cards_in_stack1)), (global_transform2, mut transform2, CardStack(cards_in_stack2))],
-    ) = combinations.fetch_next()
-    {
-        let stack1_wanted_space =
-            stack_visual_size(card_visual_size.0, cards_in_stack1.len()) + STACK_OVERLAP_SPACING;
-        let mut stack1_center = global_transform1.translation.truncate();
-        stack1_center.y -= 0.5 * cards_in_stack1.len() as f32 * CARD_STACK_Y_SPACING;
-
-        let stack2_wanted_space =
-            stack_visual_size(card_visual_size.0, cards_in_stack2.len()) + STACK_OVERLAP_SPACING;
-        let mut stack2_center = global_transform2.translation.truncate();
-        stack2_center.y -= 0.5 * cards_in_stack2.len() as f32 * CARD_STACK_Y_SPACING;
-
-        // TODO (Wybe 2022-05-14): Should we account for scaling and rotation?
-        if let Some(total_movement) = get_movement_to_no_longer_overlap(
-            stack1_center,
-            stack1_wanted_space,
-            stack2_center,
-            stack2_wanted_space,
-        ) {
-            let max_movement_this_frame = STACK_OVERLAP_MOVEMENT * time.delta_seconds();
-
-            let movement = if total_movement.length() <= max_movement_this_frame {
-                total_movement.extend(0.0)
-            } else {
-                (total_movement.normalize() * max_movement_this_frame).extend(0.0)
-            };
+    // Movements are added up first, because a query can't hand out two stacks mutably at once.
+    let mut movements: HashMap<Entity, Vec2> = HashMap::default();
+
+    for (stack1, _) in physics_stacks.iter() {
+        let indexed1 = match index.get(stack1) {
+            Some(indexed) => indexed,
+            None => continue,
+        };
+        let stack1_wanted_space = indexed1.area.grown(STACK_OVERLAP_SPACING);
+
+        // The other stack wants some spacing as well.
+        for stack2 in index.overlapping(&stack1_wanted_space.grown(STACK_OVERLAP_SPACING)) {
+            // Every pair of stacks only needs to be handled once.
+            if stack2 <= stack1 || physics_stacks.get(stack2).is_err() {
+                continue;
+            }
+            let indexed2 = index.get(stack2).unwrap();
+            let stack2_wanted_space = indexed2.area.grown(STACK_OVERLAP_SPACING);
 
-            transform1.translation += movement;
-            transform2.translation -= movement;
+            if let Some(total_movement) =
+                get_movement_to_no_longer_overlap(&stack1_wanted_space, &stack2_wanted_space)
+            {
+                let max_movement_this_frame = STACK_OVERLAP_MOVEMENT * frame_time.delta_seconds();
+
+                let movement = if total_movement.length() <= max_movement_this_frame {
+                    total_movement
+                } else {
+                    total_movement.normalize() * max_movement_this_frame
+                };
+
+                *movements.entry(stack1).or_default() += movement;
+                *movements.entry(stack2).or_default() -= movement;
+            }
+        }
+    }
+
+    for (stack, mut transform) in physics_stacks.iter_mut() {
+        if let Some(movement) = movements.get(&stack) {
+            transform.translation += movement.extend(0.0);
         }
     }
 }
@@ -838,53 +928,63 @@ pub fn stack_move_to_target_system(
         Option<&OngoingRecipe>,
     )>,
     all_stacks: Query<(Entity, &GlobalTransform, &CardStack, Option<&OngoingRecipe>)>,
-    time: Res<Time>,
+    frame_time: Res<FrameTime>,
 ) {
-    for (
-        root,
-        global_transform,
-        mut transform,
-        stack,
-        &MovingStackTarget(movement_target),
-        maybe_recipe,
-    ) in stacks_with_target.iter_mut()
+    for (root, global_transform, mut transform, stack, &movement_target, maybe_recipe) in
+        stacks_with_target.iter_mut()
     {
         // TODO (Wybe 2022-05-25): Remove targeting when a stack is targeting itself.
 
-        if let Ok((target_root, target_global_transform, target_stack, maybe_target_recipe)) =
-            all_stacks.get(movement_target)
-        {
-            // TODO (Wybe 2022-05-25): Set the stacks Z position so it is on top of all other stacks. but below the dragged stacks
-            let target_pos =
-                global_center_of_top_card(target_global_transform, target_stack.len()).translation;
+        let target_pos = match movement_target {
+            MovingStackTarget::Stack(target_root) => {
+                if let Ok((_, target_global_transform, target_stack, _)) =
+                    all_stacks.get(target_root)
+                {
+                    global_center_of_top_card(target_global_transform, target_stack.len())
+                        .translation
+                        .truncate()
+                } else {
+                    // Target does not exist.
+                    remove_movement_target(&mut commands, root);
+                    continue;
+                }
+            }
+            MovingStackTarget::Position(position) => position,
+        };
 
-            let total_movement = target_pos.truncate() - global_transform.translation.truncate();
+        // TODO (Wybe 2022-05-25): Set the stacks Z position so it is on top of all other stacks. but below the dragged stacks
+        let total_movement = target_pos - global_transform.translation.truncate();
 
-            let movement_this_frame =
-                total_movement.normalize() * STACK_AUTO_MOVEMENT_SPEED * time.delta_seconds();
+        let movement_this_frame =
+            total_movement.normalize() * STACK_AUTO_MOVEMENT_SPEED * frame_time.delta_seconds();
 
-            if total_movement.length() == 0.
-                || movement_this_frame.length() >= total_movement.length()
-            {
-                // Target will be reached in this frame. Snap to it.
-                // Don't need to remove the movement target, because the source stack won't exist
-                // after this frame.
-                merge_stacks(
-                    &mut commands,
-                    root,
-                    stack,
-                    maybe_recipe,
-                    target_root,
-                    target_stack,
-                    maybe_target_recipe,
-                );
-            } else {
-                transform.translation += movement_this_frame.extend(0.);
-                transform.translation.z = STACK_AUTO_MOVE_Z;
+        if total_movement.length() == 0. || movement_this_frame.length() >= total_movement.length()
+        {
+            // Target will be reached in this frame. Snap to it.
+            match movement_target {
+                MovingStackTarget::Stack(target_root) => {
+                    // Don't need to remove the movement target, because the source stack won't
+                    // exist after this frame.
+                    let (_, _, target_stack, maybe_target_recipe) =
+                        all_stacks.get(target_root).unwrap();
+                    merge_stacks(
+                        &mut commands,
+                        root,
+                        stack,
+                        maybe_recipe,
+                        target_root,
+                        target_stack,
+                        maybe_target_recipe,
+                    );
+                }
+                MovingStackTarget::Position(_) => {
+                    transform.translation = target_pos.extend(get_semi_random_stack_root_z(root));
+                    remove_movement_target(&mut commands, root);
+                }
             }
         } else {
-            // Target does not exist.
-            remove_movement_target(&mut commands, root);
+            transform.translation += movement_this_frame.extend(0.);
+            transform.translation.z = STACK_AUTO_MOVE_Z;
         }
     }
 }
@@ -896,32 +996,53 @@ fn remove_movement_target(commands: &mut Commands, stack_root: Entity) {
         .insert(StackPhysics);
 }
 
-/// Handles stacks marked with [StackLookingForTargetLocation] (and removes the mark).
-/// Finds either an open space, or another stack that this one can combine with.
+/// Handles stacks marked with [StackLookingForMovementTarget] (and removes the mark).
+/// Finds either another stack that this one can combine with, or the closest free space.
+/// New stacks start out on top of the stack that created them, so this moves them next to it.
 /// Wont auto-combine with ongoing recipes.
 /// TODO (Wybe 2022-05-30): Prevent instant recipes from being automatically creating (like dropping a stack of coins onto a "buy stack" card that already has a coin on it).
 ///     non-instant recipes are allowed to be auto-created, because the user can cancel them, or even set the cards up so that it auto-creates a wanted recipe.
 pub fn find_stack_movement_target_system(
     mut commands: Commands,
     lost_stack_query: Query<
-        (Entity, &GlobalTransform, &CardStack),
+        (
+            Entity,
+            &GlobalTransform,
+            &CardStack,
+            Option<&FeedsWaitingStack>,
+        ),
         With<StackLookingForMovementTarget>,
     >,
     potential_target_stack_query: Query<
-        (Entity, &GlobalTransform, &CardStack),
+        &CardStack,
         (
             Without<StackLookingForMovementTarget>,
             Without<StackRelativeDragPosition>,
             Without<OngoingRecipe>,
         ),
     >,
+    moving_stacks: Query<(&MovingStackTarget, &CardStack)>,
     cards: Query<&Card>,
     card_visual_size: Res<CardVisualSize>,
+    index: Res<StackSpatialIndex>,
+    recipes: Res<Recipes>,
 ) {
     let card_cross_sections_max_search_radius = 1.5;
     let search_radius_range = card_visual_size.length() * card_cross_sections_max_search_radius;
 
-    for (root, global_transform, stack) in lost_stack_query.iter() {
+    // Stacks that are on their way to a free space already claim that space.
+    let mut claimed: Vec<(Vec2, Vec2)> = moving_stacks
+        .iter()
+        .filter_map(|(&target, stack)| match target {
+            MovingStackTarget::Position(position) => Some((
+                stack_center(position, stack.len()),
+                stack_visual_size(card_visual_size.0, stack.len()) + STACK_OVERLAP_SPACING,
+            )),
+            MovingStackTarget::Stack(_) => None,
+        })
+        .collect();
+
+    for (root, global_transform, stack, maybe_feeds) in lost_stack_query.iter() {
         // TODO (Wybe 2022-05-25): don't unwrap here.
         let wanted_top_card = cards.get(stack[0]).unwrap();
         if stack.iter().map(|&e| cards.get(e)).any(|maybe_card| {
@@ -940,56 +1061,155 @@ pub fn find_stack_movement_target_system(
             break;
         }
 
-        let mut target_found = false;
-
-        // TODO (Wybe 2022-05-25): Clean up so it isn't so nested.
-        for (target_root, target_global, target_stack) in potential_target_stack_query.iter() {
-            let top_card_transform = global_center_of_top_card(target_global, target_stack.len());
-
-            if (global_transform.translation.truncate() - top_card_transform.translation.truncate())
-                .length()
-                < search_radius_range
-            {
-                // Top card in range. Check if it is the same as the cards in the seeking stack.
-                if cards.get(*target_stack.last().unwrap()).unwrap() == wanted_top_card {
-                    // Can auto-stack with this target stack.
-                    commands
-                        .entity(root)
-                        .remove::<StackLookingForMovementTarget>()
-                        .insert(MovingStackTarget(target_root));
-                    target_found = true;
-                    break;
-                }
+        let root_position = global_transform.translation.truncate();
+
+        if maybe_feeds.is_some() {
+            commands.entity(root).remove::<FeedsWaitingStack>();
+
+            // Feed the closest stack that these cards make valid for a recipe.
+            let maybe_waiting =
+                index.nearest_top_card(root_position, search_radius_range, |target| {
+                    potential_target_stack_query
+                        .get(target)
+                        .map(|target_stack| {
+                            let combined_cards: Vec<Card> = target_stack
+                                .iter()
+                                .chain(stack.iter())
+                                .filter_map(|&e| cards.get(e).ok())
+                                .copied()
+                                .collect();
+                            let stack_check = StackCheck(combined_cards);
+                            recipes
+                                .values()
+                                .any(|recipe| (recipe.is_valid)(&stack_check))
+                        })
+                        .unwrap_or(false)
+                });
+            if let Some(target_root) = maybe_waiting {
+                commands
+                    .entity(root)
+                    .remove::<StackLookingForMovementTarget>()
+                    .insert(MovingStackTarget::Stack(target_root));
+                continue;
             }
         }
 
-        if !target_found {
+        // Can auto-stack with the closest stack that has the same top card.
+        let maybe_target = index.nearest_top_card(root_position, search_radius_range, |target| {
+            potential_target_stack_query
+                .get(target)
+                .map(|target_stack| {
+                    cards.get(*target_stack.last().unwrap()).ok() == Some(wanted_top_card)
+                })
+                .unwrap_or(false)
+        });
+        if let Some(target_root) = maybe_target {
             commands
                 .entity(root)
                 .remove::<StackLookingForMovementTarget>()
-                .insert(StackPhysics);
+                .insert(MovingStackTarget::Stack(target_root));
+            continue;
+        }
+
+        let center = stack_center(root_position, stack.len());
+        let size = stack_visual_size(card_visual_size.0, stack.len()) + STACK_OVERLAP_SPACING;
+
+        // Stacks that are still looking, or moving to free space, are not where they will end up.
+        let mut occupied: Vec<(Vec2, Vec2)> = index
+            .overlapping(&OrientedRect::axis_aligned(
+                center,
+                free_space_search_area(size),
+            ))
+            .into_iter()
+            .filter(|&other| {
+                lost_stack_query.get(other).is_err()
+                    && !matches!(
+                        moving_stacks.get(other),
+                        Ok((MovingStackTarget::Position(_), _))
+                    )
+            })
+            .filter_map(|other| index.get(other))
+            .map(|other| {
+                (
+                    other.area.center,
+                    other.area.bounding_size() + STACK_OVERLAP_SPACING,
+                )
+            })
+            .collect();
+        occupied.extend_from_slice(&claimed);
+
+        let mut entity_commands = commands.entity(root);
+        entity_commands.remove::<StackLookingForMovementTarget>();
+        match find_free_space(center, size, &occupied) {
+            Some(free_center) if free_center != center => {
+                claimed.push((free_center, size));
+                entity_commands.insert(MovingStackTarget::Position(
+                    root_position + (free_center - center),
+                ));
+            }
+            _ => {
+                // Already in free space, or there is no free space nearby.
+                // Either way, the nudging will sort it out.
+                claimed.push((center, size));
+                entity_commands.insert(StackPhysics);
+            }
         }
     }
 
-    // TODO (Wybe 2022-05-25): Implement moving (teleporting for now) to the closest empty space
-    // TODO (Wybe 2022-05-25): Implement smoothly moving to the target (needs another system which handles the movement).
     // TODO (Wybe 2022-05-25): Implement what happens when cards get picked up by the user during this movement.
 }
 
-/// Updates the card titles when the localization language is changed.
-/// Should be ran while in the pause menu.
-fn card_title_relocalization_system(
-    mut title_query: Query<(&mut Text, &Parent), With<IsCardTitle>>,
+/// Lays out the titles of new cards, and of all cards when the localization language is
+/// changed or its messages are reloaded.
+/// A title gets the first of the [Localizer::title_fonts] that has all of its letters, and is
+/// made smaller or wrapped when it does not fit on the card.
+fn card_title_layout_system(
+    mut events: EventReader<LanguageChangedEvent>,
+    mut title_query: Query<(&mut Text, &Parent, ChangeTrackers<IsCardTitle>)>,
     card_query: Query<&Card>,
     card_fonts: Res<CardFonts>,
+    fonts: Res<Assets<Font>>,
+    card_visual_size: Res<CardVisualSize>,
     localizer: Res<Localizer>,
 ) {
-    if localizer.is_changed() {
-        for (mut text, parent) in title_query.iter_mut() {
-            if let Ok(card) = card_query.get(parent.0) {
-                *text = card_title_text(card, &card_fonts, &localizer);
-            }
+    let language_changed = events.iter().count() > 0;
+
+    let mut title_fonts: Vec<Handle<Font>> = localizer
+        .title_fonts()
+        .iter()
+        .map(|path| Handle::weak(path.as_str().into()))
+        .collect();
+    title_fonts.push(card_fonts.title.clone());
+    let max_size = card_title_size(card_visual_size.0);
+
+    for (mut text, parent, title_tracker) in title_query.iter_mut() {
+        if !language_changed && !title_tracker.is_added() {
+            continue;
         }
+        let card = match card_query.get(parent.0) {
+            Ok(card) => card,
+            Err(_) => continue,
+        };
+
+        let title = card.localize_title(&localizer);
+        let loaded_fonts: Vec<(&Handle<Font>, &Font)> = title_fonts
+            .iter()
+            .filter_map(|handle| fonts.get(handle).map(|font| (handle, font)))
+            .collect();
+        let maybe_font = loaded_fonts
+            .iter()
+            .find(|(_, font)| font_has_letters(&font.font, &title))
+            .or_else(|| loaded_fonts.first());
+        let (handle, font) = match maybe_font {
+            Some(&font) => font,
+            None => continue,
+        };
+
+        let layout = layout_card_title(&title, &font.font, max_size);
+        let section = &mut text.sections[0];
+        section.value = layout.text;
+        section.style.font = handle.clone();
+        section.style.font_size = layout.font_size;
     }
 }
 
@@ -1007,25 +1227,20 @@ fn window_pos_to_world_pos(
         .truncate()
 }
 
-/// Returns where in the bounds the position is located.
-/// `None` if the position is not in bounds.
-/// Assumes the `size`'s origin is at it's center.
+/// Returns where the `position_to_check` is on a rectangle of `unscaled_size`, which is placed,
+/// rotated and scaled by the `transform`. Returns `None` if it is not on the rectangle.
+/// The returned position is relative to the center, along the axes of the rectangle, and is
+/// not scaled.
 fn in_bounds(
     unscaled_size: Vec2,
     transform: &GlobalTransform,
     position_to_check: Vec2,
 ) -> Option<Vec2> {
-    // TODO (Wybe 2022-05-14): Take into account rotation.
-    let half_size = unscaled_size * transform.scale.truncate() / 2.0;
+    let rect = OrientedRect::from_transform(unscaled_size, transform);
 
-    let pos_in_bounds = position_to_check - transform.translation.truncate();
-
-    if pos_in_bounds.x >= -half_size.x
-        && pos_in_bounds.x <= half_size.x
-        && pos_in_bounds.y >= -half_size.y
-        && pos_in_bounds.y <= half_size.y
-    {
-        Some(pos_in_bounds)
+    if rect.contains(position_to_check) {
+        let scale = transform.scale.truncate();
+        Some(rect.to_local(position_to_check) * scale.signum() / scale)
     } else {
         None
     }
@@ -1034,63 +1249,58 @@ fn in_bounds(
 /// Returns the shortest distance two rectangles should move in, in order not to overlap anymore.
 /// The first rectangle given should use the movement vector as-is, the second should invert it.
 /// Returns `None` if the rectangles are not overlapping.
-/// TODO (Wybe 2022-05-14): Take into account scaling and rotation?
-///                         And then accept a GlobalTransform instead of a Vec2.
-fn get_movement_to_no_longer_overlap(
-    pos1: Vec2,
-    size1: Vec2,
-    pos2: Vec2,
-    size2: Vec2,
-) -> Option<Vec2> {
-    let minimum_allowed_distance = (size1 / 2.0) + (size2 / 2.0);
-
-    let distance = pos1 - pos2;
-    let abs_distance = distance.abs();
-    let overlap = minimum_allowed_distance - abs_distance;
-    let mut movement = overlap * (distance / abs_distance);
-
-    if overlap.x > 0.0 && overlap.y > 0.0 {
-        if movement.x.is_nan() {
-            movement.x = minimum_allowed_distance.x;
-        }
-        if movement.y.is_nan() {
-            movement.y = minimum_allowed_distance.y;
+/// Uses the separating axis theorem, so the rectangles can be rotated.
+fn get_movement_to_no_longer_overlap(rect1: &OrientedRect, rect2: &OrientedRect) -> Option<Vec2> {
+    let distance = rect1.center - rect2.center;
+
+    // Vertical axes first, so moving vertically is preferred when both are just as short.
+    let mut shortest_movement: Option<Vec2> = None;
+    for axis in [rect1.y_axis(), rect1.x_axis, rect2.y_axis(), rect2.x_axis] {
+        let distance_on_axis = distance.dot(axis);
+        let overlap =
+            rect1.projected_radius(axis) + rect2.projected_radius(axis) - distance_on_axis.abs();
+
+        if overlap <= 0.0 {
+            // Found an axis the rectangles can be separated on.
+            return None;
         }
 
-        // Select shortest distance.
-        if overlap.x < overlap.y {
-            movement.y = 0.0;
-        } else {
-            movement.x = 0.0;
+        // When the centers are at the same spot, any direction will do.
+        let movement = axis * overlap * distance_on_axis.signum();
+        if shortest_movement.map_or(true, |shortest| movement.length() < shortest.length()) {
+            shortest_movement = Some(movement);
         }
-
-        // Divide by 2, because both rectangles are going to move.
-        Some(movement / 2.0)
-    } else {
-        None
     }
+
+    // Divide by 2, because both rectangles are going to move.
+    shortest_movement.map(|movement| movement / 2.0)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{stack::get_movement_to_no_longer_overlap, Vec2};
+    use crate::stack::stack_utils::{
+        global_center_of_top_card, stack_area, OrientedRect, CARD_STACK_Y_SPACING,
+    };
+    use crate::stack::{get_movement_to_no_longer_overlap, in_bounds};
+    use bevy::prelude::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_close(a: Vec2, b: Vec2) {
+        assert!((a - b).length() < 0.001, "{} is not {}", a, b);
+    }
 
     #[test]
     fn test_get_movement_to_no_longer_overlap() {
         let overlap = get_movement_to_no_longer_overlap(
-            Vec2::new(10.0, 10.0),
-            Vec2::new(6.0, 6.0),
-            Vec2::new(12.0, 12.0),
-            Vec2::new(4.0, 4.0),
+            &OrientedRect::axis_aligned(Vec2::new(10.0, 10.0), Vec2::new(6.0, 6.0)),
+            &OrientedRect::axis_aligned(Vec2::new(12.0, 12.0), Vec2::new(4.0, 4.0)),
         )
         .unwrap();
         assert_eq!(overlap, Vec2::new(0.0, -1.5));
 
         let overlap_invert_arguments = get_movement_to_no_longer_overlap(
-            Vec2::new(12.0, 12.0),
-            Vec2::new(4.0, 4.0),
-            Vec2::new(10.0, 10.0),
-            Vec2::new(6.0, 6.0),
+            &OrientedRect::axis_aligned(Vec2::new(12.0, 12.0), Vec2::new(4.0, 4.0)),
+            &OrientedRect::axis_aligned(Vec2::new(10.0, 10.0), Vec2::new(6.0, 6.0)),
         )
         .unwrap();
 
@@ -1101,11 +1311,91 @@ mod test {
     #[test]
     fn test_get_movement_to_no_longer_overlap_when_no_overlap() {
         let overlap = get_movement_to_no_longer_overlap(
-            Vec2::new(10.0, 10.0),
-            Vec2::new(6.0, 6.0),
-            Vec2::new(16.0, 10.0),
-            Vec2::new(4.0, 4.0),
+            &OrientedRect::axis_aligned(Vec2::new(10.0, 10.0), Vec2::new(6.0, 6.0)),
+            &OrientedRect::axis_aligned(Vec2::new(16.0, 10.0), Vec2::new(4.0, 4.0)),
         );
         assert_eq!(overlap, None);
     }
+
+    #[test]
+    fn test_get_movement_to_no_longer_overlap_when_rotated() {
+        // A tall rectangle turned on its side, next to a square.
+        let tilted = OrientedRect::from_transform(
+            Vec2::new(2.0, 10.0),
+            &GlobalTransform::from_rotation(Quat::from_rotation_z(FRAC_PI_2)),
+        );
+        let square = OrientedRect::axis_aligned(Vec2::new(6.0, 0.0), Vec2::new(4.0, 4.0));
+
+        let overlap = get_movement_to_no_longer_overlap(&tilted, &square).unwrap();
+        assert_close(overlap, Vec2::new(-0.5, 0.0));
+
+        // Unrotated, the tall rectangle would not reach the square.
+        let upright = OrientedRect::axis_aligned(Vec2::ZERO, Vec2::new(2.0, 10.0));
+        assert_eq!(get_movement_to_no_longer_overlap(&upright, &square), None);
+
+        // Tilted by 45 degrees, the corner of a square reaches further.
+        let diamond = OrientedRect::from_transform(
+            Vec2::new(4.0, 4.0),
+            &GlobalTransform::from_rotation(Quat::from_rotation_z(FRAC_PI_2 / 2.0)),
+        );
+        let other = OrientedRect::axis_aligned(Vec2::new(4.5, 0.0), Vec2::new(4.0, 4.0));
+        assert!(get_movement_to_no_longer_overlap(&diamond, &other).is_some());
+        assert!(!OrientedRect::axis_aligned(Vec2::ZERO, Vec2::new(4.0, 4.0)).overlaps(&other));
+    }
+
+    #[test]
+    fn test_get_movement_to_no_longer_overlap_when_scaled() {
+        let stack_size = Vec2::new(100.0, 200.0);
+        let scaled = stack_area(
+            &GlobalTransform::from_scale(Vec3::new(1.5, 1.5, 1.0)),
+            1,
+            stack_size,
+        );
+        let other = OrientedRect::axis_aligned(Vec2::new(120.0, 0.0), stack_size);
+
+        let overlap = get_movement_to_no_longer_overlap(&scaled, &other).unwrap();
+        assert_close(overlap, Vec2::new(-2.5, 0.0));
+    }
+
+    #[test]
+    fn test_in_bounds() {
+        let card_size = Vec2::new(100.0, 200.0);
+
+        let transform = GlobalTransform::from_xyz(10.0, 20.0, 0.0);
+        assert_eq!(
+            in_bounds(card_size, &transform, Vec2::new(20.0, 110.0)),
+            Some(Vec2::new(10.0, 90.0))
+        );
+        assert_eq!(
+            in_bounds(card_size, &transform, Vec2::new(70.0, 20.0)),
+            None
+        );
+
+        // Like a dragged stack.
+        let scaled = transform.with_scale(Vec3::new(1.1, 1.1, 1.0));
+        let position = in_bounds(card_size, &scaled, Vec2::new(65.0, 20.0)).unwrap();
+        assert_close(position, Vec2::new(50.0, 0.0));
+
+        // Lying on its side, the card is wide.
+        let rotated = transform.with_rotation(Quat::from_rotation_z(FRAC_PI_2));
+        let position = in_bounds(card_size, &rotated, Vec2::new(100.0, 20.0)).unwrap();
+        assert_close(position, Vec2::new(0.0, -90.0));
+        assert_eq!(in_bounds(card_size, &rotated, Vec2::new(10.0, 110.0)), None);
+    }
+
+    #[test]
+    fn test_top_card_follows_root_transform() {
+        let root = GlobalTransform::from_xyz(10.0, 20.0, 0.0)
+            .with_rotation(Quat::from_rotation_z(FRAC_PI_2))
+            .with_scale(Vec3::new(2.0, 2.0, 1.0));
+
+        let top_card = global_center_of_top_card(&root, 2);
+        // The stack hangs down from the root, which is to the right when rotated a quarter turn.
+        assert_close(
+            top_card.translation.truncate(),
+            Vec2::new(10.0 + 4.0 * CARD_STACK_Y_SPACING, 20.0),
+        );
+        assert_eq!(top_card.rotation, root.rotation);
+        assert_eq!(top_card.scale, root.scale);
+    }
 }
@@ -0,0 +1,344 @@
+//! Keeps track of where the stacks are, so systems don't need to look at every stack on the
+//! board to find the few that are near a certain position.
+
+use crate::stack::stack_utils::{global_center_of_top_card, stack_area, OrientedRect};
+use crate::stack::{CardStack, CardVisualSize};
+use bevy::math::IVec2;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+/// Size of the squares the board is divided into.
+/// A bit bigger than a card, so most stacks are in only a few squares.
+const CELL_SIZE: f32 = 300.0;
+
+/// Where a stack is, as far as the [StackSpatialIndex] knows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndexedStack {
+    /// The space the stack takes up.
+    pub area: OrientedRect,
+    /// Where other stacks are merged onto, see [global_center_of_top_card].
+    pub top_card: Vec2,
+    pub z: f32,
+}
+
+impl IndexedStack {
+    pub fn new(root_transform: &GlobalTransform, cards_in_stack: usize, card_size: Vec2) -> Self {
+        IndexedStack {
+            area: stack_area(root_transform, cards_in_stack, card_size),
+            top_card: global_center_of_top_card(root_transform, cards_in_stack)
+                .translation
+                .truncate(),
+            z: root_transform.translation.z,
+        }
+    }
+
+    /// Squares of the index that the stack is in.
+    fn cells(&self) -> impl Iterator<Item = IVec2> {
+        cells_covering(self.area.center, self.area.bounding_size())
+    }
+}
+
+/// Resource that divides the board into a grid, and remembers which stacks are in which squares.
+/// Kept up-to-date by the [stack_spatial_index_update_system], which runs after the transforms
+/// are propagated. So during `Update`, it knows where the stacks were at the start of the frame,
+/// just like their [GlobalTransform]s do.
+#[derive(Default)]
+pub struct StackSpatialIndex {
+    cells: HashMap<IVec2, Vec<Entity>>,
+    stacks: HashMap<Entity, IndexedStack>,
+}
+
+impl StackSpatialIndex {
+    pub fn get(&self, stack_root: Entity) -> Option<&IndexedStack> {
+        self.stacks.get(&stack_root)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stacks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    /// Adds the stack, or moves it if it is already known.
+    pub fn insert(&mut self, stack_root: Entity, stack: IndexedStack) {
+        if let Some(old) = self.stacks.insert(stack_root, stack) {
+            if old.cells().eq(stack.cells()) {
+                // Still in the same squares.
+                return;
+            }
+            self.remove_from_cells(stack_root, &old);
+        }
+
+        for cell in stack.cells() {
+            self.cells.entry(cell).or_default().push(stack_root);
+        }
+    }
+
+    pub fn remove(&mut self, stack_root: Entity) {
+        if let Some(old) = self.stacks.remove(&stack_root) {
+            self.remove_from_cells(stack_root, &old);
+        }
+    }
+
+    fn remove_from_cells(&mut self, stack_root: Entity, stack: &IndexedStack) {
+        for cell in stack.cells() {
+            if let Some(stacks_in_cell) = self.cells.get_mut(&cell) {
+                stacks_in_cell.retain(|&e| e != stack_root);
+                if stacks_in_cell.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// Stacks in the squares that the rectangle covers. Might include stacks that are close to
+    /// the rectangle, but not in it. Every stack is given only once.
+    fn candidates(
+        &self,
+        center: Vec2,
+        size: Vec2,
+    ) -> impl Iterator<Item = (Entity, &IndexedStack)> + '_ {
+        let mut seen = HashSet::default();
+        cells_covering(center, size)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(move |&&e| seen.insert(e))
+            .map(|e| (*e, &self.stacks[e]))
+    }
+
+    /// All stacks that overlap the rectangle.
+    pub fn overlapping(&self, area: &OrientedRect) -> Vec<Entity> {
+        self.candidates(area.center, area.bounding_size())
+            .filter(|(_, stack)| stack.area.overlaps(area))
+            .map(|(e, _)| e)
+            .collect()
+    }
+
+    /// The stack with the top card closest to the `position`, that is also within the `radius`
+    /// and is accepted by the `filter`.
+    pub fn nearest_top_card(
+        &self,
+        position: Vec2,
+        radius: f32,
+        mut filter: impl FnMut(Entity) -> bool,
+    ) -> Option<Entity> {
+        self.candidates(position, Vec2::splat(2.0 * radius))
+            .map(|(e, stack)| (e, stack.top_card.distance(position)))
+            .filter(|&(e, distance)| distance < radius && filter(e))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(e, _)| e)
+    }
+
+    /// All stacks that cover the `point`, the one that is on top first.
+    pub fn at_point(&self, point: Vec2) -> Vec<Entity> {
+        let mut stacks: Vec<(Entity, f32)> = self
+            .candidates(point, Vec2::ZERO)
+            .filter(|(_, stack)| stack.area.contains(point))
+            .map(|(e, stack)| (e, stack.z))
+            .collect();
+        stacks.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        stacks.into_iter().map(|(e, _)| e).collect()
+    }
+}
+
+fn cell_of(position: Vec2) -> IVec2 {
+    (position / CELL_SIZE).floor().as_ivec2()
+}
+
+fn cells_covering(center: Vec2, size: Vec2) -> impl Iterator<Item = IVec2> {
+    let min = cell_of(center - size / 2.0);
+    let max = cell_of(center + size / 2.0);
+    (min.x..=max.x).flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+}
+
+/// Should run after the transforms are propagated, so it sees where the stacks are drawn.
+pub fn stack_spatial_index_update_system(
+    mut index: ResMut<StackSpatialIndex>,
+    changed_stacks: Query<
+        (Entity, &GlobalTransform, &CardStack),
+        Or<(Changed<GlobalTransform>, Changed<CardStack>)>,
+    >,
+    all_stacks: Query<(Entity, &GlobalTransform, &CardStack)>,
+    removed_stacks: RemovedComponents<CardStack>,
+    card_visual_size: Res<CardVisualSize>,
+) {
+    for stack_root in removed_stacks.iter() {
+        index.remove(stack_root);
+    }
+
+    let stacks_to_update = if card_visual_size.is_changed() {
+        // Every stack changed size.
+        all_stacks.iter().collect::<Vec<_>>()
+    } else {
+        changed_stacks.iter().collect()
+    };
+
+    for (stack_root, global_transform, stack) in stacks_to_update {
+        if stack.is_empty() {
+            index.remove(stack_root);
+        } else {
+            index.insert(
+                stack_root,
+                IndexedStack::new(global_transform, stack.len(), card_visual_size.0),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stack::spatial_index::{
+        stack_spatial_index_update_system, IndexedStack, StackSpatialIndex,
+    };
+    use crate::stack::stack_utils::OrientedRect;
+    use crate::stack::{stack_overlap_nudging_system, CardStack, CardVisualSize, StackPhysics};
+    use crate::FrameTime;
+    use bevy::prelude::*;
+    use test::Bencher;
+
+    fn stack_at(center: Vec2, size: Vec2, z: f32) -> IndexedStack {
+        IndexedStack {
+            area: OrientedRect::axis_aligned(center, size),
+            top_card: center,
+            z,
+        }
+    }
+
+    #[test]
+    fn test_queries() {
+        let size = Vec2::new(200.0, 275.0);
+        let mut world = World::default();
+        let [a, b, c] = [(); 3].map(|_| world.spawn().id());
+
+        let mut index = StackSpatialIndex::default();
+        index.insert(a, stack_at(Vec2::ZERO, size, 1.0));
+        index.insert(b, stack_at(Vec2::new(150.0, 0.0), size, 2.0));
+        index.insert(c, stack_at(Vec2::new(2000.0, 2000.0), size, 3.0));
+        assert_eq!(index.len(), 3);
+
+        let mut overlapping = index.overlapping(&OrientedRect::axis_aligned(
+            Vec2::new(100.0, 100.0),
+            Vec2::splat(10.0),
+        ));
+        overlapping.sort();
+        assert_eq!(overlapping, vec![a, b]);
+
+        assert_eq!(index.at_point(Vec2::new(75.0, 0.0)), vec![b, a]);
+        assert_eq!(index.at_point(Vec2::new(1000.0, 0.0)), vec![]);
+
+        assert_eq!(
+            index.nearest_top_card(Vec2::new(100.0, 0.0), 100.0, |_| true),
+            Some(b)
+        );
+        assert_eq!(
+            index.nearest_top_card(Vec2::new(100.0, 0.0), 100.0, |e| e != b),
+            None
+        );
+        assert_eq!(
+            index.nearest_top_card(Vec2::new(100.0, 0.0), 120.0, |e| e != b),
+            Some(a)
+        );
+
+        // Moving a stack far away.
+        index.insert(a, stack_at(Vec2::new(-2000.0, 0.0), size, 1.0));
+        assert_eq!(index.at_point(Vec2::new(75.0, 0.0)), vec![b]);
+        assert_eq!(index.at_point(Vec2::new(-2000.0, 0.0)), vec![a]);
+
+        index.remove(b);
+        assert_eq!(index.at_point(Vec2::new(75.0, 0.0)), vec![]);
+        assert_eq!(index.len(), 2);
+    }
+
+    #[test]
+    fn test_update_system() {
+        let mut world = World::default();
+        let mut update_stage =
+            SystemStage::parallel().with_system(stack_spatial_index_update_system);
+
+        let card_size = Vec2::new(100.0, 200.0);
+        world.insert_resource(CardVisualSize(card_size));
+        world.insert_resource(StackSpatialIndex::default());
+
+        let card = world.spawn().id();
+        let stack = world
+            .spawn()
+            .insert(GlobalTransform::default())
+            .insert(CardStack(vec![card]))
+            .id();
+
+        update_stage.run(&mut world);
+        assert_eq!(
+            world.resource::<StackSpatialIndex>().at_point(Vec2::ZERO),
+            vec![stack]
+        );
+
+        world
+            .get_mut::<GlobalTransform>(stack)
+            .unwrap()
+            .translation
+            .x = 1000.0;
+        update_stage.run(&mut world);
+        let index = world.resource::<StackSpatialIndex>();
+        assert!(index.at_point(Vec2::ZERO).is_empty());
+        assert_eq!(index.at_point(Vec2::new(1000.0, 0.0)), vec![stack]);
+
+        world.despawn(stack);
+        update_stage.run(&mut world);
+        assert!(world.resource::<StackSpatialIndex>().is_empty());
+    }
+
+    /// A board with a thousand stacks, where most stacks overlap with their neighbours.
+    fn crowded_world(stacks: usize) -> World {
+        let mut world = World::default();
+        let card_size = Vec2::new(200.0, 275.0);
+        world.insert_resource(CardVisualSize(card_size));
+        world.insert_resource(StackSpatialIndex::default());
+        world.insert_resource(FrameTime {
+            delta_seconds: 1.0 / 60.0,
+            fixed_timestep: None,
+        });
+
+        let columns = (stacks as f32).sqrt() as usize;
+        for i in 0..stacks {
+            let position = Vec2::new((i % columns) as f32, (i / columns) as f32) * card_size * 0.8;
+            let card = world.spawn().id();
+            world
+                .spawn()
+                .insert_bundle(TransformBundle::from_transform(
+                    Transform::from_translation(position.extend(0.0)),
+                ))
+                .insert(GlobalTransform::from_translation(position.extend(0.0)))
+                .insert(CardStack(vec![card]))
+                .insert(StackPhysics);
+        }
+        world
+    }
+
+    #[bench]
+    fn bench_nudging_1000_stacks(bencher: &mut Bencher) {
+        let mut world = crowded_world(1000);
+        let mut index_stage =
+            SystemStage::parallel().with_system(stack_spatial_index_update_system);
+        let mut nudging_stage = SystemStage::parallel().with_system(stack_overlap_nudging_system);
+        index_stage.run(&mut world);
+        assert_eq!(world.resource::<StackSpatialIndex>().len(), 1000);
+
+        bencher.iter(|| nudging_stage.run(&mut world));
+    }
+
+    #[bench]
+    fn bench_index_update_1000_moving_stacks(bencher: &mut Bencher) {
+        let mut world = crowded_world(1000);
+        let mut stage = SystemStage::parallel().with_system(stack_spatial_index_update_system);
+        stage.run(&mut world);
+
+        bencher.iter(|| {
+            for mut transform in world.query::<&mut GlobalTransform>().iter_mut(&mut world) {
+                transform.translation.x += 1.0;
+            }
+            stage.run(&mut world);
+        });
+    }
+}
@@ -5,7 +5,9 @@ use crate::stack::{
     Card, CardFonts, CardImages, CardStack, IsCardHoverOverlay, IsCardTitle,
     StackLookingForMovementTarget, StackPhysics, DELTA_Z,
 };
+use ab_glyph::{Font as _, FontArc, PxScale, PxScaleFont, ScaleFont};
 use bevy::prelude::*;
+use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::ops::Range;
@@ -21,7 +23,16 @@ pub const STACK_ROOT_Z_RANGE: Range<f32> = 1.0..100.0;
 const CARD_HOVER_OVERLAY_COLOR: Color = Color::rgba(1., 1., 1., 0.1);
 const CARD_BORDER_COLOR: Color = Color::BLACK;
 
-pub const CARD_VALUE_SPACING_FROM_CARD_EDGE: f32 = 10.0;
+const CARD_VALUE_SPACING_FROM_CARD_EDGE: f32 = 10.0;
+
+/// Space to keep free on the left and right of a card title.
+const CARD_TITLE_SPACING_FROM_CARD_EDGE: f32 = 10.0;
+/// Titles that do not fit are made smaller, down to this size, and wrapped over multiple lines.
+const MIN_CARD_TITLE_FONT_SIZE: f32 = 16.0;
+const CARD_TITLE_FONT_SIZE_STEP: f32 = 2.0;
+
+/// How far [find_free_space] looks, in steps of half the size of the stack it is looking for.
+const FREE_SPACE_MAX_SEARCH_RINGS: usize = 20;
 
 /// Prefix used in front of the card type id when requesting the localized card title.
 /// For example, a card with id `tree` will have it's localized title stored under `ct_tree`.
@@ -30,58 +41,114 @@ pub const CARD_TITLE_LOCALIZATION_PREFIX: &str = "ct_";
 /// For example, a card with id `tree` will have it's localized description stored under `cd_tree`.
 pub const CARD_DESCRIPTION_LOCALIZATION_PREFIX: &str = "cd_";
 
+/// Spawns a stack containing the given cards, in order. The first card is the bottom card.
+/// Returns the root of the new stack, and the cards in it.
+/// Returns `None` if there are no cards to spawn.
+///
+/// - `move_to_empty_space`: Whether this stack should try moving somewhere relatively empty nearby.
 pub fn spawn_stack(
     commands: &mut Commands,
     position: Vec2,
-    card_type: &CardType,
-    card_amount: usize,
+    card_types: &[&CardType],
+    move_to_empty_space: bool,
     card_images: &Res<CardImages>,
     card_fonts: &Res<CardFonts>,
-    title_transform: Transform,
-    card_value_transform: Transform,
-    foreground_image: Handle<Image>,
+    card_visual_size: Vec2,
     localizer: &Res<Localizer>,
-) {
-    if card_amount == 0 {
-        return;
+) -> Option<(Entity, Vec<Entity>)> {
+    if card_types.is_empty() {
+        return None;
     }
 
-    let entities: Vec<Entity> = (0..card_amount)
-        .map(|_| {
-            spawn_card(
+    let entities: Vec<Entity> = card_types
+        .iter()
+        .map(|&card_type| {
+            let entity = commands.spawn().id();
+            build_card(
                 commands,
+                entity,
                 card_type,
                 card_images,
                 card_fonts,
-                title_transform,
-                card_value_transform,
-                foreground_image.clone(),
+                card_visual_size,
                 localizer,
-            )
+            );
+            entity
+        })
+        .collect();
+
+    let root = spawn_stack_root(commands, position, &entities, move_to_empty_space);
+    set_stack_card_transforms(commands, &entities);
+
+    Some((root, entities))
+}
+
+/// Spawns a stack like [spawn_stack] does, but without any visuals.
+/// Used when the game runs without a window.
+pub fn spawn_bare_stack(
+    commands: &mut Commands,
+    position: Vec2,
+    card_types: &[&CardType],
+    move_to_empty_space: bool,
+) -> Option<(Entity, Vec<Entity>)> {
+    if card_types.is_empty() {
+        return None;
+    }
+
+    let entities: Vec<Entity> = card_types
+        .iter()
+        .map(|&card_type| {
+            let entity = commands
+                .spawn_bundle(TransformBundle::default())
+                .insert(card_type.get_card_component())
+                .id();
+            card_type.insert_components(commands, entity);
+            entity
         })
         .collect();
 
-    spawn_stack_root(commands, position, &entities, true);
+    let root = spawn_stack_root(commands, position, &entities, move_to_empty_space);
     set_stack_card_transforms(commands, &entities);
+
+    Some((root, entities))
+}
+
+/// Position of the title text, relative to the card.
+pub fn card_title_transform(card_visual_size: Vec2) -> Transform {
+    Transform::from_xyz(
+        0.,
+        0.5 * (card_visual_size.y - CARD_STACK_Y_SPACING),
+        DELTA_Z,
+    )
 }
 
-/// Spawns a loose card. The new card should be added to a stack straight away.
-fn spawn_card(
+/// Position of the coin value text, relative to the card.
+pub fn card_value_transform(card_visual_size: Vec2) -> Transform {
+    Transform::from_xyz(
+        -0.5 * card_visual_size.x + CARD_VALUE_SPACING_FROM_CARD_EDGE,
+        -0.5 * card_visual_size.y + CARD_VALUE_SPACING_FROM_CARD_EDGE,
+        DELTA_Z,
+    )
+}
+
+/// Turns an empty entity into a loose card of the given type.
+/// The card should be added to a stack straight away.
+pub fn build_card(
     commands: &mut Commands,
+    entity: Entity,
     card: &CardType,
     card_images: &Res<CardImages>,
     card_fonts: &Res<CardFonts>,
-    title_transform: Transform,
-    card_value_transform: Transform,
-    foreground_image: Handle<Image>,
+    card_visual_size: Vec2,
     localizer: &Res<Localizer>,
-) -> Entity {
+) {
     let foreground_color = card.category.text_color();
 
     let card_component = card.get_card_component();
 
-    let entity = commands
-        .spawn_bundle(SpriteBundle {
+    commands
+        .entity(entity)
+        .insert_bundle(SpriteBundle {
             texture: card_images.background.clone(),
             sprite: Sprite {
                 color: card.category.background_color(),
@@ -105,20 +172,22 @@ fn spawn_card(
             parent
                 .spawn_bundle(Text2dBundle {
                     text: card_title_text(&card_component, card_fonts, localizer),
-                    transform: title_transform,
+                    transform: card_title_transform(card_visual_size),
                     ..default()
                 })
                 .insert(IsCardTitle);
             // Foreground image
-            parent.spawn_bundle(SpriteBundle {
-                texture: foreground_image,
-                transform: Transform::from_xyz(0.0, 0.0, DELTA_Z),
-                sprite: Sprite {
-                    color: foreground_color,
+            if let Some(foreground_image) = &card.foreground_image {
+                parent.spawn_bundle(SpriteBundle {
+                    texture: foreground_image.clone(),
+                    transform: Transform::from_xyz(0.0, 0.0, DELTA_Z),
+                    sprite: Sprite {
+                        color: foreground_color,
+                        ..default()
+                    },
                     ..default()
-                },
-                ..default()
-            });
+                });
+            }
             // Hover overlay
             parent
                 .spawn_bundle(SpriteBundle {
@@ -148,19 +217,13 @@ fn spawn_card(
                             horizontal: HorizontalAlign::Left,
                         },
                     ),
-                    transform: card_value_transform,
+                    transform: card_value_transform(card_visual_size),
                     ..default()
                 });
             }
-        })
-        .id();
-
-    // Call the custom on_spawn function, if there is one.
-    if let Some(func) = card.on_spawn {
-        func(commands, entity)
-    }
+        });
 
-    entity
+    card.insert_components(commands, entity);
 }
 
 /// - `move_to_empty_space`: Whether this stack should try moving somewhere relatively empty nearby.
@@ -192,6 +255,7 @@ fn spawn_stack_root(
     root_id
 }
 
+/// The title is made to fit on the card by the `card_title_layout_system`.
 pub fn card_title_text(
     card: &Card,
     card_fonts: &Res<CardFonts>,
@@ -211,6 +275,90 @@ pub fn card_title_text(
     )
 }
 
+/// Space a card title can take up without going over the edge of the card, or the card on top
+/// of it in a stack.
+pub fn card_title_size(card_visual_size: Vec2) -> Vec2 {
+    Vec2::new(
+        card_visual_size.x - 2.0 * CARD_TITLE_SPACING_FROM_CARD_EDGE,
+        CARD_STACK_Y_SPACING,
+    )
+}
+
+/// Whether the font has all the letters of the text.
+pub fn font_has_letters(font: &FontArc, text: &str) -> bool {
+    text.chars()
+        .filter(|letter| !letter.is_whitespace())
+        .all(|letter| font.glyph_id(letter).0 != 0)
+}
+
+/// A localized card title, made to fit on the card.
+#[derive(Debug, PartialEq)]
+pub struct CardTitleLayout {
+    /// The lines of the title, separated by newlines.
+    pub text: String,
+    pub font_size: f32,
+}
+
+/// Finds the largest font size at which the title fits in `max_size`, wrapping it at spaces
+/// when it is too wide for a single line. Titles that do not fit at all get the minimum size.
+///
+/// Right to left languages are not supported. Bevy only lays text out left to right, and showing
+/// them properly takes bidi and shaping support, not just reversing the letters.
+pub fn layout_card_title(title: &str, font: &FontArc, max_size: Vec2) -> CardTitleLayout {
+    let mut font_size = CARD_STACK_Y_SPACING;
+    let lines = loop {
+        let scaled_font = font.as_scaled(PxScale::from(font_size));
+        let lines = wrap_text(title, &scaled_font, max_size.x);
+        // The first line may take up the whole font size, like the titles always did.
+        let height = font_size
+            + (lines.len().max(1) - 1) as f32 * (scaled_font.height() + scaled_font.line_gap());
+
+        let fits = height <= max_size.y
+            && lines
+                .iter()
+                .all(|line| text_width(line, &scaled_font) <= max_size.x);
+        if fits || font_size <= MIN_CARD_TITLE_FONT_SIZE {
+            break lines;
+        }
+        font_size = (font_size - CARD_TITLE_FONT_SIZE_STEP).max(MIN_CARD_TITLE_FONT_SIZE);
+    };
+
+    CardTitleLayout {
+        text: lines.join("\n"),
+        font_size,
+    }
+}
+
+/// Puts as many words on a line as fit in the width.
+/// A word that is wider than the width gets a line of its own.
+fn wrap_text(text: &str, font: &PxScaleFont<&FontArc>, width: f32) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if text_width(&format!("{} {}", line, word), font) <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+fn text_width(text: &str, font: &PxScaleFont<&FontArc>) -> f32 {
+    let mut width = 0.0;
+    let mut previous = None;
+    for letter in text.chars() {
+        let glyph = font.glyph_id(letter);
+        if let Some(previous) = previous {
+            width += font.kern(previous, glyph);
+        }
+        width += font.h_advance(glyph);
+        previous = Some(glyph);
+    }
+    width
+}
+
 /// Generates a semi-random z position for a stack, based on either the entity id of the stack
 /// itself, or if that is not available, any other entity id.
 /// Should have the effect of minimizing the clipping of card foreground sprites.
@@ -239,6 +387,167 @@ pub fn stack_visual_size(single_card_visual_size: Vec2, cards_in_stack: usize) -
     )
 }
 
+/// Returns the center of the space a stack takes up, when its root is at `root_position`.
+/// The cards of a stack hang down from the root.
+pub fn stack_center(root_position: Vec2, cards_in_stack: usize) -> Vec2 {
+    root_position
+        - Vec2::new(
+            0.0,
+            0.5 * cards_in_stack.saturating_sub(1) as f32 * CARD_STACK_Y_SPACING,
+        )
+}
+
+/// Whether two rectangles, given by their centers and sizes, overlap.
+/// Rectangles that only touch each other do not overlap.
+pub fn rectangles_overlap(pos1: Vec2, size1: Vec2, pos2: Vec2, size2: Vec2) -> bool {
+    let distance = (pos1 - pos2).abs();
+    let minimum_allowed_distance = (size1 + size2) / 2.0;
+    distance.x < minimum_allowed_distance.x && distance.y < minimum_allowed_distance.y
+}
+
+/// A rectangle that can be rotated. Used for hit testing and overlap, so that keeps working for
+/// cards that are scaled or tilted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrientedRect {
+    pub center: Vec2,
+    pub half_size: Vec2,
+    /// Direction of the local x axis of the rectangle. The local y axis is perpendicular to it.
+    pub x_axis: Vec2,
+}
+
+impl OrientedRect {
+    pub fn axis_aligned(center: Vec2, size: Vec2) -> Self {
+        OrientedRect {
+            center,
+            half_size: size / 2.0,
+            x_axis: Vec2::X,
+        }
+    }
+
+    /// A rectangle of `unscaled_size`, which is placed, rotated and scaled by the `transform`.
+    /// Only rotation around the z axis is taken into account.
+    pub fn from_transform(unscaled_size: Vec2, transform: &GlobalTransform) -> Self {
+        let x_axis = (transform.rotation * Vec3::X)
+            .truncate()
+            .normalize_or_zero();
+        OrientedRect {
+            center: transform.translation.truncate(),
+            half_size: unscaled_size * transform.scale.truncate().abs() / 2.0,
+            x_axis: if x_axis == Vec2::ZERO {
+                Vec2::X
+            } else {
+                x_axis
+            },
+        }
+    }
+
+    pub fn y_axis(&self) -> Vec2 {
+        self.x_axis.perp()
+    }
+
+    /// The same rectangle, but `extra_size` bigger, along its own axes.
+    pub fn grown(&self, extra_size: Vec2) -> Self {
+        OrientedRect {
+            half_size: self.half_size + extra_size / 2.0,
+            ..*self
+        }
+    }
+
+    /// Where the `point` is relative to the center, along the axes of the rectangle.
+    pub fn to_local(&self, point: Vec2) -> Vec2 {
+        let relative = point - self.center;
+        Vec2::new(relative.dot(self.x_axis), relative.dot(self.y_axis()))
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        let local = self.to_local(point).abs();
+        local.x <= self.half_size.x && local.y <= self.half_size.y
+    }
+
+    /// Half the length of the shadow the rectangle casts on the `axis`.
+    pub fn projected_radius(&self, axis: Vec2) -> f32 {
+        self.half_size.x * self.x_axis.dot(axis).abs()
+            + self.half_size.y * self.y_axis().dot(axis).abs()
+    }
+
+    /// Size of the smallest axis-aligned rectangle around this one.
+    pub fn bounding_size(&self) -> Vec2 {
+        2.0 * Vec2::new(
+            self.projected_radius(Vec2::X),
+            self.projected_radius(Vec2::Y),
+        )
+    }
+
+    /// Rectangles that only touch each other do not overlap.
+    pub fn overlaps(&self, other: &OrientedRect) -> bool {
+        let distance = other.center - self.center;
+        [self.x_axis, self.y_axis(), other.x_axis, other.y_axis()]
+            .iter()
+            .all(|&axis| {
+                distance.dot(axis).abs()
+                    < self.projected_radius(axis) + other.projected_radius(axis)
+            })
+    }
+}
+
+/// The space a stack takes up, placed, rotated and scaled like the stack root.
+pub fn stack_area(
+    root_transform: &GlobalTransform,
+    cards_in_stack: usize,
+    card_visual_size: Vec2,
+) -> OrientedRect {
+    let center = root_transform.mul_vec3(stack_center(Vec2::ZERO, cards_in_stack).extend(0.0));
+    OrientedRect::from_transform(
+        stack_visual_size(card_visual_size, cards_in_stack),
+        &GlobalTransform {
+            translation: center,
+            ..*root_transform
+        },
+    )
+}
+
+/// Size of the area around a center that [find_free_space] looks at, when looking for space
+/// for a rectangle of `size`.
+pub fn free_space_search_area(size: Vec2) -> Vec2 {
+    size * (FREE_SPACE_MAX_SEARCH_RINGS as f32 + 1.0)
+}
+
+/// Finds the center closest to `center` where a rectangle of `size` does not overlap any
+/// of the `occupied` rectangles, which are given as `(center, size)`.
+/// Searches outward in rings of half a rectangle in size.
+/// Returns `None` when there is no free space within [FREE_SPACE_MAX_SEARCH_RINGS] rings.
+pub fn find_free_space(center: Vec2, size: Vec2, occupied: &[(Vec2, Vec2)]) -> Option<Vec2> {
+    let step = size / 2.0;
+    let is_free = |candidate: Vec2| {
+        !occupied.iter().any(|&(other_center, other_size)| {
+            rectangles_overlap(candidate, size, other_center, other_size)
+        })
+    };
+
+    if is_free(center) {
+        return Some(center);
+    }
+
+    for ring in 1..=FREE_SPACE_MAX_SEARCH_RINGS as i32 {
+        let mut candidates: Vec<Vec2> = (-ring..=ring)
+            .flat_map(|x| (-ring..=ring).map(move |y| (x, y)))
+            .filter(|&(x, y)| x.abs() == ring || y.abs() == ring)
+            .map(|(x, y)| center + Vec2::new(x as f32, y as f32) * step)
+            .collect();
+        candidates.sort_by(|a, b| {
+            a.distance_squared(center)
+                .partial_cmp(&b.distance_squared(center))
+                .unwrap_or(Ordering::Equal)
+        });
+
+        if let Some(free) = candidates.into_iter().find(|&candidate| is_free(candidate)) {
+            return Some(free);
+        }
+    }
+
+    None
+}
+
 /// Removes a card from the world.
 /// It does not matter if this card is in the middle of a stack,
 /// or the only card in a stack. This function will handle it gracefully.
@@ -257,8 +566,8 @@ pub fn delete_cards(
     //      the whole stack's Vec every time a card is deleted. but this works for now.
     //      (don't do pre-mature optimizations and all that).
 
-    if stack[0] == cards_to_delete[0] && stack.len() == 1 && cards_to_delete.len() == 1 {
-        // Last card in the stack. Delete the stack as well.
+    if stack.iter().all(|e| cards_to_delete.contains(e)) {
+        // No cards left in the stack. Delete the stack as well.
         commands.entity(stack_root).despawn_recursive();
     } else {
         let new_stack = CardStack(
@@ -278,17 +587,16 @@ pub fn delete_cards(
 }
 
 /// Returns the global transform which indicates the center of the top card of a stack.
+/// It is rotated and scaled like the stack root.
 pub fn global_center_of_top_card(
     root_transform: &GlobalTransform,
     amount_of_cards: usize,
 ) -> GlobalTransform {
-    GlobalTransform::from_translation(
-        root_transform.translation
-            + root_transform.down()
-                * root_transform.scale
-                * CARD_STACK_Y_SPACING
-                * amount_of_cards as f32,
-    )
+    GlobalTransform {
+        translation: root_transform
+            .mul_vec3(Vec3::new(0.0, -CARD_STACK_Y_SPACING, 0.0) * amount_of_cards as f32),
+        ..*root_transform
+    }
 }
 
 /// Does not need to keep rotation or scaling in mind, because that is applied to the stack root.
@@ -306,7 +614,8 @@ pub fn relative_center_of_nth_card_in_stack(nth_card: usize) -> Vec3 {
 /// Adds the cards of the `source_stack` to the top of the `target_stack`.
 /// Assumes no duplicate cards.
 /// If stacks have ongoing recipes, it will prefer the target stack's recipe when deciding
-/// which one to keep.
+/// which one to keep, unless only the source stack's recipe is still running.
+/// That way a paused recipe can be resumed by dropping its cards back onto the stack.
 /// Recipes that are no longer valid after the merge will be handled by [recipe_check_system](crate::recipe::recipe_check_system).
 ///
 /// Effects are applied via `Commands`, which means it is visible next update.
@@ -340,8 +649,11 @@ pub fn merge_stacks(
         .insert(CardStack(combined_stack))
         .insert_children(0, source_stack);
 
-    // Keep recipes. Prefer target stack recipe.
-    let kept_recipe = target_stack_recipe.or(source_stack_recipe);
+    // Keep recipes. Prefer target stack recipe, unless it is paused.
+    let kept_recipe = match (target_stack_recipe, source_stack_recipe) {
+        (Some(target), Some(source)) if target.is_paused() && !source.is_paused() => Some(source),
+        _ => target_stack_recipe.or(source_stack_recipe),
+    };
     if let Some(recipe) = kept_recipe {
         commands.entity(target_root).insert(recipe.clone());
     }
@@ -349,7 +661,8 @@ pub fn merge_stacks(
 
 /// Splits a stack so that the `new_root` card is the root of a new stack.
 /// Effects are applied via `Commands`, which means it is visible next update.
-/// If a recipe is ongoing, the recipe will be kept on both child stacks.
+/// If a recipe is ongoing, the recipe will be kept on both child stacks, as copies with the same
+/// [RecipeInstanceId](crate::recipe::RecipeInstanceId).
 /// Recipes that are no longer valid after the split will be handled by [recipe_check_system](crate::recipe::recipe_check_system).
 ///
 /// Returns the Entity id of the newly created stack root, if the stack needed to be split.
@@ -401,9 +714,76 @@ pub fn split_stack(
 
 #[cfg(test)]
 mod tests {
-    use crate::stack::stack_utils::{stack_visual_size, CARD_STACK_Y_SPACING};
+    use crate::localization::pseudo_localize;
+    use crate::stack::stack_utils::{
+        card_title_size, find_free_space, font_has_letters, layout_card_title, rectangles_overlap,
+        stack_center, stack_visual_size, CardTitleLayout, CARD_STACK_Y_SPACING,
+        CARD_TITLE_LOCALIZATION_PREFIX, MIN_CARD_TITLE_FONT_SIZE,
+    };
+    use ab_glyph::FontArc;
     use bevy::prelude::Vec2;
 
+    /// Size of the card background image.
+    const CARD_SIZE: Vec2 = bevy::math::const_vec2!([200.0, 275.0]);
+
+    fn title_font() -> FontArc {
+        FontArc::try_from_slice(include_bytes!("../../assets/fonts/FallingSky-JKwK.otf")).unwrap()
+    }
+
+    #[test]
+    fn test_short_titles_keep_their_size() {
+        let layout = layout_card_title("Tree", &title_font(), card_title_size(CARD_SIZE));
+        assert_eq!(
+            layout,
+            CardTitleLayout {
+                text: "Tree".to_string(),
+                font_size: CARD_STACK_Y_SPACING,
+            }
+        );
+    }
+
+    #[test]
+    fn test_long_titles_are_shrunk_and_wrapped() {
+        let font = title_font();
+        let layout = layout_card_title(
+            "A very long title that does not fit",
+            &font,
+            card_title_size(CARD_SIZE),
+        );
+        assert!(layout.font_size < CARD_STACK_Y_SPACING);
+        assert!(layout.text.contains('\n'), "{:?}", layout);
+    }
+
+    #[test]
+    fn test_font_has_letters() {
+        let font = title_font();
+        assert!(font_has_letters(&font, "Ťřḗḗ tree"));
+        assert!(!font_has_letters(&font, "שלום"));
+    }
+
+    /// Every card title, made longer by pseudo localization, should fit on a card.
+    #[test]
+    fn test_pseudo_localized_titles_fit() {
+        let font = title_font();
+        let max_size = card_title_size(CARD_SIZE);
+        let titles = include_str!("../../assets/localization/en-US.ftl")
+            .lines()
+            .filter(|line| line.starts_with(CARD_TITLE_LOCALIZATION_PREFIX))
+            .filter_map(|line| line.split_once(" = "))
+            .map(|(_, title)| pseudo_localize(title));
+
+        for title in titles {
+            assert!(font_has_letters(&font, &title), "{}", title);
+            let layout = layout_card_title(&title, &font, max_size);
+            assert!(
+                layout.font_size > MIN_CARD_TITLE_FONT_SIZE,
+                "`{}` does not fit: {:?}",
+                title,
+                layout
+            );
+        }
+    }
+
     #[test]
     fn test_stack_visual_size() {
         let single_card_size = Vec2::new(100.0, 250.0);
@@ -419,4 +799,44 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_stack_center() {
+        assert_eq!(
+            stack_center(Vec2::new(10.0, 20.0), 1),
+            Vec2::new(10.0, 20.0)
+        );
+        assert_eq!(
+            stack_center(Vec2::ZERO, 3),
+            Vec2::new(0.0, -CARD_STACK_Y_SPACING)
+        );
+    }
+
+    #[test]
+    fn test_find_free_space() {
+        let size = Vec2::new(100.0, 200.0);
+
+        // Nothing in the way.
+        assert_eq!(find_free_space(Vec2::ZERO, size, &[]), Some(Vec2::ZERO));
+
+        // Right next to the one stack that is in the way.
+        let occupied = [(Vec2::ZERO, size)];
+        let free = find_free_space(Vec2::ZERO, size, &occupied).unwrap();
+        assert!(!rectangles_overlap(free, size, Vec2::ZERO, size));
+        assert_eq!(free.length(), size.x);
+
+        // Fully surrounded, so it has to look further out.
+        let occupied: Vec<(Vec2, Vec2)> = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |y| Vec2::new(x as f32, y as f32) * size))
+            .map(|center| (center, size))
+            .collect();
+        let free = find_free_space(Vec2::ZERO, size, &occupied).unwrap();
+        assert!(occupied
+            .iter()
+            .all(|&(center, other_size)| !rectangles_overlap(free, size, center, other_size)));
+
+        // Nowhere to go.
+        let everything = [(Vec2::ZERO, size * 1000.0)];
+        assert_eq!(find_free_space(Vec2::ZERO, size, &everything), None);
+    }
 }
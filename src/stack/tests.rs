@@ -1,9 +1,17 @@
 #![cfg(test)]
 
-use crate::card_types::CardCategory;
+use crate::card_types::{CardCategory, CardTypeId};
+use crate::recipe::{Recipes, RecipesBuilder};
+use crate::stack::spatial_index::{
+    stack_spatial_index_update_system, IndexedStack, StackSpatialIndex,
+};
+use crate::stack::stack_utils::{rectangles_overlap, stack_center, stack_visual_size};
 use crate::stack::{
-    card_hover_system, Card, CardVisualSize, HoveredCard, IsCardHoverOverlay, MouseWorldPos,
+    card_hover_system, find_stack_movement_target_system, Card, CardStack, CardVisualSize,
+    FeedsWaitingStack, HoveredCard, IsCardHoverOverlay, MouseWorldPos, MovingStackTarget,
+    StackLookingForMovementTarget, StackPhysics,
 };
+use crate::ui::UiClaimsMouse;
 use bevy::prelude::*;
 
 #[test]
@@ -15,6 +23,8 @@ fn test_hover_system() {
     let card_size = Vec2::new(100.0, 200.0);
     world.insert_resource(MouseWorldPos(None));
     world.insert_resource(CardVisualSize(card_size));
+    world.insert_resource(UiClaimsMouse(false));
+    world.insert_resource(StackSpatialIndex::default());
 
     // Running it without any cards shouldn't fail.
     update_stage.run(&mut world);
@@ -29,12 +39,19 @@ fn test_hover_system() {
         .spawn()
         .insert_bundle(TransformBundle::default())
         .insert(Card {
-            type_id: "Test card",
+            type_id: CardTypeId("Test card"),
             category: CardCategory::Resource,
             value: None,
+            buys: None,
         })
         .push_children(&[hover_overlay])
         .id();
+    // Only cards of stacks that the index knows about can be hovered.
+    let stack = world.spawn().insert(CardStack(vec![card])).id();
+    world.resource_mut::<StackSpatialIndex>().insert(
+        stack,
+        IndexedStack::new(&GlobalTransform::default(), 1, card_size),
+    );
 
     // Put the mouse right at the middle of the card.
     let mouse_pos = Vec2::ZERO;
@@ -82,3 +99,146 @@ fn test_hover_system() {
     let visible = world.get::<Visibility>(hover_overlay).unwrap().is_visible;
     assert!(!visible);
 }
+
+/// Spawns a stack at the given position, without any visuals.
+fn spawn_test_stack(
+    world: &mut World,
+    position: Vec2,
+    card_type: &'static str,
+    amount: usize,
+) -> Entity {
+    let cards: Vec<Entity> = (0..amount)
+        .map(|_| {
+            world
+                .spawn()
+                .insert_bundle(TransformBundle::default())
+                .insert(Card {
+                    type_id: CardTypeId(card_type),
+                    category: CardCategory::Resource,
+                    value: None,
+                    buys: None,
+                })
+                .id()
+        })
+        .collect();
+    world
+        .spawn()
+        .insert_bundle(TransformBundle::from_transform(
+            Transform::from_translation(position.extend(0.0)),
+        ))
+        .insert(GlobalTransform::from_translation(position.extend(0.0)))
+        .insert(CardStack(cards))
+        .id()
+}
+
+#[test]
+fn test_new_stack_moves_to_free_space() {
+    let mut world = World::default();
+    let mut index_stage = SystemStage::parallel().with_system(stack_spatial_index_update_system);
+    let mut update_stage = SystemStage::parallel().with_system(find_stack_movement_target_system);
+
+    let card_size = Vec2::new(100.0, 200.0);
+    world.insert_resource(CardVisualSize(card_size));
+    world.insert_resource(StackSpatialIndex::default());
+    world.insert_resource(Recipes::default());
+
+    // A stack that made something, and the new stack it made, which starts on top of it.
+    let producer = spawn_test_stack(&mut world, Vec2::ZERO, "villager", 1);
+    world.entity_mut(producer).insert(StackPhysics);
+    let output = spawn_test_stack(&mut world, Vec2::ZERO, "tree", 2);
+    world
+        .entity_mut(output)
+        .insert(StackLookingForMovementTarget);
+
+    index_stage.run(&mut world);
+    update_stage.run(&mut world);
+
+    assert!(world.get::<StackLookingForMovementTarget>(output).is_none());
+    let position = match *world.get::<MovingStackTarget>(output).unwrap() {
+        MovingStackTarget::Position(position) => position,
+        MovingStackTarget::Stack(_) => panic!("There is no stack to merge with"),
+    };
+    assert!(!rectangles_overlap(
+        stack_center(position, 2),
+        stack_visual_size(card_size, 2),
+        Vec2::ZERO,
+        card_size
+    ));
+    assert!(world.get::<MovingStackTarget>(producer).is_none());
+}
+
+#[test]
+fn test_new_stack_moves_to_closest_matching_stack() {
+    let mut world = World::default();
+    let mut index_stage = SystemStage::parallel().with_system(stack_spatial_index_update_system);
+    let mut update_stage = SystemStage::parallel().with_system(find_stack_movement_target_system);
+
+    let card_size = Vec2::new(100.0, 200.0);
+    world.insert_resource(CardVisualSize(card_size));
+    world.insert_resource(StackSpatialIndex::default());
+    world.insert_resource(Recipes::default());
+
+    let far_tree = spawn_test_stack(&mut world, Vec2::new(-200.0, 0.0), "tree", 1);
+    let close_tree = spawn_test_stack(&mut world, Vec2::new(150.0, 0.0), "tree", 1);
+    let closer_villager = spawn_test_stack(&mut world, Vec2::new(100.0, 0.0), "villager", 1);
+    let far_away_tree = spawn_test_stack(&mut world, Vec2::new(5000.0, 0.0), "tree", 1);
+    for stack in [far_tree, close_tree, closer_villager, far_away_tree] {
+        world.entity_mut(stack).insert(StackPhysics);
+    }
+    let new_tree = spawn_test_stack(&mut world, Vec2::ZERO, "tree", 1);
+    world
+        .entity_mut(new_tree)
+        .insert(StackLookingForMovementTarget);
+
+    index_stage.run(&mut world);
+    update_stage.run(&mut world);
+
+    assert_eq!(
+        world.get::<MovingStackTarget>(new_tree),
+        Some(&MovingStackTarget::Stack(close_tree))
+    );
+}
+
+#[test]
+fn test_new_stack_feeds_waiting_stack() {
+    let mut world = World::default();
+    let mut index_stage = SystemStage::parallel().with_system(stack_spatial_index_update_system);
+    let mut update_stage = SystemStage::parallel().with_system(find_stack_movement_target_system);
+
+    let card_size = Vec2::new(100.0, 200.0);
+    world.insert_resource(CardVisualSize(card_size));
+    world.insert_resource(StackSpatialIndex::default());
+    let mut builder = RecipesBuilder::new(&mut world);
+    builder.add_recipe(
+        "cut_tree",
+        10.0,
+        |stack| {
+            let types: Vec<&str> = stack.iter().map(|card| card.type_id.0).collect();
+            types == ["villager", "tree"]
+        },
+        || {},
+    );
+    let recipes = builder.build();
+    world.insert_resource(recipes);
+
+    let close_tree = spawn_test_stack(&mut world, Vec2::new(100.0, 0.0), "tree", 1);
+    let waiting_villager = spawn_test_stack(&mut world, Vec2::new(150.0, 0.0), "villager", 1);
+    for stack in [close_tree, waiting_villager] {
+        world.entity_mut(stack).insert(StackPhysics);
+    }
+    let new_tree = spawn_test_stack(&mut world, Vec2::ZERO, "tree", 1);
+    world
+        .entity_mut(new_tree)
+        .insert(StackLookingForMovementTarget)
+        .insert(FeedsWaitingStack);
+
+    index_stage.run(&mut world);
+    update_stage.run(&mut world);
+
+    // The villager is further away than the other tree, but can use the tree for a recipe.
+    assert_eq!(
+        world.get::<MovingStackTarget>(new_tree),
+        Some(&MovingStackTarget::Stack(waiting_villager))
+    );
+    assert!(world.get::<FeedsWaitingStack>(new_tree).is_none());
+}
@@ -1,5 +1,13 @@
-use crate::localization::Localizer;
-use crate::recipe::{OngoingRecipe, RECIPE_TITLE_LOCALIZATION_PREFIX};
+use crate::camera::{MAX_ZOOMED_IN_SCALE, MAX_ZOOMED_OUT_SCALE};
+use crate::card_types::CardCategory;
+use crate::day_end::DayEndSummary;
+use crate::history::{HistoryRecorder, Operation};
+use crate::key_bindings::{Action, ActionInput, KeyBindings, RebindingAction};
+use crate::localization::{decimal_number, LocalizationCache, Localizer};
+use crate::procedural::{random_seed, seed_from_text, SeededHasherResource};
+use crate::recipe::{cancel_recipe, OngoingRecipe, RECIPE_TITLE_LOCALIZATION_PREFIX};
+use crate::save_game::{LoadGameEvent, SaveGameEvent};
+use crate::settings::{Settings, LENGTH_OF_DAY_RANGE, MIN_WINDOW_SIZE};
 use crate::stack::{Card, CardStack, HoveredCard};
 use crate::{GameState, LengthOfDay, Speed, TimeOfDay, TimeSpeed};
 use bevy::prelude::*;
@@ -17,7 +25,7 @@ const OFFSETS: egui::Vec2 = egui::vec2(10.0, 10.0);
 
 const CARD_INFO_SIZE: egui::Vec2 = egui::vec2(200.0, 100.0);
 const CARD_INFO_WINDOW_OFFSET: egui::Vec2 = egui::vec2(OFFSETS.x, -OFFSETS.y);
-const RECIPE_INFO_SIZE: egui::Vec2 = egui::vec2(CARD_INFO_SIZE.x, 20.0);
+const RECIPE_INFO_SIZE: egui::Vec2 = egui::vec2(CARD_INFO_SIZE.x, 50.0);
 const RECIPE_INFO_WINDOW_OFFSET: egui::Vec2 = egui::vec2(
     CARD_INFO_WINDOW_OFFSET.x,
     -(CARD_INFO_SIZE.y + TITLE_HEIGHT) + CARD_INFO_WINDOW_OFFSET.y,
@@ -33,43 +41,100 @@ impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(EguiPlugin)
             .insert_resource(UiClaimsMouse(false))
+            .insert_resource(UiClaimsKeyboard(false))
+            // Runs in every state, because the keyboard is used in menus as well.
+            .add_system(ui_keyboard_claim_system)
             .add_system_set(
                 SystemSet::on_update(GameState::Run)
                     .with_system(ui_mouse_claim_system)
                     .with_system(card_info_ui)
                     .with_system(card_crafting_info_ui)
                     .with_system(game_speed_ui)
-                    .with_system(open_pause_menu_ui),
+                    .with_system(open_pause_menu_ui)
+                    .with_system(open_pause_menu_keyboard_system)
+                    .with_system(day_end_summary_ui),
             )
-            .add_system_set(SystemSet::on_update(GameState::PauseMenu).with_system(pause_menu_ui));
+            .add_system_set(SystemSet::on_update(GameState::MainMenu).with_system(main_menu_ui))
+            .add_system_set(
+                SystemSet::on_update(GameState::PauseMenu)
+                    .with_system(pause_menu_ui)
+                    .with_system(close_pause_menu_keyboard_system),
+            );
     }
 }
 
 pub struct UiClaimsMouse(pub bool);
 
+/// Whether the ui is using the keyboard, for example because a text field has focus.
+/// If not, the game can react to keys.
+pub struct UiClaimsKeyboard(pub bool);
+
+/// Resource with the seed the player typed on the main menu.
+pub struct NewGameSeed(pub String);
+
 /// Keeps track of whether the ui is currently claiming the mouse or not.
 /// If the ui is not claiming the mouse, the game world can use it.
-/// TODO (Wybe 2022-07-18): Add a similar system for the keyboard input.
-fn ui_mouse_claim_system(mut context: ResMut<EguiContext>, mut claims_mouse: ResMut<UiClaimsMouse>) {
+fn ui_mouse_claim_system(
+    mut context: ResMut<EguiContext>,
+    mut claims_mouse: ResMut<UiClaimsMouse>,
+) {
     claims_mouse.0 = context.ctx_mut().wants_pointer_input();
 }
 
+/// Keeps track of whether the ui is currently claiming the keyboard or not.
+/// Keys bound to an [Action] are ignored while it does, see [ActionInput].
+fn ui_keyboard_claim_system(
+    mut context: ResMut<EguiContext>,
+    mut claims_keyboard: ResMut<UiClaimsKeyboard>,
+) {
+    claims_keyboard.0 = context.ctx_mut().wants_keyboard_input();
+}
+
 fn card_info_ui(
     mut context: ResMut<EguiContext>,
-    hovered_card_query: Query<&Card, With<HoveredCard>>,
+    hovered_card_query: Query<(&Card, &Parent), With<HoveredCard>>,
+    stack_query: Query<&Children, With<CardStack>>,
     localizer: Res<Localizer>,
+    mut cache: ResMut<LocalizationCache>,
 ) {
-    if let Some(hovered_card) = hovered_card_query.iter().next() {
-        egui::Window::new(hovered_card.localize_title(&localizer))
+    if let Some((hovered_card, stack)) = hovered_card_query.iter().next() {
+        let cards_in_stack = stack_query.get(stack.0).map_or(1, |cards| cards.len());
+
+        egui::Window::new(cache.localize(&localizer, &hovered_card.title_localization_id()))
             .id(egui::Id::new("Card info window"))
             .fixed_size(CARD_INFO_SIZE)
             .anchor(egui::Align2::LEFT_BOTTOM, CARD_INFO_WINDOW_OFFSET)
             .collapsible(false)
             .show(context.ctx_mut(), |ui| {
-                ui.label(hovered_card.localize_description(&localizer));
+                ui.label(cache.localize(&localizer, &hovered_card.description_localization_id()));
+
+                match (hovered_card.value, hovered_card.category) {
+                    (None, _) => {
+                        ui.label(cache.localize(&localizer, "ui_cannot_be_sold"));
+                    }
+                    // Value on a system card is the cost to buy something.
+                    (Some(coins), CardCategory::SystemCard) => {
+                        ui.label(cache.localize_with_args(
+                            &localizer,
+                            "ui_card_cost",
+                            &[("coins", coins.into())],
+                        ));
+                    }
+                    (Some(coins), _) => {
+                        ui.label(cache.localize_with_args(
+                            &localizer,
+                            "ui_card_value",
+                            &[("coins", coins.into())],
+                        ));
+                    }
+                }
 
-                if hovered_card.value.is_none() {
-                    ui.label(localizer.localize("ui_cannot_be_sold"));
+                if cards_in_stack > 1 {
+                    ui.label(cache.localize_with_args(
+                        &localizer,
+                        "ui_cards_in_stack",
+                        &[("count", cards_in_stack.into())],
+                    ));
                 }
 
                 ui.allocate_space(ui.available_size())
@@ -77,22 +142,32 @@ fn card_info_ui(
     }
 }
 
+/// Shows the recipe of the stack that was last hovered.
+/// Stays open while the mouse moves off the stack, so the recipe can be cancelled.
 fn card_crafting_info_ui(
+    mut commands: Commands,
     mut context: ResMut<EguiContext>,
+    mut shown_stack: Local<Option<Entity>>,
     hovered_card_query: Query<&Parent, With<HoveredCard>>,
     stack_recipe_query: Query<&OngoingRecipe, With<CardStack>>,
     localizer: Res<Localizer>,
+    mut cache: ResMut<LocalizationCache>,
+    mut history: HistoryRecorder,
 ) {
     if let Some(hovered_card) = hovered_card_query.iter().next() {
-        if let Ok(recipe) = stack_recipe_query.get(hovered_card.0) {
+        *shown_stack = Some(hovered_card.0);
+    }
+
+    if let Some(root) = *shown_stack {
+        if let Ok(recipe) = stack_recipe_query.get(root) {
             let title_localization_id = RECIPE_TITLE_LOCALIZATION_PREFIX.to_string() + recipe.id.0;
-            // TODO (Wybe 2022-06-19): Cache this string?
-            let title = localizer.localize(&title_localization_id);
+            let title = cache.localize(&localizer, &title_localization_id);
 
             let seconds_left = (recipe.timer.duration() - recipe.timer.elapsed()).as_secs_f32();
-            let seconds_left_string = localizer.localize_with_args(
+            let seconds_left_string = cache.localize_with_args(
+                &localizer,
                 "ui_seconds_left_in_recipe",
-                &[("seconds", &format!("{:.1}", seconds_left))],
+                &[("seconds", decimal_number(seconds_left, 1))],
             );
 
             egui::Window::new(title)
@@ -101,10 +176,25 @@ fn card_crafting_info_ui(
                 .anchor(egui::Align2::LEFT_BOTTOM, RECIPE_INFO_WINDOW_OFFSET)
                 .collapsible(false)
                 .show(context.ctx_mut(), |ui| {
-                    ui.label(seconds_left_string);
+                    if recipe.is_paused() {
+                        ui.label(cache.localize(&localizer, "ui_recipe_paused"));
+                    } else {
+                        ui.label(seconds_left_string);
+                    }
+
+                    if ui
+                        .button(cache.localize(&localizer, "ui_cancel_recipe"))
+                        .clicked()
+                    {
+                        history.record(Operation::CancelRecipe(recipe.id));
+                        cancel_recipe(&mut commands, root, recipe);
+                    }
 
                     ui.allocate_space(ui.available_size())
                 });
+        } else {
+            // Recipe is done, or the stack is gone.
+            *shown_stack = None;
         }
     }
 }
@@ -115,6 +205,9 @@ fn game_speed_ui(
     time_of_day: Res<TimeOfDay>,
     length_of_day: Res<LengthOfDay>,
     localizer: Res<Localizer>,
+    mut cache: ResMut<LocalizationCache>,
+    day_end_summary: Option<Res<DayEndSummary>>,
+    key_bindings: Res<KeyBindings>,
 ) {
     egui::Window::new("speed_window")
         .title_bar(false)
@@ -122,24 +215,31 @@ fn game_speed_ui(
         .anchor(egui::Align2::RIGHT_TOP, GAME_SPEED_WINDOW_OFFSET)
         .show(context.ctx_mut(), |ui| {
             ui.horizontal(|ui| {
+                // Time stays paused until the player has seen how the day ended.
+                ui.set_enabled(day_end_summary.is_none());
+
                 let mut paused = !speed.running;
-                ui.toggle_value(&mut paused, "||").on_hover_text("[space]");
+                ui.toggle_value(&mut paused, "||")
+                    .on_hover_text(key_bindings.hint(Action::TogglePause));
                 speed.running = !paused;
 
-                // TODO (Wybe 2022-05-28): Use a key mapping plugin, instead of hardcoding.
                 ui.selectable_value(&mut speed.speed, Speed::NORMAL, ">")
-                    .on_hover_text("[1]");
+                    .on_hover_text(key_bindings.hint(Action::SpeedNormal));
                 ui.selectable_value(&mut speed.speed, Speed::DOUBLE, ">>")
-                    .on_hover_text("[2]");
+                    .on_hover_text(key_bindings.hint(Action::SpeedDouble));
                 ui.selectable_value(&mut speed.speed, Speed::TRIPLE, ">>>")
-                    .on_hover_text("[3]");
+                    .on_hover_text(key_bindings.hint(Action::SpeedTriple));
 
                 let seconds_left_in_day = (1.0 - time_of_day.time_of_day) * length_of_day.0;
-                let day_string = localizer
-                    .localize_with_args("ui_current_day", &[("day", &time_of_day.day.to_string())]);
-                let seconds_left_in_day_string = localizer.localize_with_args(
+                let day_string = cache.localize_with_args(
+                    &localizer,
+                    "ui_current_day",
+                    &[("day", time_of_day.day.into())],
+                );
+                let seconds_left_in_day_string = cache.localize_with_args(
+                    &localizer,
                     "ui_seconds_left_in_day",
-                    &[("seconds", &format!("{:.0}", seconds_left_in_day))],
+                    &[("seconds", decimal_number(seconds_left_in_day, 0))],
                 );
 
                 let day_progress = ProgressBar::new(time_of_day.time_of_day)
@@ -151,24 +251,151 @@ fn game_speed_ui(
         });
 }
 
-fn open_pause_menu_ui(mut context: ResMut<EguiContext>, mut app_state: ResMut<State<GameState>>) {
+fn day_end_summary_ui(
+    mut commands: Commands,
+    mut context: ResMut<EguiContext>,
+    mut speed: ResMut<TimeSpeed>,
+    maybe_summary: Option<Res<DayEndSummary>>,
+    localizer: Res<Localizer>,
+    mut cache: ResMut<LocalizationCache>,
+) {
+    let summary = match maybe_summary {
+        Some(summary) => summary,
+        None => return,
+    };
+
+    let title = cache.localize_with_args(
+        &localizer,
+        "ui_day_end_title",
+        &[("day", summary.day.into())],
+    );
+    egui::Window::new(title)
+        .id(egui::Id::new("day_end_summary"))
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .resizable(false)
+        .collapsible(false)
+        .show(context.ctx_mut(), |ui| {
+            ui.vertical_centered_justified(|ui| {
+                for (id, amount) in [
+                    ("ui_day_end_fed_workers", summary.fed_workers),
+                    ("ui_day_end_starved_workers", summary.starved_workers),
+                    ("ui_day_end_food_eaten", summary.food_eaten),
+                    ("ui_day_end_food_left", summary.food_left),
+                ] {
+                    ui.label(cache.localize_with_args(
+                        &localizer,
+                        id,
+                        &[("amount", amount.into())],
+                    ));
+                }
+
+                if ui
+                    .button(cache.localize(&localizer, "ui_day_end_continue"))
+                    .clicked()
+                {
+                    commands.remove_resource::<DayEndSummary>();
+                    speed.running = true;
+                }
+            });
+        });
+}
+
+fn open_pause_menu_ui(
+    mut context: ResMut<EguiContext>,
+    mut app_state: ResMut<State<GameState>>,
+    key_bindings: Res<KeyBindings>,
+) {
     egui::Window::new("open_menu")
         .title_bar(false)
         .resizable(false)
         .anchor(egui::Align2::LEFT_TOP, OPEN_MENU_WINDOW_OFFSET)
         .show(context.ctx_mut(), |ui| {
-            if ui.button("☰").clicked() {
+            if ui
+                .button("☰")
+                .on_hover_text(key_bindings.hint(Action::OpenMenu))
+                .clicked()
+            {
                 app_state.push(GameState::PauseMenu);
             }
         });
 }
 
+fn open_pause_menu_keyboard_system(
+    mut input: ActionInput,
+    mut app_state: ResMut<State<GameState>>,
+) {
+    // Taken, so the pause menu does not close again right away.
+    if input.take_just_pressed(Action::OpenMenu) {
+        // Can only fail when already changing state, in which case the menu is not needed.
+        let _ = app_state.push(GameState::PauseMenu);
+    }
+}
+
+fn close_pause_menu_keyboard_system(
+    mut input: ActionInput,
+    mut app_state: ResMut<State<GameState>>,
+    rebinding: Res<RebindingAction>,
+) {
+    if rebinding.0.is_none() && input.take_just_pressed(Action::OpenMenu) {
+        let _ = app_state.pop();
+    }
+}
+
+fn main_menu_ui(
+    mut commands: Commands,
+    mut context: ResMut<EguiContext>,
+    mut app_state: ResMut<State<GameState>>,
+    mut seed: ResMut<NewGameSeed>,
+    localizer: Res<Localizer>,
+    mut cache: ResMut<LocalizationCache>,
+) {
+    egui::Window::new(cache.localize(&localizer, "ui_main_menu_title"))
+        .id(egui::Id::new("main_menu"))
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .resizable(false)
+        .collapsible(false)
+        .show(context.ctx_mut(), |ui| {
+            ui.vertical_centered_justified(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label(cache.localize(&localizer, "ui_main_menu_seed_label"));
+                    ui.text_edit_singleline(&mut seed.0);
+                    if ui
+                        .button("🎲")
+                        .on_hover_text(cache.localize(&localizer, "ui_main_menu_random_seed"))
+                        .clicked()
+                    {
+                        seed.0 = random_seed().to_string();
+                    }
+                });
+
+                if ui
+                    .button(cache.localize(&localizer, "ui_main_menu_start"))
+                    .clicked()
+                {
+                    commands.insert_resource(SeededHasherResource::new(seed_from_text(&seed.0)));
+                    // Can only fail when already changing state, in which case the game starts anyway.
+                    let _ = app_state.set(GameState::Run);
+                }
+            });
+        });
+}
+
 fn pause_menu_ui(
     mut context: ResMut<EguiContext>,
     mut app_state: ResMut<State<GameState>>,
     mut localizer: ResMut<Localizer>,
+    mut cache: ResMut<LocalizationCache>,
+    mut save_events: EventWriter<SaveGameEvent>,
+    mut load_events: EventWriter<LoadGameEvent>,
+    seeded_hasher: Res<SeededHasherResource>,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut rebinding: ResMut<RebindingAction>,
+    mut settings: ResMut<Settings>,
 ) {
-    egui::Window::new(localizer.localize("ui_pause_menu_title"))
+    // Edits a copy, so the settings are only marked as changed when something really changed.
+    let mut edited_settings = settings.clone();
+
+    egui::Window::new(cache.localize(&localizer, "ui_pause_menu_title"))
         .id(egui::Id::new("pause_menu"))
         .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
         .title_bar(true)
@@ -183,29 +410,178 @@ fn pause_menu_ui(
                     language_options.keys().collect();
                 sorted_language_identifiers.sort();
 
-                egui::ComboBox::from_label(localizer.localize("ui_pause_menu_language_label"))
-                    .selected_text(language_options[selected])
-                    .show_ui(ui, |ui| {
-                        for identifier in sorted_language_identifiers {
-                            ui.selectable_value(
-                                &mut selected,
-                                identifier,
-                                language_options[identifier],
-                            );
-                        }
-                    });
+                egui::ComboBox::from_label(
+                    cache.localize(&localizer, "ui_pause_menu_language_label"),
+                )
+                .selected_text(language_options[selected].as_str())
+                .show_ui(ui, |ui| {
+                    for identifier in sorted_language_identifiers {
+                        ui.selectable_value(
+                            &mut selected,
+                            identifier,
+                            language_options[identifier].as_str(),
+                        );
+                    }
+                });
 
                 if selected != &localizer.current_language() {
-                    // TODO (Wybe 2022-06-07): Update the titles of cards.
+                    edited_settings.language = Some(selected.to_string());
                     localizer.select_language(selected.clone());
                 }
 
+                let seed = seeded_hasher.seed().to_string();
+                ui.horizontal(|ui| {
+                    ui.label(
+                        // As text, so it is shown exactly as it should be typed in.
+                        cache.localize_with_args(
+                            &localizer,
+                            "ui_pause_menu_seed",
+                            &[("seed", seed.as_str().into())],
+                        ),
+                    );
+                    if ui
+                        .button(cache.localize(&localizer, "ui_pause_menu_copy_seed"))
+                        .clicked()
+                    {
+                        ui.output().copied_text = seed.clone();
+                    }
+                });
+
+                ui.collapsing(cache.localize(&localizer, "ui_pause_menu_settings"), |ui| {
+                    settings_ui(ui, &mut edited_settings, &localizer, &mut cache);
+                });
+                ui.collapsing(
+                    cache.localize(&localizer, "ui_pause_menu_key_bindings"),
+                    |ui| {
+                        key_bindings_ui(
+                            ui,
+                            &mut key_bindings,
+                            &mut rebinding,
+                            &localizer,
+                            &mut cache,
+                        );
+                    },
+                );
+
                 if ui
-                    .button(localizer.localize("ui_pause_menu_resume"))
+                    .button(cache.localize(&localizer, "ui_pause_menu_save"))
                     .clicked()
                 {
+                    save_events.send(SaveGameEvent);
+                }
+                if ui
+                    .button(cache.localize(&localizer, "ui_pause_menu_load"))
+                    .clicked()
+                {
+                    load_events.send(LoadGameEvent);
+                }
+
+                if ui
+                    .button(cache.localize(&localizer, "ui_pause_menu_resume"))
+                    .clicked()
+                {
+                    rebinding.0 = None;
                     app_state.pop();
                 }
             });
         });
+
+    if edited_settings != *settings {
+        *settings = edited_settings;
+    }
+}
+
+/// The settings that are not changed elsewhere in the pause menu.
+fn settings_ui(
+    ui: &mut egui::Ui,
+    settings: &mut Settings,
+    localizer: &Localizer,
+    cache: &mut LocalizationCache,
+) {
+    egui::Grid::new("settings").show(ui, |ui| {
+        ui.label(cache.localize(localizer, "ui_settings_start_zoom"));
+        ui.add(egui::Slider::new(
+            &mut settings.start_zoom,
+            MAX_ZOOMED_IN_SCALE..=MAX_ZOOMED_OUT_SCALE,
+        ));
+        ui.end_row();
+
+        ui.label(cache.localize(localizer, "ui_settings_length_of_day"));
+        ui.add(egui::Slider::new(&mut settings.length_of_day, LENGTH_OF_DAY_RANGE).step_by(10.0));
+        ui.end_row();
+
+        ui.label(cache.localize(localizer, "ui_settings_fullscreen"));
+        ui.checkbox(&mut settings.window.fullscreen, "");
+        ui.end_row();
+
+        ui.label(cache.localize(localizer, "ui_settings_window_size"));
+        ui.add_enabled_ui(!settings.window.fullscreen, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut settings.window.width)
+                        .clamp_range(MIN_WINDOW_SIZE..=f32::MAX),
+                );
+                ui.label("×");
+                ui.add(
+                    egui::DragValue::new(&mut settings.window.height)
+                        .clamp_range(MIN_WINDOW_SIZE..=f32::MAX),
+                );
+            });
+        });
+        ui.end_row();
+
+        for (id, volume) in [
+            (
+                "ui_settings_master_volume",
+                &mut settings.audio.master_volume,
+            ),
+            ("ui_settings_music_volume", &mut settings.audio.music_volume),
+            (
+                "ui_settings_effects_volume",
+                &mut settings.audio.effects_volume,
+            ),
+        ] {
+            ui.label(cache.localize(localizer, id));
+            ui.add(egui::Slider::new(volume, 0.0..=1.0));
+            ui.end_row();
+        }
+    });
+}
+
+/// Lists the bindings of every action. Clicking a binding waits for the player to press the
+/// new key or mouse button, which is picked up by the
+/// [KeyBindingsPlugin](crate::key_bindings::KeyBindingsPlugin).
+fn key_bindings_ui(
+    ui: &mut egui::Ui,
+    key_bindings: &mut KeyBindings,
+    rebinding: &mut RebindingAction,
+    localizer: &Localizer,
+    cache: &mut LocalizationCache,
+) {
+    egui::Grid::new("key_bindings").show(ui, |ui| {
+        for action in Action::ALL {
+            ui.label(cache.localize(localizer, &action.localization_id()));
+
+            let waiting = rebinding.0 == Some(action);
+            let text = if waiting {
+                cache.localize(localizer, "ui_key_bindings_waiting")
+            } else {
+                key_bindings.hint(action)
+            };
+            if ui.selectable_label(waiting, text).clicked() {
+                // Clicking again stops waiting.
+                rebinding.0 = if waiting { None } else { Some(action) };
+            }
+            ui.end_row();
+        }
+    });
+
+    if ui
+        .button(cache.localize(localizer, "ui_key_bindings_reset"))
+        .clicked()
+    {
+        *key_bindings = KeyBindings::default();
+        key_bindings.save();
+        rebinding.0 = None;
+    }
 }